@@ -0,0 +1,271 @@
+use std::collections::VecDeque;
+
+use alga::general::Real;
+use na::{DMatrix, DVector, convert};
+
+use kf::{KalmanFilter, BorrowedSystemState, MeasurementUpdate, GatingDecision};
+use nt::{SystemNoiseVarianceMatrix, CovarianceMatrix, InputVector, MeasurementVector,
+         MeasurementMatrix, MeasurementNoiseCovarianceMatrix};
+
+
+/// Adaptive Kalman filter
+///
+/// Wraps a `KalmanFilter` and estimates the system noise covariance Q and the measurement noise
+/// covariance R online from the innovations e = y - C x_k|k-1. Both estimators rely on
+///
+/// ```math
+///     E[ e e^T ]          = C P_k|k-1 C^T + R
+///     E[ K e e^T K^T ]    = P_k|k-1 - P_k|k   = F P_k-1|k-1 F^T + Q - P_k|k
+/// ```
+///
+/// Each measurement is gated and processed with the current R. Only accepted measurements
+/// contribute to the estimates, which take effect from the next measurement on.
+///
+/// After every estimation step, the eigenvalues of Q and R are clamped to the configured bounds,
+/// which keeps R positive definite and Q positive semidefinite (definite if the initial Q is).
+///
+/// Since R is estimated, measurements are processed with `measure_vector()` only, once per
+/// timestep and always with the same number of measurements.
+pub struct AdaptiveKalmanFilter<N : Real>
+{
+    filter : KalmanFilter<N>,
+    estimation : NoiseEstimation<N>,
+    adapted_noise : AdaptedNoise,
+    mat_r : MeasurementNoiseCovarianceMatrix<N>,
+    system_noise_bounds : (N, Option<N>),
+    measurement_noise_bounds : (N, Option<N>),
+    /// P_k-1|k-1
+    mat_p_previous : CovarianceMatrix<N>,
+    num_updates : usize,
+    /// e e^T of the last innovations, newest first
+    innovations : VecDeque<DMatrix<N>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum NoiseEstimation<N : Real> {
+    /// Sage-Husa estimator with fading memory. With the fading factor 0 < b < 1, the weight of
+    /// the k-th update is d_k = (1 - b) / (1 - b^(k+1)) and
+    ///
+    /// ```math
+    ///     R = (1 - d_k) R + d_k ( e e^T - C P_k|k-1 C^T )
+    ///     Q = (1 - d_k) Q + d_k ( K e e^T K^T + P_k|k - F P_k-1|k-1 F^T )
+    /// ```
+    SageHusa { fading_factor : N },
+    /// Innovation covariance matching over a sliding window of the last W innovations
+    ///
+    /// ```math
+    ///     C_e = 1/W SUM_i e_i e_i^T
+    ///     R   = C_e - C P_k|k-1 C^T
+    ///     Q   = K C_e K^T + P_k|k - F P_k-1|k-1 F^T
+    /// ```
+    CovarianceMatching { window : usize },
+}
+
+/// Which covariances are estimated. Estimating both at once needs sufficiently exciting
+/// innovations, otherwise Q and R cannot be separated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdaptedNoise {
+    SystemNoise,
+    MeasurementNoise,
+    Both,
+}
+
+pub struct AdaptiveKalmanFilterBuilder<N : Real>
+{
+    filter : AdaptiveKalmanFilter<N>,
+}
+
+impl<N : Real> AdaptiveKalmanFilterBuilder<N> {
+    /// Starts with the Q of `filter` and the initial guess `mat_r`, which has to be positive
+    /// definite
+    pub fn new(filter : KalmanFilter<N>, mat_r : MeasurementNoiseCovarianceMatrix<N>) -> Self {
+        assert_eq!(mat_r.nrows(), mat_r.ncols());
+        let mat_p_previous = filter.get_state().mat_covariances.clone();
+        let floor = convert::<f64, N>(1e-3);
+        let system_noise_floor = max_eigenvalue(&filter.get_system_noise_variances().0) * floor;
+        let measurement_noise_floor = max_eigenvalue(&mat_r.0) * floor;
+        assert!(measurement_noise_floor > N::zero(), "R must be positive definite");
+        AdaptiveKalmanFilterBuilder {
+            filter : AdaptiveKalmanFilter {
+                filter : filter,
+                estimation : NoiseEstimation::SageHusa { fading_factor : convert(0.98) },
+                adapted_noise : AdaptedNoise::Both,
+                mat_r : mat_r,
+                system_noise_bounds : (system_noise_floor, None),
+                measurement_noise_bounds : (measurement_noise_floor, None),
+                mat_p_previous : mat_p_previous,
+                num_updates : 0,
+                innovations : VecDeque::new(),
+            }
+        }
+    }
+
+    /// Default is Sage-Husa with a fading factor of 0.98
+    pub fn with_estimation(mut self, estimation : NoiseEstimation<N>) -> Self {
+        match estimation {
+            NoiseEstimation::SageHusa { fading_factor } =>
+                assert!(fading_factor > N::zero() && fading_factor < N::one()),
+            NoiseEstimation::CovarianceMatching { window } =>
+                assert!(window >= 1),
+        }
+        self.filter.estimation = estimation;
+        self
+    }
+
+    /// Default is `AdaptedNoise::Both`
+    pub fn with_adapted_noise(mut self, adapted_noise : AdaptedNoise) -> Self {
+        self.filter.adapted_noise = adapted_noise;
+        self
+    }
+
+    /// Bounds for the eigenvalues of Q, default is [q, inf) with 1e-3 times the largest
+    /// eigenvalue of the initial Q as q
+    pub fn with_system_noise_bounds(mut self, lower : N, upper : Option<N>) -> Self {
+        assert!(lower >= N::zero());
+        self.filter.system_noise_bounds = (lower, upper);
+        self
+    }
+
+    /// Bounds for the eigenvalues of R, default is [r, inf) with 1e-3 times the largest
+    /// eigenvalue of the initial R as r. The lower bound has to be positive, so that the
+    /// innovation covariance S stays regular.
+    pub fn with_measurement_noise_bounds(mut self, lower : N, upper : Option<N>) -> Self {
+        assert!(lower > N::zero());
+        self.filter.measurement_noise_bounds = (lower, upper);
+        self
+    }
+}
+
+impl<N : Real> From<AdaptiveKalmanFilterBuilder<N>> for AdaptiveKalmanFilter<N> {
+    fn from(builder : AdaptiveKalmanFilterBuilder<N>) -> AdaptiveKalmanFilter<N> {
+        builder.filter
+    }
+}
+
+impl<N : Real> AdaptiveKalmanFilter<N> {
+
+    pub fn get_state<'a>(&'a self) -> BorrowedSystemState<'a, N> {
+        self.filter.get_state()
+    }
+
+    pub fn get_filter(&self) -> &KalmanFilter<N> {
+        &self.filter
+    }
+
+    /// The current estimate of Q
+    pub fn get_system_noise_variances(&self) -> &SystemNoiseVarianceMatrix<N> {
+        self.filter.get_system_noise_variances()
+    }
+
+    /// The current estimate of R
+    pub fn get_measurement_noise_variances(&self) -> &MeasurementNoiseCovarianceMatrix<N> {
+        &self.mat_r
+    }
+
+    pub fn predict<'a>(&'a mut self, u : &InputVector<N>) -> BorrowedSystemState<'a, N> {
+        self.mat_p_previous = self.filter.get_state().mat_covariances.clone();
+        self.filter.predict(u)
+    }
+
+    /// Gates and processes the measurement with the current R, then updates the estimates of R
+    /// and Q. Rejected measurements do not change the estimates.
+    pub fn measure_vector<'a>(&'a mut self,
+                              vec_y : MeasurementVector<N>,
+                              mat_c : MeasurementMatrix<N>)
+                           -> MeasurementUpdate<'a, N> {
+        assert_eq!(self.mat_r.nrows(), vec_y.len());
+
+        let (vec_innovation, mat_cpc) = {
+            let state = self.filter.get_state();
+            (&vec_y.0 - &mat_c.0 * &state.vec_state.0,
+             &mat_c.0 * &state.mat_covariances.0 * mat_c.0.transpose())
+        };
+        let mat_ee = &vec_innovation * vec_innovation.transpose();
+        let (weight, mat_ce) = self.weigh(&mat_ee);
+
+        // The measurement is gated and processed with the prior R, so an outlier cannot widen
+        // its own gate
+        let (vec_innovation, mat_s, mat_k, nis, gating) = {
+            let update = self.filter.measure_vector(vec_y, mat_c, self.mat_r.clone());
+            (update.vec_innovation, update.mat_s, update.mat_k, update.nis, update.gating)
+        };
+
+        if gating != GatingDecision::Rejected {
+            self.num_updates += 1;
+            self.record_innovation(mat_ee);
+            if self.adapted_noise != AdaptedNoise::SystemNoise {
+                let mat_r = &self.mat_r.0 * (N::one() - weight) + (&mat_ce - mat_cpc) * weight;
+                self.mat_r.0 = clamp_eigenvalues(mat_r, self.measurement_noise_bounds);
+            }
+            if self.adapted_noise != AdaptedNoise::MeasurementNoise {
+                let mat_f = &self.filter.get_system_matrix().0;
+                let mat_q_observed = &mat_k.0 * &mat_ce * mat_k.0.transpose()
+                                   + &self.filter.get_state().mat_covariances.0
+                                   - mat_f * &self.mat_p_previous.0 * mat_f.transpose();
+                let mat_q = &self.filter.get_system_noise_variances().0 * (N::one() - weight)
+                          + mat_q_observed * weight;
+                let mat_q = clamp_eigenvalues(mat_q, self.system_noise_bounds);
+                self.filter.set_system_noise_variances(SystemNoiseVarianceMatrix(mat_q));
+            }
+        }
+
+        let state = self.filter.get_state();
+        MeasurementUpdate {
+            vec_state : state.vec_state,
+            mat_covariances : state.mat_covariances,
+            vec_innovation : vec_innovation,
+            mat_s : mat_s,
+            mat_k : mat_k,
+            nis : nis,
+            gating : gating,
+        }
+    }
+
+    /// The weight of the new estimate and the observed innovation covariance, including the new
+    /// innovation e e^T
+    fn weigh(&self, mat_ee : &DMatrix<N>) -> (N, DMatrix<N>) {
+        match self.estimation {
+            NoiseEstimation::SageHusa { fading_factor } => {
+                let b_k = fading_factor.powi(self.num_updates as i32 + 1);
+                ((N::one() - fading_factor) / (N::one() - b_k), mat_ee.clone())
+            },
+            NoiseEstimation::CovarianceMatching { window } => {
+                let mut mat_ce = mat_ee.clone();
+                for mat in self.innovations.iter().take(window - 1) {
+                    mat_ce += mat;
+                }
+                let count = self.innovations.len().min(window - 1) + 1;
+                mat_ce /= convert::<f64, N>(count as f64);
+                (N::one(), mat_ce)
+            },
+        }
+    }
+
+    /// Adds the innovation of an accepted measurement to the window
+    fn record_innovation(&mut self, mat_ee : DMatrix<N>) {
+        if let NoiseEstimation::CovarianceMatching { window } = self.estimation {
+            self.innovations.push_front(mat_ee);
+            self.innovations.truncate(window);
+        }
+    }
+}
+
+fn max_eigenvalue<N : Real>(mat_a : &DMatrix<N>) -> N {
+    mat_a.clone().symmetric_eigen().eigenvalues.iter().fold(N::zero(), |acc, &l| acc.max(l))
+}
+
+/// Symmetrizes A and clamps its eigenvalues to [lower, upper]
+fn clamp_eigenvalues<N : Real>(mat_a : DMatrix<N>, bounds : (N, Option<N>)) -> DMatrix<N> {
+    let (lower, upper) = bounds;
+    let mat_a = (&mat_a + mat_a.transpose()) * convert::<f64, N>(0.5);
+    let eigen = mat_a.symmetric_eigen();
+    let vec_lambda : DVector<N> = eigen.eigenvalues.map(|lambda| {
+        let lambda = lambda.max(lower);
+        match upper {
+            Some(upper) => lambda.min(upper),
+            None => lambda,
+        }
+    });
+    let mat_v = eigen.eigenvectors;
+    &mat_v * DMatrix::from_diagonal(&vec_lambda) * mat_v.transpose()
+}
@@ -0,0 +1,390 @@
+use std::convert::From;
+
+use alga::general::Real;
+use na::{DMatrix, DVector, convert};
+
+use kf::KalmanFilter;
+use nt::{DiscreteSystemMatrix, DiscreteInputMatrix, SystemNoiseVarianceMatrix, StateVector,
+         InputVector, MeasurementVector, MeasurementMatrix, MeasurementNoiseCovarianceMatrix,
+         KalmanGainMatrix};
+
+
+/// Autocovariance least-squares (ALS) estimation of the noise covariances
+///
+/// For the model
+///
+/// ```math
+///     x_k+1 = F x_k + H u_k + w_k,    w_k ~ N(0, Q)
+///     y_k   = C x_k + v_k,            v_k ~ N(0, R)
+/// ```
+///
+/// a filter with a fixed, possibly suboptimal gain K runs over the dataset. Its prediction
+/// error e_k = x_k - x_k|k-1 evolves with A = F (I - K C) as
+/// e_k+1 = A e_k + w_k - F K v_k, and the autocovariances of the innovations
+/// i_k = C e_k + v_k are linear in Q and R:
+///
+/// ```math
+///     P    = A P A^T + Q + F K R K^T F^T
+///     C_0  = C P C^T + R
+///     C_j  = C A^j P C^T - C A^j-1 F K R,   j >= 1
+/// ```
+///
+/// The estimates of C_0 ... C_N-1 from the data then give a least-squares problem for Q and R,
+/// which is solved subject to Q and R being positive semidefinite.
+///
+/// Q and R are identifiable if the least-squares problem has a unique solution, i.e. if its
+/// regression matrix has full column rank. Its condition number is reported with the estimates;
+/// restricting Q and R to diagonal matrices often makes them identifiable.
+pub struct AutocovarianceLeastSquares<N : Real>
+{
+    mat_f : DiscreteSystemMatrix<N>,
+    mat_h : DiscreteInputMatrix<N>,
+    mat_c : MeasurementMatrix<N>,
+    mat_k : KalmanGainMatrix<N>,
+    vec_x_init : StateVector<N>,
+    num_lags : usize,
+    structure : NoiseStructure,
+    num_discarded : usize,
+}
+
+/// The parametrization of Q and R
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoiseStructure {
+    /// Symmetric matrices
+    Full,
+    /// Diagonal matrices, i.e. uncorrelated noises
+    Diagonal,
+}
+
+pub struct AutocovarianceLeastSquaresBuilder<N : Real>
+{
+    als : AutocovarianceLeastSquares<N>,
+}
+
+/// Result of `AutocovarianceLeastSquares::estimate()`
+pub struct NoiseCovarianceEstimate<N : Real> {
+    pub mat_q : SystemNoiseVarianceMatrix<N>,
+    pub mat_r : MeasurementNoiseCovarianceMatrix<N>,
+    /// Condition number of the least-squares problem, infinite if it is singular
+    pub condition_number : N,
+    /// `true` if the condition number is below 1e6
+    pub identifiable : bool,
+}
+
+impl<N : Real> AutocovarianceLeastSquaresBuilder<N> {
+    /// F, H and the initial state are taken from `filter`. The gain K is the steady-state gain
+    /// for the guessed Q of `filter` and the guessed R.
+    pub fn new(filter : &KalmanFilter<N>,
+               mat_c : MeasurementMatrix<N>,
+               mat_r : MeasurementNoiseCovarianceMatrix<N>) -> Self {
+        assert_eq!(filter.get_num_states(), mat_c.ncols());
+        assert_eq!(mat_c.nrows(), mat_r.nrows());
+        assert_eq!(mat_c.nrows(), mat_r.ncols());
+        let mat_k = steady_state_gain(&filter.get_system_matrix().0,
+                                      &filter.get_system_noise_variances().0,
+                                      &mat_c.0, &mat_r.0);
+        AutocovarianceLeastSquaresBuilder {
+            als : AutocovarianceLeastSquares {
+                mat_f : filter.get_system_matrix().clone(),
+                mat_h : filter.get_input_matrix().clone(),
+                mat_c : mat_c,
+                mat_k : KalmanGainMatrix(mat_k),
+                vec_x_init : filter.get_state().vec_state.clone(),
+                num_lags : 10,
+                structure : NoiseStructure::Full,
+                num_discarded : 0,
+            }
+        }
+    }
+
+    /// Uses the gain K instead of the steady-state gain. F (I - K C) has to be stable.
+    pub fn with_gain(mut self, mat_k : KalmanGainMatrix<N>) -> Self {
+        assert_eq!(self.als.mat_c.ncols(), mat_k.nrows());
+        assert_eq!(self.als.mat_c.nrows(), mat_k.ncols());
+        self.als.mat_k = mat_k;
+        self
+    }
+
+    /// Number N of autocovariances C_0 ... C_N-1, default is 10
+    pub fn with_num_lags(mut self, num_lags : usize) -> Self {
+        assert!(num_lags >= 1);
+        self.als.num_lags = num_lags;
+        self
+    }
+
+    /// Default is `NoiseStructure::Full`
+    pub fn with_noise_structure(mut self, structure : NoiseStructure) -> Self {
+        self.als.structure = structure;
+        self
+    }
+
+    /// Number of innovations at the start of the dataset that are ignored while the initial
+    /// estimation error decays, default is 0
+    pub fn with_discarded_steps(mut self, num_discarded : usize) -> Self {
+        self.als.num_discarded = num_discarded;
+        self
+    }
+}
+
+impl<N : Real> From<AutocovarianceLeastSquaresBuilder<N>> for AutocovarianceLeastSquares<N> {
+    fn from(builder : AutocovarianceLeastSquaresBuilder<N>) -> AutocovarianceLeastSquares<N> {
+        builder.als
+    }
+}
+
+impl<N : Real> AutocovarianceLeastSquares<N> {
+
+    pub fn get_gain(&self) -> &KalmanGainMatrix<N> {
+        &self.mat_k
+    }
+
+    /// `measurements[k]` is y_k+1, measured after the prediction with `inputs[k]`
+    pub fn estimate(&self, inputs : &[InputVector<N>], measurements : &[MeasurementVector<N>])
+                    -> NoiseCovarianceEstimate<N> {
+        assert_eq!(inputs.len(), measurements.len());
+        assert!(measurements.len() > self.num_discarded + self.num_lags);
+
+        let n = self.mat_f.nrows();
+        let m = self.mat_c.nrows();
+        let vec_b = self.estimate_autocovariances(inputs, measurements);
+        let (mat_a_q, mat_a_r) = self.regression_matrices();
+
+        // Regression matrix for the free parameters of Q and R
+        let mat_d_q = structure_basis(n, self.structure);
+        let mat_d_r = structure_basis(m, self.structure);
+        let num_params_q = mat_d_q.ncols();
+        let num_params_r = mat_d_r.ncols();
+        let mut mat_m = DMatrix::zeros(vec_b.len(), num_params_q + num_params_r);
+        mat_m.columns_mut(0, num_params_q).copy_from(&(&mat_a_q * &mat_d_q));
+        mat_m.columns_mut(num_params_q, num_params_r).copy_from(&(&mat_a_r * &mat_d_r));
+
+        let mat_mm = mat_m.transpose() * &mat_m;
+        let eigenvalues = mat_mm.clone().symmetric_eigen().eigenvalues;
+        let lambda_max = eigenvalues.iter().fold(N::zero(), |acc, &l| acc.max(l));
+        let lambda_min = eigenvalues.iter().fold(lambda_max, |acc, &l| acc.min(l));
+        let condition_number = if lambda_min > N::zero() {
+            (lambda_max / lambda_min).sqrt()
+        } else {
+            N::one() / N::zero()
+        };
+        let identifiable = condition_number < convert(1e6);
+
+        // Unconstrained solution as starting point, if it exists
+        let mut mat_q = DMatrix::zeros(n, n);
+        let mut mat_r = DMatrix::zeros(m, m);
+        if identifiable {
+            let vec_theta = mat_mm.cholesky()
+                                  .expect("Least-squares problem is singular")
+                                  .solve(&(mat_m.transpose() * &vec_b));
+            let vec_q = &mat_d_q * vec_theta.rows(0, num_params_q);
+            let vec_r = &mat_d_r * vec_theta.rows(num_params_q, num_params_r);
+            mat_q = DMatrix::from_column_slice(n, n, vec_q.as_slice());
+            mat_r = DMatrix::from_column_slice(m, m, vec_r.as_slice());
+        }
+
+        let (mat_q, mat_r) = self.solve_constrained(&mat_a_q, &mat_a_r, &vec_b,
+                                                    mat_q, mat_r);
+        NoiseCovarianceEstimate {
+            mat_q : SystemNoiseVarianceMatrix(mat_q),
+            mat_r : MeasurementNoiseCovarianceMatrix(mat_r),
+            condition_number : condition_number,
+            identifiable : identifiable,
+        }
+    }
+
+    /// Runs the fixed gain filter and returns vec([ C_0; C_1; ...; C_N-1 ]) of the innovations
+    fn estimate_autocovariances(&self,
+                                inputs : &[InputVector<N>],
+                                measurements : &[MeasurementVector<N>]) -> DVector<N> {
+        let m = self.mat_c.nrows();
+        let num_lags = self.num_lags;
+
+        let mut vec_x = self.vec_x_init.0.clone();
+        let mut innovations = Vec::with_capacity(measurements.len());
+        for (u, vec_y) in inputs.iter().zip(measurements.iter()) {
+            vec_x = &self.mat_f.0 * &vec_x + &self.mat_h.0 * &u.0;
+            let vec_innovation = &vec_y.0 - &self.mat_c.0 * &vec_x;
+            vec_x += &self.mat_k.0 * &vec_innovation;
+            innovations.push(vec_innovation);
+        }
+        let innovations = &innovations[self.num_discarded..];
+
+        let mut mat_autocovariances = DMatrix::zeros(num_lags * m, m);
+        for j in 0..num_lags {
+            let mut mat_c_j = DMatrix::zeros(m, m);
+            for k in 0..(innovations.len() - j) {
+                mat_c_j += &innovations[k + j] * innovations[k].transpose();
+            }
+            mat_c_j /= convert::<f64, N>((innovations.len() - j) as f64);
+            mat_autocovariances.rows_mut(j * m, m).copy_from(&mat_c_j);
+        }
+        DVector::from_column_slice(num_lags * m * m, mat_autocovariances.as_slice())
+    }
+
+    /// A_Q and A_R with vec([ C_0; ...; C_N-1 ]) = A_Q vec(Q) + A_R vec(R)
+    fn regression_matrices(&self) -> (DMatrix<N>, DMatrix<N>) {
+        let n = self.mat_f.nrows();
+        let m = self.mat_c.nrows();
+        let num_lags = self.num_lags;
+        let mat_c = &self.mat_c.0;
+        let mat_fk = &self.mat_f.0 * &self.mat_k.0;
+        let mat_a = &self.mat_f.0 - &mat_fk * mat_c;
+
+        // O = [ C; C A; ...; C A^N-1 ],  G = [ I; -C F K; -C A F K; ...; -C A^N-2 F K ]
+        let mut mat_o = DMatrix::zeros(num_lags * m, n);
+        let mut mat_g = DMatrix::zeros(num_lags * m, m);
+        mat_g.rows_mut(0, m).copy_from(&DMatrix::identity(m, m));
+        let mut mat_c_a = mat_c.clone();
+        for j in 0..num_lags {
+            mat_o.rows_mut(j * m, m).copy_from(&mat_c_a);
+            if j + 1 < num_lags {
+                mat_g.rows_mut((j + 1) * m, m).copy_from(&-(&mat_c_a * &mat_fk));
+            }
+            mat_c_a = &mat_c_a * &mat_a;
+        }
+
+        // vec(O P C^T) = (C x O) vec(P),  vec(P) = (I - A x A)^-1 ( vec(Q) + (FK x FK) vec(R) )
+        let mat_i_aa = DMatrix::identity(n * n, n * n) - kronecker(&mat_a, &mat_a);
+        let mat_p_q = mat_i_aa.lu()
+                              .try_inverse()
+                              .expect("F (I - K C) is not stable");
+        let mat_c_o = kronecker(mat_c, &mat_o);
+        let mat_a_q = &mat_c_o * &mat_p_q;
+        let mat_a_r = &mat_a_q * kronecker(&mat_fk, &mat_fk)
+                    + kronecker(&DMatrix::identity(m, m), &mat_g);
+        (mat_a_q, mat_a_r)
+    }
+
+    /// Minimizes || A_Q vec(Q) + A_R vec(R) - b ||^2 subject to Q, R positive semidefinite, with
+    /// the accelerated projected gradient method
+    fn solve_constrained(&self,
+                         mat_a_q : &DMatrix<N>,
+                         mat_a_r : &DMatrix<N>,
+                         vec_b : &DVector<N>,
+                         mat_q : DMatrix<N>,
+                         mat_r : DMatrix<N>) -> (DMatrix<N>, DMatrix<N>) {
+        let n = mat_q.nrows();
+        let m = mat_r.nrows();
+        let project = |mat_q : DMatrix<N>, mat_r : DMatrix<N>| {
+            (project_psd(mat_q, self.structure), project_psd(mat_r, self.structure))
+        };
+
+        // Step size 1 / L with the Lipschitz constant L = lambda_max( A^T A )
+        let mut mat_a = DMatrix::zeros(vec_b.len(), n * n + m * m);
+        mat_a.columns_mut(0, n * n).copy_from(mat_a_q);
+        mat_a.columns_mut(n * n, m * m).copy_from(mat_a_r);
+        let lipschitz = (mat_a.transpose() * &mat_a).symmetric_eigen().eigenvalues
+                            .iter().fold(N::zero(), |acc, &l| acc.max(l));
+        let step = lipschitz.recip();
+
+        let (mut mat_q, mut mat_r) = project(mat_q, mat_r);
+        let (mut mat_q_y, mut mat_r_y) = (mat_q.clone(), mat_r.clone());
+        let mut t = N::one();
+        let tolerance : N = convert(1e-12);
+        for _ in 0..10000 {
+            let vec_residual = mat_a_q * DVector::from_column_slice(n * n, mat_q_y.as_slice())
+                             + mat_a_r * DVector::from_column_slice(m * m, mat_r_y.as_slice())
+                             - vec_b;
+            let vec_grad_q = mat_a_q.transpose() * &vec_residual;
+            let vec_grad_r = mat_a_r.transpose() * &vec_residual;
+            let (mat_q_next, mat_r_next) = project(
+                &mat_q_y - DMatrix::from_column_slice(n, n, vec_grad_q.as_slice()) * step,
+                &mat_r_y - DMatrix::from_column_slice(m, m, vec_grad_r.as_slice()) * step);
+
+            let t_next = (N::one() + (N::one() + t * t * convert(4.0)).sqrt()) * convert(0.5);
+            let momentum = (t - N::one()) / t_next;
+            let mat_dq = &mat_q_next - &mat_q;
+            let mat_dr = &mat_r_next - &mat_r;
+            mat_q_y = &mat_q_next + &mat_dq * momentum;
+            mat_r_y = &mat_r_next + &mat_dr * momentum;
+            mat_q = mat_q_next;
+            mat_r = mat_r_next;
+            t = t_next;
+
+            let change = mat_dq.norm_squared() + mat_dr.norm_squared();
+            let size = (mat_q.norm_squared() + mat_r.norm_squared()).max(N::one());
+            if change <= tolerance * tolerance * size {
+                break;
+            }
+        }
+        (mat_q, mat_r)
+    }
+}
+
+/// Steady-state gain K = P C^T ( C P C^T + R )^-1 of the predicted covariance P from the
+/// Riccati recursion
+fn steady_state_gain<N : Real>(mat_f : &DMatrix<N>, mat_q : &DMatrix<N>, mat_c : &DMatrix<N>,
+                               mat_r : &DMatrix<N>) -> DMatrix<N> {
+    let mut mat_p = mat_q.clone();
+    let mut mat_k = DMatrix::zeros(mat_c.ncols(), mat_c.nrows());
+    for _ in 0..10000 {
+        let mat_cp = mat_c * &mat_p;
+        let mat_s = &mat_cp * mat_c.transpose() + mat_r;
+        mat_k = mat_s.cholesky()
+                     .expect("Innovation covariance S is not positive definite")
+                     .solve(&mat_cp)
+                     .transpose();
+        let mat_p_next = mat_f * (&mat_p - &mat_k * mat_cp) * mat_f.transpose() + mat_q;
+        let mat_p_next = (&mat_p_next + mat_p_next.transpose()) * convert::<f64, N>(0.5);
+        let change = (&mat_p_next - &mat_p).norm_squared();
+        let size = mat_p_next.norm_squared();
+        mat_p = mat_p_next;
+        if change <= size * convert(1e-24) {
+            break;
+        }
+    }
+    mat_k
+}
+
+/// D with vec(X) = D theta for the free parameters theta of an n x n matrix X
+fn structure_basis<N : Real>(n : usize, structure : NoiseStructure) -> DMatrix<N> {
+    let mut columns = Vec::new();
+    for j in 0..n {
+        let mut vec_d = DVector::zeros(n * n);
+        vec_d[j * n + j] = N::one();
+        columns.push(vec_d);
+        if structure == NoiseStructure::Full {
+            for i in (j + 1)..n {
+                let mut vec_d = DVector::zeros(n * n);
+                vec_d[j * n + i] = N::one();
+                vec_d[i * n + j] = N::one();
+                columns.push(vec_d);
+            }
+        }
+    }
+    let mut mat_d = DMatrix::zeros(n * n, columns.len());
+    for (i, vec_d) in columns.iter().enumerate() {
+        mat_d.set_column(i, vec_d);
+    }
+    mat_d
+}
+
+/// Projection onto the positive semidefinite matrices with the given structure
+fn project_psd<N : Real>(mat_x : DMatrix<N>, structure : NoiseStructure) -> DMatrix<N> {
+    match structure {
+        NoiseStructure::Diagonal => {
+            let vec_diagonal = mat_x.diagonal().map(|x| x.max(N::zero()));
+            DMatrix::from_diagonal(&vec_diagonal)
+        },
+        NoiseStructure::Full => {
+            let mat_x = (&mat_x + mat_x.transpose()) * convert::<f64, N>(0.5);
+            let eigen = mat_x.symmetric_eigen();
+            let vec_lambda = eigen.eigenvalues.map(|lambda| lambda.max(N::zero()));
+            let mat_v = eigen.eigenvectors;
+            &mat_v * DMatrix::from_diagonal(&vec_lambda) * mat_v.transpose()
+        },
+    }
+}
+
+/// Kronecker product A x B
+fn kronecker<N : Real>(mat_a : &DMatrix<N>, mat_b : &DMatrix<N>) -> DMatrix<N> {
+    let (rows_b, cols_b) = mat_b.shape();
+    let mut mat_ab = DMatrix::zeros(mat_a.nrows() * rows_b, mat_a.ncols() * cols_b);
+    for i in 0..mat_a.nrows() {
+        for j in 0..mat_a.ncols() {
+            mat_ab.slice_mut((i * rows_b, j * cols_b), (rows_b, cols_b))
+                  .copy_from(&(mat_b * mat_a[(i, j)]));
+        }
+    }
+    mat_ab
+}
@@ -0,0 +1,95 @@
+use std::convert::From;
+
+use alga::general::Real;
+
+use kf::BorrowedSystemState;
+use ukf::{UnscentedKalmanFilter, UnscentedKalmanFilterBuilder, UnitSigmaPoints};
+use nt::{SystemNoiseVarianceMatrix, StateVector, CovarianceMatrix, InputVector, Measurement,
+         MeasurementNoiseVariance, MeasurementVector, MeasurementNoiseCovarianceMatrix};
+
+
+/// Cubature Kalman Filter
+///
+/// Uses the third-degree spherical-radial cubature rule with the 2n points
+///
+/// ```math
+///     x +- sqrt(n) sqrt(P) e_i        with the weights 1 / 2n
+/// ```
+///
+/// Unlike the unscented filter, there are no tuning parameters and all weights are positive,
+/// which keeps P positive definite also for a large number of states.
+pub struct CubatureKalmanFilter<N : Real>
+{
+    filter : UnscentedKalmanFilter<N>,
+}
+
+pub struct CubatureKalmanFilterBuilder<N : Real>
+{
+    builder : UnscentedKalmanFilterBuilder<N>,
+}
+
+impl<N : Real> CubatureKalmanFilterBuilder<N> {
+    /// Defaults to `f(x, u) = x` and `c(x) = x`.
+    pub fn with_numstates_and_numinputs(num_states : usize, num_inputs : usize) -> CubatureKalmanFilterBuilder<N> {
+        CubatureKalmanFilterBuilder {
+            builder : UnscentedKalmanFilterBuilder::with_numstates_and_numinputs(num_states, num_inputs)
+                .with_unit_sigma_points(UnitSigmaPoints::cubature(num_states)),
+        }
+    }
+
+    pub fn with_state_function<F>(self, fn_f : F) -> Self
+        where F : Fn(&StateVector<N>, &InputVector<N>) -> StateVector<N> + 'static {
+        CubatureKalmanFilterBuilder { builder : self.builder.with_state_function(fn_f) }
+    }
+
+    pub fn with_output_function<C>(self, fn_c : C) -> Self
+        where C : Fn(&StateVector<N>) -> MeasurementVector<N> + 'static {
+        CubatureKalmanFilterBuilder { builder : self.builder.with_output_function(fn_c) }
+    }
+
+    pub fn with_system_noise_variances(self, mat_q : SystemNoiseVarianceMatrix<N>) -> Self {
+        CubatureKalmanFilterBuilder { builder : self.builder.with_system_noise_variances(mat_q) }
+    }
+
+    pub fn with_initial_state(self, vec_state : StateVector<N>, mat_covariances : CovarianceMatrix<N>) -> Self {
+        CubatureKalmanFilterBuilder {
+            builder : self.builder.with_initial_state(vec_state, mat_covariances)
+        }
+    }
+}
+
+impl<N : Real> From<CubatureKalmanFilterBuilder<N>> for CubatureKalmanFilter<N> {
+    fn from(builder : CubatureKalmanFilterBuilder<N>) -> CubatureKalmanFilter<N> {
+        CubatureKalmanFilter {
+            filter : builder.builder.into(),
+        }
+    }
+}
+
+impl<N : Real> CubatureKalmanFilter<N> {
+
+    pub fn get_state<'a>(&'a self) -> BorrowedSystemState<'a, N> {
+        self.filter.get_state()
+    }
+
+    pub fn predict<'a>(&'a mut self, u : &InputVector<N>) -> BorrowedSystemState<'a, N> {
+        self.filter.predict(u)
+    }
+
+    /// Processes the measurement of the single output `index` of `c(x)`.
+    pub fn measure<'a>(&'a mut self,
+                       y : Measurement<N>,
+                       index : usize,
+                       r : MeasurementNoiseVariance<N>)
+                    -> BorrowedSystemState<'a, N> {
+        self.filter.measure(y, index, r)
+    }
+
+    /// Processes the measurement of all outputs of `c(x)` at once.
+    pub fn measure_vector<'a>(&'a mut self,
+                              vec_y : MeasurementVector<N>,
+                              mat_r : MeasurementNoiseCovarianceMatrix<N>)
+                           -> BorrowedSystemState<'a, N> {
+        self.filter.measure_vector(vec_y, mat_r)
+    }
+}
@@ -0,0 +1,248 @@
+use std::convert::From;
+
+use alga::general::Real;
+use na::{DMatrix, DVector};
+
+use kf::BorrowedSystemState;
+use nt::{DiscreteSystemMatrix, SystemNoiseVarianceMatrix, StateVector, CovarianceMatrix,
+         InputVector, Measurement, MeasurementNoiseVariance, MeasurementVector,
+         MeasurementMatrix, MeasurementNoiseCovarianceMatrix};
+
+
+/// x_{k+1} = f( x_k, u_k )
+pub type StateFunction<N> = Box<Fn(&StateVector<N>, &InputVector<N>) -> StateVector<N>>;
+/// J_f( x_k, u_k ), the Jacobian of f() with respect to the state
+pub type StateJacobian<N> = Box<Fn(&StateVector<N>, &InputVector<N>) -> DiscreteSystemMatrix<N>>;
+/// y_k = c( x_k )
+pub type OutputFunction<N> = Box<Fn(&StateVector<N>) -> MeasurementVector<N>>;
+/// J_c( x_k ), the Jacobian of c() with respect to the state
+pub type OutputJacobian<N> = Box<Fn(&StateVector<N>) -> MeasurementMatrix<N>>;
+
+pub struct ExtendedKalmanFilter<N : Real>
+{
+    num_states : usize,
+    num_inputs : usize,
+    fn_f : StateFunction<N>,
+    fn_jacobian_f : StateJacobian<N>,
+    fn_c : OutputFunction<N>,
+    fn_jacobian_c : OutputJacobian<N>,
+    mat_q : SystemNoiseVarianceMatrix<N>,
+    vec_state : StateVector<N>,
+    mat_p : CovarianceMatrix<N>,
+    linearization : MeasurementLinearization<N>,
+}
+
+/// Linearization of c() in the measurement update
+#[derive(Clone, Copy, Debug)]
+pub enum MeasurementLinearization<N : Real> {
+    /// Standard EKF: c() is linearized once around the predicted state
+    Single,
+    /// Iterated EKF: c() is relinearized around the updated state (Gauss-Newton) until the
+    /// state changes less than `tolerance` (euclidean norm), but at most `max_iterations` times
+    Iterated { max_iterations : usize, tolerance : N },
+}
+
+pub struct ExtendedMeasurementUpdate<'a, N : Real + 'a> {
+    pub vec_state : &'a StateVector<N>,
+    pub mat_covariances : &'a CovarianceMatrix<N>,
+    /// Number of linearizations of c(), always 1 for `MeasurementLinearization::Single`
+    pub iterations : usize,
+}
+
+pub struct ExtendedKalmanFilterBuilder<N : Real>
+{
+    filter : ExtendedKalmanFilter<N>,
+}
+
+impl<N : Real> ExtendedKalmanFilterBuilder<N> {
+    /// Defaults to `f(x, u) = x` and `c(x) = x`.
+    pub fn with_numstates_and_numinputs(num_states : usize, num_inputs : usize) -> ExtendedKalmanFilterBuilder<N> {
+        ExtendedKalmanFilterBuilder {
+            filter : ExtendedKalmanFilter {
+                num_states : num_states,
+                num_inputs : num_inputs,
+                fn_f : Box::new(|x, _| x.clone()),
+                fn_jacobian_f : Box::new(move |_, _| DiscreteSystemMatrix(DMatrix::identity(num_states, num_states))),
+                fn_c : Box::new(|x| MeasurementVector(x.0.clone())),
+                fn_jacobian_c : Box::new(move |_| MeasurementMatrix(DMatrix::identity(num_states, num_states))),
+                mat_q : SystemNoiseVarianceMatrix(DMatrix::zeros(num_states, num_states)),
+                vec_state : StateVector(DVector::zeros(num_states)),
+                mat_p : CovarianceMatrix(DMatrix::identity(num_states, num_states)),
+                linearization : MeasurementLinearization::Single,
+            }
+        }
+    }
+
+    pub fn with_state_function<F, J>(mut self, fn_f : F, fn_jacobian_f : J) -> Self
+        where F : Fn(&StateVector<N>, &InputVector<N>) -> StateVector<N> + 'static,
+              J : Fn(&StateVector<N>, &InputVector<N>) -> DiscreteSystemMatrix<N> + 'static {
+        self.filter.fn_f = Box::new(fn_f);
+        self.filter.fn_jacobian_f = Box::new(fn_jacobian_f);
+        self
+    }
+
+    pub fn with_output_function<C, J>(mut self, fn_c : C, fn_jacobian_c : J) -> Self
+        where C : Fn(&StateVector<N>) -> MeasurementVector<N> + 'static,
+              J : Fn(&StateVector<N>) -> MeasurementMatrix<N> + 'static {
+        self.filter.fn_c = Box::new(fn_c);
+        self.filter.fn_jacobian_c = Box::new(fn_jacobian_c);
+        self
+    }
+
+    pub fn with_system_noise_variances(mut self, mat_q : SystemNoiseVarianceMatrix<N>) -> Self {
+        assert_eq!(self.filter.num_states, mat_q.ncols());
+        assert_eq!(self.filter.num_states, mat_q.nrows());
+        self.filter.mat_q = mat_q;
+        self
+    }
+
+    pub fn with_initial_state(mut self, vec_state : StateVector<N>, mat_covariances : CovarianceMatrix<N>) -> Self {
+        assert_eq!(self.filter.num_states, vec_state.len());
+        assert_eq!(self.filter.num_states, mat_covariances.ncols());
+        assert_eq!(self.filter.num_states, mat_covariances.nrows());
+        self.filter.vec_state = vec_state;
+        self.filter.mat_p = mat_covariances;
+        self
+    }
+
+    pub fn with_measurement_linearization(mut self, linearization : MeasurementLinearization<N>) -> Self {
+        self.filter.linearization = linearization;
+        self
+    }
+}
+
+impl<N : Real> From<ExtendedKalmanFilterBuilder<N>> for ExtendedKalmanFilter<N> {
+    fn from(builder : ExtendedKalmanFilterBuilder<N>) -> ExtendedKalmanFilter<N> {
+        builder.filter
+    }
+}
+
+impl<N : Real> ExtendedKalmanFilter<N> {
+
+    pub fn get_state<'a>(&'a self) -> BorrowedSystemState<'a, N> {
+        BorrowedSystemState {
+            vec_state : &self.vec_state,
+            mat_covariances : &self.mat_p,
+        }
+    }
+
+    pub fn predict<'a>(&'a mut self, u : &InputVector<N>) -> BorrowedSystemState<'a, N> {
+        assert_eq!(self.num_inputs, u.0.len());
+        // The Jacobian has to be evaluated at the old state
+        let mat_jf = (self.fn_jacobian_f)(&self.vec_state, u);
+        assert_eq!(self.num_states, mat_jf.nrows());
+        assert_eq!(self.num_states, mat_jf.ncols());
+
+        self.vec_state = (self.fn_f)(&self.vec_state, u);
+        assert_eq!(self.num_states, self.vec_state.len());
+        self.mat_p = CovarianceMatrix( &mat_jf.0 * &self.mat_p.0 * &mat_jf.0.transpose()
+                                     + &self.mat_q.0 );
+        BorrowedSystemState {
+            vec_state : &self.vec_state,
+            mat_covariances : &self.mat_p,
+        }
+    }
+
+    /// Processes the measurement of the single output `index` of `c(x)`.
+    pub fn measure<'a>(&'a mut self,
+                       y : Measurement<N>,
+                       index : usize,
+                       r : MeasurementNoiseVariance<N>)
+                    -> ExtendedMeasurementUpdate<'a, N> {
+        let iterations = self.update(&DVector::from_element(1, y.0),
+                                     &DMatrix::from_element(1, 1, r.0),
+                                     Some(index));
+        ExtendedMeasurementUpdate {
+            vec_state : &self.vec_state,
+            mat_covariances : &self.mat_p,
+            iterations : iterations,
+        }
+    }
+
+    /// Processes the measurement of all outputs of `c(x)` at once.
+    pub fn measure_vector<'a>(&'a mut self,
+                              vec_y : MeasurementVector<N>,
+                              mat_r : MeasurementNoiseCovarianceMatrix<N>)
+                           -> ExtendedMeasurementUpdate<'a, N> {
+        let iterations = self.update(&vec_y.0, &mat_r.0, None);
+        ExtendedMeasurementUpdate {
+            vec_state : &self.vec_state,
+            mat_covariances : &self.mat_p,
+            iterations : iterations,
+        }
+    }
+
+    /// Returns the number of linearizations
+    fn update(&mut self, vec_y : &DVector<N>, mat_r : &DMatrix<N>, index : Option<usize>) -> usize {
+        let num_measurements = vec_y.len();
+        assert_eq!(num_measurements, mat_r.nrows());
+        assert_eq!(num_measurements, mat_r.ncols());
+
+        let (max_iterations, tolerance) = match self.linearization {
+            MeasurementLinearization::Single => (1, N::zero()),
+            MeasurementLinearization::Iterated { max_iterations, tolerance } => (max_iterations, tolerance),
+        };
+        assert!(max_iterations >= 1);
+
+        // Gauss-Newton iteration, relinearizing c() around x_i:
+        //     K_i     = P J_c(x_i)^T ( J_c(x_i) P J_c(x_i)^T + R )^-1
+        //     x_(i+1) = x + K_i ( y - c(x_i) - J_c(x_i) (x - x_i) )
+        // The first iteration (x_0 = x) is the standard EKF update.
+        let vec_x_prior = self.vec_state.0.clone();
+        let mut vec_x_i = vec_x_prior.clone();
+        let mut mat_k;
+        let mut mat_jc;
+        let mut iterations = 0;
+        loop {
+            iterations += 1;
+            let vec_state_i = StateVector(vec_x_i.clone());
+            let vec_c_x = (self.fn_c)(&vec_state_i);
+            let mat_jc_full = (self.fn_jacobian_c)(&vec_state_i);
+            assert_eq!(vec_c_x.len(), mat_jc_full.nrows());
+            assert_eq!(self.num_states, mat_jc_full.ncols());
+            if let Some(index) = index {
+                assert!(index < vec_c_x.len(),
+                        "Output index {} out of range, c(x) has {} outputs", index, vec_c_x.len());
+            }
+
+            let vec_c_x = match index {
+                Some(index) => DVector::from_element(1, vec_c_x[index]),
+                None => vec_c_x.0,
+            };
+            mat_jc = match index {
+                Some(index) => DMatrix::from_iterator(1, self.num_states,
+                                                      mat_jc_full.0.row(index).iter().cloned()),
+                None => mat_jc_full.0,
+            };
+            assert_eq!(num_measurements, vec_c_x.len());
+
+            let mat_pct = &self.mat_p.0 * mat_jc.transpose();
+
+            // S = C P C^T + R
+            let mat_s = &mat_jc * &mat_pct + mat_r;
+
+            // K = P C^T S^-1, calculated as solution of  S K^T = C P
+            mat_k = mat_s.cholesky()
+                         .expect("Innovation covariance S is not positive definite")
+                         .solve(&mat_pct.transpose())
+                         .transpose();
+
+            let vec_residual = vec_y - vec_c_x - &mat_jc * (&vec_x_prior - &vec_x_i);
+            let vec_x_next = &vec_x_prior + &mat_k * vec_residual;
+            let step = (&vec_x_next - &vec_x_i).norm();
+            vec_x_i = vec_x_next;
+
+            if iterations >= max_iterations || step <= tolerance {
+                break;
+            }
+        }
+
+        self.vec_state.0 = vec_x_i;
+
+        // P = P - K C P
+        let mat_kcp = &mat_k * &mat_jc * &self.mat_p.0;
+        self.mat_p.0 -= mat_kcp;
+
+        iterations
+    }
+}
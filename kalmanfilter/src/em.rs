@@ -0,0 +1,305 @@
+use std::convert::From;
+use std::ops::AddAssign;
+
+use alga::general::Real;
+use na::{DMatrix, DVector, convert};
+
+use kf::{KalmanFilter, KalmanFilterBuilder, CovarianceUpdate};
+use rts::{RauchTungStriebelSmoother, SmoothedState};
+use nt::{DiscreteSystemMatrix, DiscreteInputMatrix, SystemNoiseVarianceMatrix, StateVector,
+         CovarianceMatrix, InputVector, MeasurementVector, MeasurementMatrix,
+         MeasurementNoiseCovarianceMatrix};
+
+
+/// Maximum likelihood identification of a linear model with the Expectation-Maximization
+/// algorithm
+///
+/// For the model
+///
+/// ```math
+///     x_k+1 = F x_k + H u_k + w_k,    w_k ~ N(0, Q)
+///     y_k   = C x_k + v_k,            v_k ~ N(0, R)
+/// ```
+///
+/// every iteration runs a `KalmanFilter` and the `RauchTungStriebelSmoother` over the whole
+/// dataset with the current parameters (E-step) and then sets the parameters to the maximizers
+/// of the expected log-likelihood (M-step). With the smoothed moments
+/// E[ x_k x_k^T ] = x_k|K x_k|K^T + P_k|K and E[ x_k+1 x_k^T ] = x_k+1|K x_k|K^T + P_k+1,k|K
+/// and z_k = [ x_k; u_k ], the M-step is
+///
+/// ```math
+///     [F H] = SUM E[ x_k+1 z_k^T ] ( SUM E[ z_k z_k^T ] )^-1
+///     Q     = 1/K SUM E[ (x_k+1 - [F H] z_k) (x_k+1 - [F H] z_k)^T ]
+///     C     = SUM y_k E[ x_k ]^T ( SUM E[ x_k x_k^T ] )^-1
+///     R     = 1/K SUM E[ (y_k - C x_k) (y_k - C x_k)^T ]
+/// ```
+///
+/// Q and R are always estimated, F, H and C only if enabled in the builder. F and C can not be
+/// estimated together, since any change of the state coordinates yields the same likelihood.
+/// The initial state is not estimated.
+///
+/// The log-likelihood does not decrease from one iteration to the next, but EM can converge to
+/// a local maximum, so the initial guesses matter.
+pub struct ExpectationMaximization<N : Real>
+{
+    num_states : usize,
+    num_inputs : usize,
+    mat_f : DiscreteSystemMatrix<N>,
+    mat_h : DiscreteInputMatrix<N>,
+    mat_q : SystemNoiseVarianceMatrix<N>,
+    mat_c : MeasurementMatrix<N>,
+    mat_r : MeasurementNoiseCovarianceMatrix<N>,
+    vec_x_init : StateVector<N>,
+    mat_p_init : CovarianceMatrix<N>,
+    estimate_system_matrix : bool,
+    estimate_input_matrix : bool,
+    estimate_measurement_matrix : bool,
+    max_iterations : usize,
+    tolerance : N,
+}
+
+pub struct ExpectationMaximizationBuilder<N : Real>
+{
+    em : ExpectationMaximization<N>,
+}
+
+/// Result of `ExpectationMaximization::identify()`
+pub struct IdentifiedModel<N : Real> {
+    pub mat_f : DiscreteSystemMatrix<N>,
+    pub mat_h : DiscreteInputMatrix<N>,
+    pub mat_q : SystemNoiseVarianceMatrix<N>,
+    pub mat_c : MeasurementMatrix<N>,
+    pub mat_r : MeasurementNoiseCovarianceMatrix<N>,
+    /// Log-likelihood of the dataset after 0, 1, 2, ... iterations
+    pub log_likelihoods : Vec<N>,
+    /// `false` if the maximum number of iterations has been reached
+    pub converged : bool,
+}
+
+impl<N : Real> ExpectationMaximizationBuilder<N> {
+    /// F, H, Q and the initial state are taken from `filter`, C and R are the initial guesses of
+    /// the measurement model
+    pub fn new(filter : &KalmanFilter<N>,
+               mat_c : MeasurementMatrix<N>,
+               mat_r : MeasurementNoiseCovarianceMatrix<N>) -> Self {
+        assert_eq!(filter.get_num_states(), mat_c.ncols());
+        assert_eq!(mat_c.nrows(), mat_r.nrows());
+        assert_eq!(mat_c.nrows(), mat_r.ncols());
+        let state = filter.get_state();
+        ExpectationMaximizationBuilder {
+            em : ExpectationMaximization {
+                num_states : filter.get_num_states(),
+                num_inputs : filter.get_num_inputs(),
+                mat_f : filter.get_system_matrix().clone(),
+                mat_h : filter.get_input_matrix().clone(),
+                mat_q : filter.get_system_noise_variances().clone(),
+                mat_c : mat_c,
+                mat_r : mat_r,
+                vec_x_init : state.vec_state.clone(),
+                mat_p_init : state.mat_covariances.clone(),
+                estimate_system_matrix : false,
+                estimate_input_matrix : false,
+                estimate_measurement_matrix : false,
+                max_iterations : 100,
+                tolerance : convert(1e-6),
+            }
+        }
+    }
+
+    pub fn with_estimated_system_matrix(mut self) -> Self {
+        assert!(!self.em.estimate_measurement_matrix, "F and C can not be estimated together");
+        self.em.estimate_system_matrix = true;
+        self
+    }
+
+    pub fn with_estimated_input_matrix(mut self) -> Self {
+        self.em.estimate_input_matrix = true;
+        self
+    }
+
+    pub fn with_estimated_measurement_matrix(mut self) -> Self {
+        assert!(!self.em.estimate_system_matrix, "F and C can not be estimated together");
+        self.em.estimate_measurement_matrix = true;
+        self
+    }
+
+    /// Default is 100
+    pub fn with_max_iterations(mut self, max_iterations : usize) -> Self {
+        assert!(max_iterations >= 1);
+        self.em.max_iterations = max_iterations;
+        self
+    }
+
+    /// Iteration stops when the log-likelihood changes by less than `tolerance` relative to its
+    /// magnitude, default is 1e-6
+    pub fn with_tolerance(mut self, tolerance : N) -> Self {
+        self.em.tolerance = tolerance;
+        self
+    }
+}
+
+impl<N : Real> From<ExpectationMaximizationBuilder<N>> for ExpectationMaximization<N> {
+    fn from(builder : ExpectationMaximizationBuilder<N>) -> ExpectationMaximization<N> {
+        builder.em
+    }
+}
+
+impl<N : Real> ExpectationMaximization<N> {
+
+    /// `measurements[k]` is y_k+1, measured after the prediction with `inputs[k]`
+    pub fn identify(&self, inputs : &[InputVector<N>], measurements : &[MeasurementVector<N>])
+                    -> IdentifiedModel<N> {
+        assert_eq!(inputs.len(), measurements.len());
+        assert!(!measurements.is_empty());
+
+        let mut model = IdentifiedModel {
+            mat_f : self.mat_f.clone(),
+            mat_h : self.mat_h.clone(),
+            mat_q : self.mat_q.clone(),
+            mat_c : self.mat_c.clone(),
+            mat_r : self.mat_r.clone(),
+            log_likelihoods : Vec::with_capacity(self.max_iterations + 1),
+            converged : false,
+        };
+
+        for iteration in 0..(self.max_iterations + 1) {
+            let (log_likelihood, smoothed) = self.expectation(&model, inputs, measurements);
+            let converged = match model.log_likelihoods.last() {
+                Some(&previous) => (log_likelihood - previous).abs()
+                                   <= self.tolerance * log_likelihood.abs().max(N::one()),
+                None => false,
+            };
+            model.log_likelihoods.push(log_likelihood);
+            if converged {
+                model.converged = true;
+                break;
+            }
+            if iteration < self.max_iterations {
+                self.maximization(&mut model, &smoothed, inputs, measurements);
+            }
+        }
+        model
+    }
+
+    /// Runs filter and smoother, returns the log-likelihood and the smoothed states
+    fn expectation(&self,
+                   model : &IdentifiedModel<N>,
+                   inputs : &[InputVector<N>],
+                   measurements : &[MeasurementVector<N>])
+                   -> (N, Vec<SmoothedState<N>>) {
+        let filter : KalmanFilter<N> = KalmanFilterBuilder
+            ::with_numstates_and_numinputs(self.num_states, self.num_inputs)
+            .with_system_matrix(model.mat_f.clone())
+            .with_input_matrix(model.mat_h.clone())
+            .with_system_noise_variances(model.mat_q.clone())
+            .with_initial_state(self.vec_x_init.clone(), self.mat_p_init.clone())
+            .with_covariance_update(CovarianceUpdate::Joseph)
+            .into();
+        let mut smoother = RauchTungStriebelSmoother::from(filter);
+
+        let mut log_likelihood = N::zero();
+        for (u, vec_y) in inputs.iter().zip(measurements.iter()) {
+            smoother.predict(u);
+            log_likelihood += smoother.measure_vector(vec_y.clone(),
+                                                      model.mat_c.clone(),
+                                                      model.mat_r.clone())
+                                      .log_likelihood();
+        }
+        (log_likelihood, smoother.smooth())
+    }
+
+    fn maximization(&self,
+                    model : &mut IdentifiedModel<N>,
+                    smoothed : &[SmoothedState<N>],
+                    inputs : &[InputVector<N>],
+                    measurements : &[MeasurementVector<N>]) {
+        let n = self.num_states;
+        let p = self.num_inputs;
+        let m = self.mat_c.nrows();
+        let num_steps : N = convert(measurements.len() as f64);
+
+        // SUM E[ x_k x_k^T ] for k = 1 ... K
+        let mut mat_sxx = DMatrix::zeros(n, n);
+        // SUM E[ z_k z_k^T ] and SUM E[ x_k+1 z_k^T ] for k = 0 ... K-1
+        let mut mat_szz = DMatrix::zeros(n + p, n + p);
+        let mut mat_sxz = DMatrix::zeros(n, n + p);
+        // SUM y_k y_k^T and SUM y_k E[ x_k ]^T for k = 1 ... K
+        let mut mat_syy = DMatrix::zeros(m, m);
+        let mut mat_syx = DMatrix::zeros(m, n);
+
+        for k in 0..measurements.len() {
+            let state = &smoothed[k];
+            let next = &smoothed[k + 1];
+            let vec_x = &state.vec_state.0;
+            let vec_x_next = &next.vec_state.0;
+            let vec_u = &inputs[k].0;
+            let vec_y = &measurements[k].0;
+
+            // z_k = [ x_k; u_k ]
+            let mut vec_z = DVector::zeros(n + p);
+            vec_z.rows_mut(0, n).copy_from(vec_x);
+            vec_z.rows_mut(n, p).copy_from(vec_u);
+
+            mat_sxx += vec_x_next * vec_x_next.transpose() + &next.mat_covariances.0;
+            mat_szz += &vec_z * vec_z.transpose();
+            mat_szz.slice_mut((0, 0), (n, n)).add_assign(&state.mat_covariances.0);
+            mat_sxz += vec_x_next * vec_z.transpose();
+            mat_sxz.columns_mut(0, n).add_assign(state.mat_cross_covariances.as_ref().unwrap());
+            mat_syy += vec_y * vec_y.transpose();
+            mat_syx += vec_y * vec_x_next.transpose();
+        }
+
+        let mat_szz_xx = mat_szz.slice((0, 0), (n, n)).clone_owned();
+        let mat_szz_xu = mat_szz.slice((0, n), (n, p)).clone_owned();
+        let mat_szz_uu = mat_szz.slice((n, n), (p, p)).clone_owned();
+        let mat_sxz_x = mat_sxz.slice((0, 0), (n, n)).clone_owned();
+        let mat_sxz_u = mat_sxz.slice((0, n), (n, p)).clone_owned();
+
+        // Each solves  A X^T = B^T  for X = B A^-1 with symmetric A
+        match (self.estimate_system_matrix, self.estimate_input_matrix) {
+            (true, true) => {
+                let mat_fh = solve_right(&mat_sxz, &mat_szz);
+                model.mat_f.0 = mat_fh.slice((0, 0), (n, n)).clone_owned();
+                model.mat_h.0 = mat_fh.slice((0, n), (n, p)).clone_owned();
+            },
+            (true, false) => {
+                let mat_b = mat_sxz_x - &model.mat_h.0 * mat_szz_xu.transpose();
+                model.mat_f.0 = solve_right(&mat_b, &mat_szz_xx);
+            },
+            (false, true) => {
+                let mat_b = mat_sxz_u - &model.mat_f.0 * &mat_szz_xu;
+                model.mat_h.0 = solve_right(&mat_b, &mat_szz_uu);
+            },
+            (false, false) => {},
+        }
+
+        // Q = 1/K ( SUM E[ x_k+1 x_k+1^T ] - M Sxz^T - Sxz M^T + M Szz M^T ),  M = [F H]
+        let mut mat_fh = DMatrix::zeros(n, n + p);
+        mat_fh.slice_mut((0, 0), (n, n)).copy_from(&model.mat_f.0);
+        mat_fh.slice_mut((0, n), (n, p)).copy_from(&model.mat_h.0);
+        let mat_fh_sxz = &mat_fh * mat_sxz.transpose();
+        let mat_q = (&mat_sxx - &mat_fh_sxz - mat_fh_sxz.transpose()
+                     + &mat_fh * &mat_szz * mat_fh.transpose()) / num_steps;
+        model.mat_q.0 = (&mat_q + mat_q.transpose()) * convert::<f64, N>(0.5);
+
+        if self.estimate_measurement_matrix {
+            model.mat_c.0 = solve_right(&mat_syx, &mat_sxx);
+        }
+
+        // R = 1/K ( SUM y_k y_k^T - C Syx^T - Syx C^T + C SUM E[ x_k x_k^T ] C^T )
+        let mat_c = &model.mat_c.0;
+        let mat_c_sxy = mat_c * mat_syx.transpose();
+        let mat_r = (&mat_syy - &mat_c_sxy - mat_c_sxy.transpose()
+                     + mat_c * &mat_sxx * mat_c.transpose()) / num_steps;
+        model.mat_r.0 = (&mat_r + mat_r.transpose()) * convert::<f64, N>(0.5);
+    }
+}
+
+/// B A^-1 for symmetric positive definite A
+fn solve_right<N : Real>(mat_b : &DMatrix<N>, mat_a : &DMatrix<N>) -> DMatrix<N> {
+    mat_a.clone()
+         .cholesky()
+         .expect("Second moment matrix is not positive definite, the data is not exciting enough")
+         .solve(&mat_b.transpose())
+         .transpose()
+}
@@ -0,0 +1,414 @@
+use std::convert::From;
+
+use alga::general::Real;
+use na::{DMatrix, DVector, convert};
+use rand::{StdRng, SeedableRng};
+use rand::distributions::{Normal, IndependentSample};
+
+use ekf::{StateFunction, OutputFunction};
+use srkf::square_root;
+use nt::{SystemNoiseVarianceMatrix, SystemNoiseSquareRootMatrix, StateVector, CovarianceMatrix, InputVector, Measurement,
+         MeasurementNoiseVariance, MeasurementVector, MeasurementNoiseCovarianceMatrix,
+         EnsembleMatrix};
+
+
+/// Localization of the sample covariances before the gain is formed.
+///
+/// Called with P H^T (num_states x num_measurements) and H P H^T
+/// (num_measurements x num_measurements), e.g. to multiply them elementwise with a
+/// distance-based taper.
+pub type Localization<N> = Box<Fn(&mut DMatrix<N>, &mut DMatrix<N>)>;
+
+/// Analysis scheme of the measurement update
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnsembleAnalysis {
+    /// Stochastic EnKF: every member is updated with its own perturbed measurement
+    Stochastic,
+    /// Ensemble transform Kalman filter (deterministic square root analysis, Hunt et al. 2007).
+    /// Does not support localization.
+    Transform,
+}
+
+/// Ensemble Kalman Filter
+///
+/// Represents the state distribution by an ensemble of m state vectors (the columns of an
+/// `EnsembleMatrix`). Mean and covariance are never formed in the filter itself, so predict and
+/// update cost O(n m) per member instead of O(n^2). This requires the system noise as a low-rank
+/// square root (see `with_system_noise_square_root()`), since sampling from a full-rank n x n
+/// square root of Q costs O(n^2) per member. Sampling the initial ensemble from x and P is O(n^3)
+/// once, `with_initial_ensemble()` avoids it.
+pub struct EnsembleKalmanFilter<N : Real>
+{
+    num_states : usize,
+    num_inputs : usize,
+    fn_f : StateFunction<N>,
+    fn_c : OutputFunction<N>,
+    mat_q_sqrt : SystemNoiseSquareRootMatrix<N>,
+    analysis : EnsembleAnalysis,
+    inflation : N,
+    localization : Option<Localization<N>>,
+    ensemble : EnsembleMatrix<N>,
+    rng : StdRng,
+}
+
+pub struct EnsembleKalmanFilterBuilder<N : Real>
+{
+    num_states : usize,
+    num_inputs : usize,
+    fn_f : StateFunction<N>,
+    fn_c : OutputFunction<N>,
+    mat_q_sqrt : SystemNoiseSquareRootMatrix<N>,
+    analysis : EnsembleAnalysis,
+    inflation : N,
+    localization : Option<Localization<N>>,
+    ensemble_size : usize,
+    seed : usize,
+    vec_state : StateVector<N>,
+    mat_p : CovarianceMatrix<N>,
+    ensemble : Option<EnsembleMatrix<N>>,
+}
+
+impl<N : Real> EnsembleKalmanFilterBuilder<N> {
+    /// Defaults to `f(x, u) = x`, `c(x) = x`, `EnsembleAnalysis::Stochastic`, no inflation and
+    /// no localization, 100 members and seed 0.
+    pub fn with_numstates_and_numinputs(num_states : usize, num_inputs : usize) -> EnsembleKalmanFilterBuilder<N> {
+        EnsembleKalmanFilterBuilder {
+            num_states : num_states,
+            num_inputs : num_inputs,
+            fn_f : Box::new(|x, _| x.clone()),
+            fn_c : Box::new(|x| MeasurementVector(x.0.clone())),
+            mat_q_sqrt : SystemNoiseSquareRootMatrix(DMatrix::zeros(num_states, 0)),
+            analysis : EnsembleAnalysis::Stochastic,
+            inflation : N::one(),
+            localization : None,
+            ensemble_size : 100,
+            seed : 0,
+            vec_state : StateVector(DVector::zeros(num_states)),
+            mat_p : CovarianceMatrix(DMatrix::identity(num_states, num_states)),
+            ensemble : None,
+        }
+    }
+
+    pub fn with_state_function<F>(mut self, fn_f : F) -> Self
+        where F : Fn(&StateVector<N>, &InputVector<N>) -> StateVector<N> + 'static {
+        self.fn_f = Box::new(fn_f);
+        self
+    }
+
+    pub fn with_output_function<C>(mut self, fn_c : C) -> Self
+        where C : Fn(&StateVector<N>) -> MeasurementVector<N> + 'static {
+        self.fn_c = Box::new(fn_c);
+        self
+    }
+
+    /// Q is factorized into an n x n square root, see `with_system_noise_square_root()` for
+    /// large n.
+    pub fn with_system_noise_variances(mut self, mat_q : SystemNoiseVarianceMatrix<N>) -> Self {
+        assert_eq!(self.num_states, mat_q.ncols());
+        assert_eq!(self.num_states, mat_q.nrows());
+        self.mat_q_sqrt = SystemNoiseSquareRootMatrix(square_root(&mat_q.0));
+        self
+    }
+
+    /// Q = A A^T with A of size n x r, e.g. the r dominant modes of the system noise. Sampling
+    /// the noise costs O(n r) per member.
+    pub fn with_system_noise_square_root(mut self, mat_q_sqrt : SystemNoiseSquareRootMatrix<N>) -> Self {
+        assert_eq!(self.num_states, mat_q_sqrt.nrows());
+        self.mat_q_sqrt = mat_q_sqrt;
+        self
+    }
+
+    pub fn with_analysis(mut self, analysis : EnsembleAnalysis) -> Self {
+        self.analysis = analysis;
+        self
+    }
+
+    /// Multiplicative inflation: after each prediction the deviations of the members from the
+    /// ensemble mean are multiplied by `inflation` (>= 1), i.e. P by `inflation^2`.
+    pub fn with_inflation(mut self, inflation : N) -> Self {
+        assert!(inflation >= N::one(), "Inflation factor must be at least 1");
+        self.inflation = inflation;
+        self
+    }
+
+    /// Only supported by `EnsembleAnalysis::Stochastic`.
+    pub fn with_localization<L>(mut self, localization : L) -> Self
+        where L : Fn(&mut DMatrix<N>, &mut DMatrix<N>) + 'static {
+        self.localization = Some(Box::new(localization));
+        self
+    }
+
+    /// Number of members m, used when sampling the initial ensemble
+    pub fn with_ensemble_size(mut self, ensemble_size : usize) -> Self {
+        assert!(ensemble_size >= 2);
+        self.ensemble_size = ensemble_size;
+        self
+    }
+
+    /// Seed of the random number generator. Filters built with the same seed and fed with the
+    /// same data produce identical ensembles.
+    pub fn with_seed(mut self, seed : usize) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// The initial ensemble is sampled from N(x, P)
+    pub fn with_initial_state(mut self, vec_state : StateVector<N>, mat_covariances : CovarianceMatrix<N>) -> Self {
+        assert_eq!(self.num_states, vec_state.len());
+        assert_eq!(self.num_states, mat_covariances.ncols());
+        assert_eq!(self.num_states, mat_covariances.nrows());
+        self.vec_state = vec_state;
+        self.mat_p = mat_covariances;
+        self.ensemble = None;
+        self
+    }
+
+    /// Uses the given members (one per column) instead of sampling them
+    pub fn with_initial_ensemble(mut self, ensemble : EnsembleMatrix<N>) -> Self {
+        assert_eq!(self.num_states, ensemble.nrows());
+        assert!(ensemble.ncols() >= 2);
+        self.ensemble_size = ensemble.ncols();
+        self.ensemble = Some(ensemble);
+        self
+    }
+}
+
+impl<N : Real> From<EnsembleKalmanFilterBuilder<N>> for EnsembleKalmanFilter<N> {
+    fn from(builder : EnsembleKalmanFilterBuilder<N>) -> EnsembleKalmanFilter<N> {
+        assert!(builder.localization.is_none() || builder.analysis == EnsembleAnalysis::Stochastic,
+                "Localization is only supported by the stochastic analysis");
+        let mut rng = StdRng::from_seed(&[builder.seed][..]);
+        let ensemble = match builder.ensemble {
+            Some(ensemble) => ensemble,
+            None => {
+                let mut mat_x = sample_normal(&mut rng, &square_root(&builder.mat_p.0),
+                                              builder.ensemble_size);
+                for i in 0..builder.ensemble_size {
+                    let vec_x_i = mat_x.column(i) + &builder.vec_state.0;
+                    mat_x.set_column(i, &vec_x_i);
+                }
+                EnsembleMatrix(mat_x)
+            }
+        };
+        EnsembleKalmanFilter {
+            num_states : builder.num_states,
+            num_inputs : builder.num_inputs,
+            fn_f : builder.fn_f,
+            fn_c : builder.fn_c,
+            mat_q_sqrt : builder.mat_q_sqrt,
+            analysis : builder.analysis,
+            inflation : builder.inflation,
+            localization : builder.localization,
+            ensemble : ensemble,
+            rng : rng,
+        }
+    }
+}
+
+impl<N : Real> EnsembleKalmanFilter<N> {
+
+    /// The members, one per column
+    pub fn get_ensemble(&self) -> &EnsembleMatrix<N> {
+        &self.ensemble
+    }
+
+    pub fn get_ensemble_size(&self) -> usize {
+        self.ensemble.ncols()
+    }
+
+    /// Ensemble mean
+    pub fn get_mean(&self) -> StateVector<N> {
+        StateVector(column_mean(&self.ensemble.0))
+    }
+
+    /// Sample covariance of the ensemble. Forms a dense n x n matrix.
+    pub fn get_covariances(&self) -> CovarianceMatrix<N> {
+        let mat_a = scaled_anomalies(&self.ensemble.0);
+        CovarianceMatrix(&mat_a * mat_a.transpose())
+    }
+
+    /// Propagates every member through f() and adds a sample of the system noise
+    pub fn predict<'a>(&'a mut self, u : &InputVector<N>) -> &'a EnsembleMatrix<N> {
+        assert_eq!(self.num_inputs, u.0.len());
+        let m = self.ensemble.ncols();
+        let mat_w = sample_normal(&mut self.rng, &self.mat_q_sqrt.0, m);
+        for i in 0..m {
+            let vec_x = (self.fn_f)(&StateVector(self.ensemble.column(i).clone_owned()), u);
+            assert_eq!(self.num_states, vec_x.len());
+            let vec_x = vec_x.0 + mat_w.column(i);
+            self.ensemble.set_column(i, &vec_x);
+        }
+
+        if self.inflation != N::one() {
+            let vec_mean = column_mean(&self.ensemble.0);
+            for i in 0..m {
+                let vec_x = (self.ensemble.column(i) - &vec_mean) * self.inflation + &vec_mean;
+                self.ensemble.set_column(i, &vec_x);
+            }
+        }
+
+        &self.ensemble
+    }
+
+    /// Processes the measurement of the single output `index` of `c(x)`.
+    pub fn measure<'a>(&'a mut self,
+                       y : Measurement<N>,
+                       index : usize,
+                       r : MeasurementNoiseVariance<N>)
+                    -> &'a EnsembleMatrix<N> {
+        self.update(&DVector::from_element(1, y.0), &DMatrix::from_element(1, 1, r.0), Some(index));
+        &self.ensemble
+    }
+
+    /// Processes the measurement of all outputs of `c(x)` at once.
+    pub fn measure_vector<'a>(&'a mut self,
+                              vec_y : MeasurementVector<N>,
+                              mat_r : MeasurementNoiseCovarianceMatrix<N>)
+                           -> &'a EnsembleMatrix<N> {
+        self.update(&vec_y.0, &mat_r.0, None);
+        &self.ensemble
+    }
+
+    fn update(&mut self, vec_y : &DVector<N>, mat_r : &DMatrix<N>, index : Option<usize>) {
+        let num_measurements = vec_y.len();
+        assert_eq!(num_measurements, mat_r.nrows());
+        assert_eq!(num_measurements, mat_r.ncols());
+        let m = self.ensemble.ncols();
+
+        // Predicted measurements of all members, one per column
+        let mut mat_gamma = DMatrix::zeros(num_measurements, m);
+        for i in 0..m {
+            let vec_c_x = (self.fn_c)(&StateVector(self.ensemble.column(i).clone_owned()));
+            match index {
+                Some(index) => mat_gamma[(0, i)] = vec_c_x[index],
+                None => {
+                    assert_eq!(num_measurements, vec_c_x.len());
+                    mat_gamma.set_column(i, &vec_c_x.0);
+                }
+            }
+        }
+
+        match self.analysis {
+            EnsembleAnalysis::Stochastic => self.update_stochastic(vec_y, mat_r, &mat_gamma),
+            EnsembleAnalysis::Transform => self.update_transform(vec_y, mat_r, &mat_gamma),
+        }
+    }
+
+    fn update_stochastic(&mut self, vec_y : &DVector<N>, mat_r : &DMatrix<N>, mat_gamma : &DMatrix<N>) {
+        let m = self.ensemble.ncols();
+
+        // A = ( X - x_mean ) / sqrt(m - 1),  HA = ( Gamma - y_mean ) / sqrt(m - 1)
+        let mat_a = scaled_anomalies(&self.ensemble.0);
+        let mat_ha = scaled_anomalies(mat_gamma);
+
+        // P H^T = A HA^T,  H P H^T = HA HA^T
+        let mut mat_pht = &mat_a * mat_ha.transpose();
+        let mut mat_hpht = &mat_ha * mat_ha.transpose();
+        if let Some(ref localization) = self.localization {
+            localization(&mut mat_pht, &mut mat_hpht);
+        }
+
+        // K = P H^T S^-1, calculated as solution of  S K^T = H P
+        let mat_s = mat_hpht + mat_r;
+        let mat_k = mat_s.cholesky()
+                         .expect("Innovation covariance S is not positive definite")
+                         .solve(&mat_pht.transpose())
+                         .transpose();
+
+        // Perturbed measurements y_i = y + e_i with e_i ~ N(0, R), centered to avoid a bias of
+        // the analysis mean
+        let mut mat_e = sample_normal(&mut self.rng, &square_root(mat_r), m);
+        let vec_e_mean = column_mean(&mat_e);
+        for i in 0..m {
+            let vec_e_i = mat_e.column(i) - &vec_e_mean;
+            mat_e.set_column(i, &vec_e_i);
+        }
+
+        // x_i = x_i + K ( y + e_i - c(x_i) )
+        for i in 0..m {
+            let vec_residual = vec_y + mat_e.column(i) - mat_gamma.column(i);
+            let vec_x_i = self.ensemble.column(i) + &mat_k * vec_residual;
+            self.ensemble.set_column(i, &vec_x_i);
+        }
+    }
+
+    fn update_transform(&mut self, vec_y : &DVector<N>, mat_r : &DMatrix<N>, mat_gamma : &DMatrix<N>) {
+        let m = self.ensemble.ncols();
+        let m_1 : N = convert((m - 1) as f64);
+
+        let vec_x_mean = column_mean(&self.ensemble.0);
+        let vec_y_mean = column_mean(mat_gamma);
+        let mut mat_xb = self.ensemble.0.clone();
+        let mut mat_yb = mat_gamma.clone();
+        for i in 0..m {
+            let vec_dx = mat_xb.column(i) - &vec_x_mean;
+            mat_xb.set_column(i, &vec_dx);
+            let vec_dy = mat_yb.column(i) - &vec_y_mean;
+            mat_yb.set_column(i, &vec_dy);
+        }
+
+        // C = Yb^T R^-1
+        let mat_c = mat_r.clone()
+                         .cholesky()
+                         .expect("Measurement noise covariance R is not positive definite")
+                         .solve(&mat_yb)
+                         .transpose();
+
+        // ( m - 1 ) I + C Yb = V L V^T,  P_a = V L^-1 V^T  (in ensemble space)
+        let mat_m = DMatrix::identity(m, m) * m_1 + &mat_c * &mat_yb;
+        let eigen = ((&mat_m + mat_m.transpose()) * convert::<f64, N>(0.5)).symmetric_eigen();
+        let mat_v = eigen.eigenvectors;
+        let vec_l = eigen.eigenvalues;
+        let mat_pa = &mat_v * DMatrix::from_diagonal(&vec_l.map(|l| l.recip())) * mat_v.transpose();
+
+        // w_mean = P_a C ( y - y_mean ),  W = ( (m - 1) P_a )^1/2  (symmetric square root)
+        let vec_w_mean = &mat_pa * (&mat_c * (vec_y - vec_y_mean));
+        let mut mat_w = &mat_v * DMatrix::from_diagonal(&vec_l.map(|l| (m_1 / l).sqrt()))
+                        * mat_v.transpose();
+        for i in 0..m {
+            let vec_w_i = mat_w.column(i) + &vec_w_mean;
+            mat_w.set_column(i, &vec_w_i);
+        }
+
+        // x_i = x_mean + Xb w_i
+        let mut mat_x = mat_xb * mat_w;
+        for i in 0..m {
+            let vec_x_i = mat_x.column(i) + &vec_x_mean;
+            mat_x.set_column(i, &vec_x_i);
+        }
+        self.ensemble.0 = mat_x;
+    }
+}
+
+/// Deviations from the column mean, divided by sqrt(m - 1)
+fn scaled_anomalies<N : Real>(mat_points : &DMatrix<N>) -> DMatrix<N> {
+    let m = mat_points.ncols();
+    let scale = convert::<f64, N>((m - 1) as f64).sqrt().recip();
+    let vec_mean = column_mean(mat_points);
+    let mut mat_a = mat_points.clone();
+    for i in 0..m {
+        let vec_a_i = (mat_a.column(i) - &vec_mean) * scale;
+        mat_a.set_column(i, &vec_a_i);
+    }
+    mat_a
+}
+
+fn column_mean<N : Real>(mat_points : &DMatrix<N>) -> DVector<N> {
+    let mut vec_mean = DVector::zeros(mat_points.nrows());
+    for i in 0..mat_points.ncols() {
+        vec_mean += mat_points.column(i);
+    }
+    vec_mean / convert::<f64, N>(mat_points.ncols() as f64)
+}
+
+/// Returns `count` samples of N(0, A A^T), one per column
+fn sample_normal<N : Real>(rng : &mut StdRng, mat_sqrt : &DMatrix<N>, count : usize) -> DMatrix<N> {
+    let normal = Normal::new(0.0, 1.0);
+    let mut mat_z = DMatrix::zeros(mat_sqrt.ncols(), count);
+    for i in 0..mat_z.nrows() {
+        for j in 0..count {
+            mat_z[(i, j)] = convert(normal.ind_sample(rng));
+        }
+    }
+    mat_sqrt * mat_z
+}
@@ -0,0 +1,185 @@
+use std::collections::VecDeque;
+
+use alga::general::Real;
+use na::{DMatrix, DVector};
+
+use kf::{KalmanFilter, KalmanFilterBuilder, BorrowedSystemState, MeasurementUpdate,
+         GatingDecision, Innovation, augment};
+use nt::{StateVector, CovarianceMatrix, InputVector, Measurement, MeasurementMatrixRow,
+         MeasurementNoiseVariance, MeasurementVector, MeasurementMatrix,
+         MeasurementNoiseCovarianceMatrix};
+
+
+/// Fixed-lag smoother
+///
+/// Wraps a `KalmanFilter` and additionally estimates the last L states x_k-1|k ... x_k-L|k.
+/// This is the Kalman filter of the augmented state [ x_k, x_k-1, ..., x_k-L ], but only the
+/// blocks needed for the smoothed estimates are stored: the lagged states, their covariances
+/// P_k-i|k and the cross covariances P_k,k-i with the current state.
+///
+/// During `predict()` the lagged states are shifted (x_k becomes x_k-1 and so on) and the cross
+/// covariances propagate with P_k+1,k-i = F P_k,k-i. A measurement with innovation v and
+/// innovation covariance S updates every lagged state with the gain
+///
+/// ```math
+///     K_i      = P_k,k-i^T C^T S^-1
+///     x_k-i    = x_k-i + K_i v
+///     P_k-i    = P_k-i - K_i S K_i^T
+///     P_k,k-i  = ( I - K C ) P_k,k-i
+/// ```
+///
+/// Consider states of the filter are not updated in the lagged states either (zero rows in
+/// K_i), and the covariances are then updated in Joseph form, as in the filter.
+///
+/// State constraints of the filter are then enforced on every lagged state jointly with the
+/// current state, i.e. on [ x_k x_k-i ] with the cross covariance P_k,k-i, like the stochastic
+/// cloning filter does with its clone. The current state itself keeps the estimate of the filter,
+/// which enforces the constraints on x_k alone (see `StateConstraints`).
+pub struct FixedLagSmoother<N : Real>
+{
+    filter : KalmanFilter<N>,
+    lag : usize,
+    /// x_k-1 first
+    lagged : VecDeque<LaggedState<N>>,
+}
+
+/// A past state together with its cross covariance to the current state
+pub(crate) struct LaggedState<N : Real>
+{
+    pub(crate) vec_state : StateVector<N>,
+    pub(crate) mat_p : CovarianceMatrix<N>,
+    /// P_k,k-i, covariance between current state (rows) and lagged state (columns)
+    mat_p_cross : DMatrix<N>,
+}
+
+impl<N : Real> LaggedState<N> {
+    /// Starts lagging the current state of `filter`
+    pub(crate) fn new(filter : &KalmanFilter<N>) -> LaggedState<N> {
+        let state = filter.get_state();
+        LaggedState {
+            vec_state : state.vec_state.clone(),
+            mat_p : state.mat_covariances.clone(),
+            mat_p_cross : state.mat_covariances.0.clone(),
+        }
+    }
+
+    /// P_k+1,k-i = F P_k,k-i, has to be called before the filter predicts
+    pub(crate) fn predict(&mut self, filter : &KalmanFilter<N>) {
+        self.mat_p_cross = &filter.get_system_matrix().0 * &self.mat_p_cross;
+    }
+}
+
+impl<N : Real> FixedLagSmoother<N> {
+    pub fn new(filter : KalmanFilter<N>, lag : usize) -> FixedLagSmoother<N> {
+        assert!(lag >= 1);
+        FixedLagSmoother {
+            filter : filter,
+            lag : lag,
+            lagged : VecDeque::with_capacity(lag),
+        }
+    }
+
+    pub fn from_builder(builder : KalmanFilterBuilder<N>, lag : usize) -> FixedLagSmoother<N> {
+        FixedLagSmoother::new(KalmanFilter::from(builder), lag)
+    }
+
+    pub fn get_lag(&self) -> usize {
+        self.lag
+    }
+
+    /// The filtered state x_k|k
+    pub fn get_state<'a>(&'a self) -> BorrowedSystemState<'a, N> {
+        self.filter.get_state()
+    }
+
+    /// x_k-i|k and P_k-i|k for 0 <= i <= L, or `None` if fewer than i timesteps have passed.
+    /// i = 0 is the filtered state.
+    pub fn get_smoothed_state<'a>(&'a self, i : usize) -> Option<BorrowedSystemState<'a, N>> {
+        assert!(i <= self.lag);
+        if i == 0 {
+            return Some(self.filter.get_state());
+        }
+        self.lagged.get(i - 1).map(|lagged| {
+            BorrowedSystemState {
+                vec_state : &lagged.vec_state,
+                mat_covariances : &lagged.mat_p,
+            }
+        })
+    }
+
+    /// x_k-L|k and P_k-L|k, or `None` during the first L timesteps
+    pub fn get_delayed_state<'a>(&'a self) -> Option<BorrowedSystemState<'a, N>> {
+        self.get_smoothed_state(self.lag)
+    }
+
+    pub fn predict<'a>(&'a mut self, u : &InputVector<N>) -> BorrowedSystemState<'a, N> {
+        if self.lagged.len() == self.lag {
+            self.lagged.pop_back();
+        }
+        self.lagged.push_front(LaggedState::new(&self.filter));
+
+        for lagged in self.lagged.iter_mut() {
+            lagged.predict(&self.filter);
+        }
+
+        self.filter.predict(u)
+    }
+
+    pub fn measure<'a>(&'a mut self,
+                       y : Measurement<N>,
+                       rvec_c : MeasurementMatrixRow<N>,
+                       r : MeasurementNoiseVariance<N>)
+                    -> MeasurementUpdate<'a, N> {
+        let mat_c = DMatrix::from_iterator(1, rvec_c.0.len(), rvec_c.0.iter().cloned());
+        self.measure_vector(MeasurementVector(DVector::from_element(1, y.0)),
+                            MeasurementMatrix(mat_c),
+                            MeasurementNoiseCovarianceMatrix(DMatrix::from_element(1, 1, r.0)))
+    }
+
+    pub fn measure_vector<'a>(&'a mut self,
+                              vec_y : MeasurementVector<N>,
+                              mat_c : MeasurementMatrix<N>,
+                              mat_r : MeasurementNoiseCovarianceMatrix<N>)
+                           -> MeasurementUpdate<'a, N> {
+        let (vec_x, mat_p) = {
+            let state = self.filter.get_state();
+            (state.vec_state.0.clone(), state.mat_covariances.0.clone())
+        };
+        let (mat_c_copy, mat_r_copy) = (mat_c.0.clone(), mat_r.0.clone());
+        let innovation = self.filter.update_state(vec_y, mat_c, mat_r);
+        update_lagged(&self.filter, self.lagged.iter_mut(), &innovation, &vec_x, &mat_p,
+                      &mat_c_copy, &mat_r_copy);
+        self.filter.measurement_update(innovation)
+    }
+}
+
+/// Applies the measurement update of the current state with the measurement matrix C to the
+/// lagged states, followed by the state constraints of `filter` on each [ x_k x_k-i ]. `vec_x`
+/// and `mat_p` are the current state before the update.
+pub(crate) fn update_lagged<'b, N, I>(filter : &KalmanFilter<N>,
+                                      lagged : I,
+                                      innovation : &Innovation<N>,
+                                      vec_x : &DVector<N>,
+                                      mat_p : &DMatrix<N>,
+                                      mat_c : &DMatrix<N>,
+                                      mat_r : &DMatrix<N>)
+    where N : Real, I : Iterator<Item = &'b mut LaggedState<N>> {
+    if innovation.gating == GatingDecision::Rejected {
+        return;
+    }
+    let n = mat_c.ncols();
+    // [ C 0 ] for the augmented state [ x_k x_k-i ]
+    let mut mat_c_a = DMatrix::zeros(mat_c.nrows(), 2 * n);
+    mat_c_a.columns_mut(0, n).copy_from(mat_c);
+
+    for lagged in lagged {
+        let (mut vec_x_a, mut mat_p_a) = augment(vec_x, mat_p, &lagged.vec_state.0,
+                                                 &lagged.mat_p.0, &lagged.mat_p_cross);
+        filter.update_augmented(&mut vec_x_a, &mut mat_p_a, &mat_c_a, mat_r, innovation);
+        filter.apply_state_constraints(&mut vec_x_a, &mut mat_p_a);
+
+        lagged.vec_state.0 = vec_x_a.rows(n, n).clone_owned();
+        lagged.mat_p.0 = mat_p_a.slice((n, n), (n, n)).clone_owned();
+        lagged.mat_p_cross = mat_p_a.slice((0, n), (n, n)).clone_owned();
+    }
+}
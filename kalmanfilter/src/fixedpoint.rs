@@ -0,0 +1,94 @@
+use std::convert::From;
+use std::iter;
+
+use alga::general::Real;
+use na::{DMatrix, DVector};
+
+use kf::{KalmanFilter, KalmanFilterBuilder, BorrowedSystemState, MeasurementUpdate};
+use fixedlag::{LaggedState, update_lagged};
+use nt::{InputVector, Measurement, MeasurementMatrixRow, MeasurementNoiseVariance,
+         MeasurementVector, MeasurementMatrix, MeasurementNoiseCovarianceMatrix};
+
+
+/// Fixed-point smoother
+///
+/// Wraps a `KalmanFilter` and refines the estimate x_j|k, P_j|k of one fixed timestep j with
+/// every measurement at k >= j. Initially j is the initial state of the filter (e.g. for the
+/// estimation of initial conditions), `fix_current_state()` moves j to the current timestep.
+///
+/// Same as the `FixedLagSmoother`, but the fixed point is not shifted during `predict()`: only
+/// its cross covariance with the current state propagates with P_k+1,j = F P_k,j.
+pub struct FixedPointSmoother<N : Real>
+{
+    filter : KalmanFilter<N>,
+    fixed_point : LaggedState<N>,
+}
+
+impl<N : Real> From<KalmanFilter<N>> for FixedPointSmoother<N> {
+    fn from(filter : KalmanFilter<N>) -> FixedPointSmoother<N> {
+        let fixed_point = LaggedState::new(&filter);
+        FixedPointSmoother {
+            filter : filter,
+            fixed_point : fixed_point,
+        }
+    }
+}
+
+impl<N : Real> From<KalmanFilterBuilder<N>> for FixedPointSmoother<N> {
+    fn from(builder : KalmanFilterBuilder<N>) -> FixedPointSmoother<N> {
+        KalmanFilter::from(builder).into()
+    }
+}
+
+impl<N : Real> FixedPointSmoother<N> {
+
+    /// Makes the current timestep the fixed point. The previous fixed point is dropped.
+    pub fn fix_current_state(&mut self) {
+        self.fixed_point = LaggedState::new(&self.filter);
+    }
+
+    /// The filtered state x_k|k
+    pub fn get_state<'a>(&'a self) -> BorrowedSystemState<'a, N> {
+        self.filter.get_state()
+    }
+
+    /// The smoothed state x_j|k of the fixed point
+    pub fn get_fixed_point_state<'a>(&'a self) -> BorrowedSystemState<'a, N> {
+        BorrowedSystemState {
+            vec_state : &self.fixed_point.vec_state,
+            mat_covariances : &self.fixed_point.mat_p,
+        }
+    }
+
+    pub fn predict<'a>(&'a mut self, u : &InputVector<N>) -> BorrowedSystemState<'a, N> {
+        self.fixed_point.predict(&self.filter);
+        self.filter.predict(u)
+    }
+
+    pub fn measure<'a>(&'a mut self,
+                       y : Measurement<N>,
+                       rvec_c : MeasurementMatrixRow<N>,
+                       r : MeasurementNoiseVariance<N>)
+                    -> MeasurementUpdate<'a, N> {
+        let mat_c = DMatrix::from_iterator(1, rvec_c.0.len(), rvec_c.0.iter().cloned());
+        self.measure_vector(MeasurementVector(DVector::from_element(1, y.0)),
+                            MeasurementMatrix(mat_c),
+                            MeasurementNoiseCovarianceMatrix(DMatrix::from_element(1, 1, r.0)))
+    }
+
+    pub fn measure_vector<'a>(&'a mut self,
+                              vec_y : MeasurementVector<N>,
+                              mat_c : MeasurementMatrix<N>,
+                              mat_r : MeasurementNoiseCovarianceMatrix<N>)
+                           -> MeasurementUpdate<'a, N> {
+        let (vec_x, mat_p) = {
+            let state = self.filter.get_state();
+            (state.vec_state.0.clone(), state.mat_covariances.0.clone())
+        };
+        let (mat_c_copy, mat_r_copy) = (mat_c.0.clone(), mat_r.0.clone());
+        let innovation = self.filter.update_state(vec_y, mat_c, mat_r);
+        update_lagged(&self.filter, iter::once(&mut self.fixed_point), &innovation, &vec_x, &mat_p,
+                      &mat_c_copy, &mat_r_copy);
+        self.filter.measurement_update(innovation)
+    }
+}
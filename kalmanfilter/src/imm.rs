@@ -0,0 +1,298 @@
+use std::convert::From;
+
+use alga::general::Real;
+use na::{DMatrix, DVector, convert};
+
+use kf::KalmanFilter;
+use nt::{StateVector, CovarianceMatrix, InputVector, Measurement, MeasurementMatrixRow,
+         MeasurementNoiseVariance, MeasurementVector, MeasurementMatrix,
+         MeasurementNoiseCovarianceMatrix, ModeProbabilities, ModeTransitionMatrix};
+
+
+/// Interacting Multiple Model estimator
+///
+/// Runs one `KalmanFilter` per mode (e.g. per maneuver regime). The mode switches as a Markov
+/// chain with the transition matrix Pi, `Pi[(i, j)]` being the probability of a switch from
+/// mode i to mode j. Before each prediction, the filters are reinitialized with the mixed
+/// estimates
+///
+/// ```math
+///     c_j      = SUM_i Pi_ij mu_i
+///     mu_i|j   = Pi_ij mu_i / c_j
+///     x_0j     = SUM_i mu_i|j x_i
+///     P_0j     = SUM_i mu_i|j ( P_i + (x_i - x_0j) (x_i - x_0j)^T )
+/// ```
+///
+/// and the mode probabilities become mu_j = c_j. Each measurement then multiplies mu_j with the
+/// measurement likelihood of filter j.
+///
+/// Modes may estimate different subsets of the common state x. The mapping x = T_i x_i of
+/// mode i selects the components of x (T_i consists of unit columns). When mixing mode i into
+/// mode j and when combining the estimates, the components that mode i lacks are taken from
+/// the estimate of mode j respectively from the modes that have them.
+pub struct InteractingMultipleModel<N : Real>
+{
+    num_states : usize,
+    modes : Vec<Mode<N>>,
+    mat_transition : ModeTransitionMatrix<N>,
+    mode_probabilities : ModeProbabilities<N>,
+}
+
+struct Mode<N : Real>
+{
+    filter : KalmanFilter<N>,
+    /// x = T x_i
+    mat_t : DMatrix<N>,
+    /// I - T T^T, selects the components of x this mode does not estimate
+    mat_missing : DMatrix<N>,
+}
+
+pub struct InteractingMultipleModelBuilder<N : Real>
+{
+    num_states : usize,
+    modes : Vec<Mode<N>>,
+    mat_transition : Option<ModeTransitionMatrix<N>>,
+    mode_probabilities : Option<ModeProbabilities<N>>,
+}
+
+impl<N : Real> InteractingMultipleModelBuilder<N> {
+    /// Defaults to Pi = I (no mode switches) and equal initial mode probabilities.
+    pub fn with_numstates(num_states : usize) -> InteractingMultipleModelBuilder<N> {
+        InteractingMultipleModelBuilder {
+            num_states : num_states,
+            modes : vec![],
+            mat_transition : None,
+            mode_probabilities : None,
+        }
+    }
+
+    /// Adds a mode that estimates the full common state
+    pub fn with_mode(self, filter : KalmanFilter<N>) -> Self {
+        let num_states = self.num_states;
+        self.with_mapped_mode(filter, DMatrix::identity(num_states, num_states))
+    }
+
+    /// Adds a mode that estimates the subset x_i of the common state x = T x_i
+    pub fn with_mapped_mode(mut self, filter : KalmanFilter<N>, mat_t : DMatrix<N>) -> Self {
+        assert_eq!(self.num_states, mat_t.nrows());
+        assert_eq!(filter.get_num_states(), mat_t.ncols());
+        if let Some(mode) = self.modes.first() {
+            assert_eq!(mode.filter.get_num_inputs(), filter.get_num_inputs());
+        }
+        let mat_missing = DMatrix::identity(self.num_states, self.num_states) - &mat_t * mat_t.transpose();
+        self.modes.push(Mode {
+            filter : filter,
+            mat_t : mat_t,
+            mat_missing : mat_missing,
+        });
+        self
+    }
+
+    /// `mat_transition[(i, j)]` is the probability of a switch from mode i to mode j. The rows
+    /// have to sum up to 1.
+    pub fn with_transition_matrix(mut self, mat_transition : ModeTransitionMatrix<N>) -> Self {
+        self.mat_transition = Some(mat_transition);
+        self
+    }
+
+    pub fn with_mode_probabilities(mut self, mode_probabilities : ModeProbabilities<N>) -> Self {
+        self.mode_probabilities = Some(mode_probabilities);
+        self
+    }
+}
+
+impl<N : Real> From<InteractingMultipleModelBuilder<N>> for InteractingMultipleModel<N> {
+    fn from(builder : InteractingMultipleModelBuilder<N>) -> InteractingMultipleModel<N> {
+        let num_modes = builder.modes.len();
+        assert!(num_modes >= 1);
+        let mat_transition = builder.mat_transition.unwrap_or_else(|| {
+            ModeTransitionMatrix(DMatrix::identity(num_modes, num_modes))
+        });
+        assert_eq!(num_modes, mat_transition.nrows());
+        assert_eq!(num_modes, mat_transition.ncols());
+        let mode_probabilities = builder.mode_probabilities.unwrap_or_else(|| {
+            ModeProbabilities(DVector::from_element(num_modes, convert::<f64, N>(num_modes as f64).recip()))
+        });
+        assert_eq!(num_modes, mode_probabilities.len());
+        InteractingMultipleModel {
+            num_states : builder.num_states,
+            modes : builder.modes,
+            mat_transition : mat_transition,
+            mode_probabilities : mode_probabilities,
+        }
+    }
+}
+
+impl<N : Real> InteractingMultipleModel<N> {
+
+    pub fn get_num_modes(&self) -> usize {
+        self.modes.len()
+    }
+
+    pub fn get_mode_probabilities(&self) -> &ModeProbabilities<N> {
+        &self.mode_probabilities
+    }
+
+    /// The filter of mode i
+    pub fn get_filter(&self, i : usize) -> &KalmanFilter<N> {
+        &self.modes[i].filter
+    }
+
+    /// Combined estimate in the common state space
+    ///
+    /// ```math
+    ///     x = SUM_i mu_i x_i
+    ///     P = SUM_i mu_i ( P_i + (x_i - x) (x_i - x)^T )
+    /// ```
+    pub fn get_state(&self) -> (StateVector<N>, CovarianceMatrix<N>) {
+        let n = self.num_states;
+
+        // First pass: componentwise combination over the modes that estimate the components
+        let mut vec_weight = DVector::zeros(n);
+        let mut mat_weight = DMatrix::zeros(n, n);
+        let mut vec_x = DVector::zeros(n);
+        for (mode, &mu) in self.modes.iter().zip(self.mode_probabilities.iter()) {
+            let vec_mask = (&mode.mat_t * mode.mat_t.transpose()).diagonal();
+            vec_weight += &vec_mask * mu;
+            mat_weight += &vec_mask * vec_mask.transpose() * mu;
+            vec_x += &mode.mat_t * &mode.filter.get_state().vec_state.0 * mu;
+        }
+        let vec_x = vec_x.component_div(&vec_weight.map(|w| if w > N::zero() { w } else { N::one() }));
+        let mut mat_p = DMatrix::zeros(n, n);
+        for (mode, &mu) in self.modes.iter().zip(self.mode_probabilities.iter()) {
+            let state = mode.filter.get_state();
+            let vec_dx = &mode.mat_t * &state.vec_state.0 - &mode.mat_t * (mode.mat_t.transpose() * &vec_x);
+            mat_p += (&mode.mat_t * &state.mat_covariances.0 * mode.mat_t.transpose()
+                      + &vec_dx * vec_dx.transpose()) * mu;
+        }
+        let mat_p = mat_p.component_div(&mat_weight.map(|w| if w > N::zero() { w } else { N::one() }));
+        if self.modes.iter().all(|mode| mode.filter.get_num_states() == n) {
+            return (StateVector(vec_x), CovarianceMatrix(mat_p));
+        }
+
+        // Second pass: fill the components a mode lacks with the first pass estimate
+        let mut vec_x_combined = DVector::zeros(n);
+        let mut mat_p_combined = DMatrix::zeros(n, n);
+        let estimates : Vec<_> = self.modes.iter()
+            .map(|mode| mode.to_common(&vec_x, &mat_p))
+            .collect();
+        for (&(ref vec_x_i, _), &mu) in estimates.iter().zip(self.mode_probabilities.iter()) {
+            vec_x_combined += vec_x_i * mu;
+        }
+        for (&(ref vec_x_i, ref mat_p_i), &mu) in estimates.iter().zip(self.mode_probabilities.iter()) {
+            let vec_dx = vec_x_i - &vec_x_combined;
+            mat_p_combined += (mat_p_i + &vec_dx * vec_dx.transpose()) * mu;
+        }
+        (StateVector(vec_x_combined), CovarianceMatrix(mat_p_combined))
+    }
+
+    /// Mixes the estimates of the modes and predicts each filter.
+    pub fn predict<'a>(&'a mut self, u : &InputVector<N>) -> &'a ModeProbabilities<N> {
+        let num_modes = self.modes.len();
+
+        // c_j = SUM_i Pi_ij mu_i
+        let vec_c = self.mat_transition.transpose() * &self.mode_probabilities.0;
+
+        let mut mixed = Vec::with_capacity(num_modes);
+        for j in 0..num_modes {
+            let mode_j = &self.modes[j];
+            let state_j = mode_j.filter.get_state();
+            let vec_x_j = &mode_j.mat_t * &state_j.vec_state.0;
+            let mat_p_j = &mode_j.mat_t * &state_j.mat_covariances.0 * mode_j.mat_t.transpose();
+
+            // Estimates of all modes in the state space of mode j
+            let estimates : Vec<_> = self.modes.iter().map(|mode_i| {
+                let (vec_x, mat_p) = mode_i.to_common(&vec_x_j, &mat_p_j);
+                (mode_j.mat_t.transpose() * vec_x,
+                 mode_j.mat_t.transpose() * mat_p * &mode_j.mat_t)
+            }).collect();
+
+            let n_j = mode_j.filter.get_num_states();
+            let mut vec_x_0 = DVector::zeros(n_j);
+            let mut mat_p_0 = DMatrix::zeros(n_j, n_j);
+            if vec_c[j] > N::zero() {
+                for i in 0..num_modes {
+                    let mu_ij = self.mat_transition[(i, j)] * self.mode_probabilities[i] / vec_c[j];
+                    vec_x_0 += &estimates[i].0 * mu_ij;
+                }
+                for i in 0..num_modes {
+                    let mu_ij = self.mat_transition[(i, j)] * self.mode_probabilities[i] / vec_c[j];
+                    let vec_dx = &estimates[i].0 - &vec_x_0;
+                    mat_p_0 += (&estimates[i].1 + &vec_dx * vec_dx.transpose()) * mu_ij;
+                }
+            } else {
+                // The mode is impossible, keep its estimate
+                vec_x_0 = state_j.vec_state.0.clone();
+                mat_p_0 = state_j.mat_covariances.0.clone();
+            }
+            mixed.push((vec_x_0, mat_p_0));
+        }
+
+        for (mode, (vec_x_0, mat_p_0)) in self.modes.iter_mut().zip(mixed.into_iter()) {
+            mode.filter.set_state(StateVector(vec_x_0), CovarianceMatrix(mat_p_0));
+            mode.filter.predict(u);
+        }
+        self.mode_probabilities.0 = vec_c;
+
+        &self.mode_probabilities
+    }
+
+    /// `rvec_c` refers to the common state space. The mode probabilities are updated with the
+    /// likelihoods of all modes, also of those whose innovation gate rejects the measurement:
+    /// a measurement far outside the prediction of one mode is evidence for the others.
+    pub fn measure<'a>(&'a mut self,
+                       y : Measurement<N>,
+                       rvec_c : MeasurementMatrixRow<N>,
+                       r : MeasurementNoiseVariance<N>)
+                    -> &'a ModeProbabilities<N> {
+        let mut log_likelihoods = Vec::with_capacity(self.modes.len());
+        for mode in self.modes.iter_mut() {
+            let rvec_c_i = &rvec_c.0 * &mode.mat_t;
+            let update = mode.filter.measure(Measurement(y.0), MeasurementMatrixRow(rvec_c_i),
+                                             MeasurementNoiseVariance(r.0));
+            log_likelihoods.push(update.log_likelihood());
+        }
+        update_probabilities(&mut self.mode_probabilities.0, &log_likelihoods);
+        &self.mode_probabilities
+    }
+
+    /// `mat_c` refers to the common state space. Gating as in `measure()`.
+    pub fn measure_vector<'a>(&'a mut self,
+                              vec_y : MeasurementVector<N>,
+                              mat_c : MeasurementMatrix<N>,
+                              mat_r : MeasurementNoiseCovarianceMatrix<N>)
+                           -> &'a ModeProbabilities<N> {
+        let mut log_likelihoods = Vec::with_capacity(self.modes.len());
+        for mode in self.modes.iter_mut() {
+            let mat_c_i = &mat_c.0 * &mode.mat_t;
+            let update = mode.filter.measure_vector(vec_y.clone(),
+                                                    MeasurementMatrix(mat_c_i),
+                                                    mat_r.clone());
+            log_likelihoods.push(update.log_likelihood());
+        }
+        update_probabilities(&mut self.mode_probabilities.0, &log_likelihoods);
+        &self.mode_probabilities
+    }
+}
+
+/// mu_j = mu_j L_j / SUM_i mu_i L_i, scaled by the largest likelihood to avoid underflow
+pub(crate) fn update_probabilities<N : Real>(vec_mu : &mut DVector<N>, log_likelihoods : &[N]) {
+    let max = log_likelihoods.iter().fold(log_likelihoods[0], |acc, &l| acc.max(l));
+    for (mu, &l) in vec_mu.iter_mut().zip(log_likelihoods.iter()) {
+        *mu *= (l - max).exp();
+    }
+    let sum = vec_mu.iter().fold(N::zero(), |acc, &mu| acc + mu);
+    *vec_mu /= sum;
+}
+
+impl<N : Real> Mode<N> {
+    /// The estimate of this mode in the common state space, with the missing components taken
+    /// from x_fill and P_fill
+    fn to_common(&self, vec_x_fill : &DVector<N>, mat_p_fill : &DMatrix<N>) -> (DVector<N>, DMatrix<N>) {
+        let state = self.filter.get_state();
+        let vec_x = &self.mat_t * &state.vec_state.0 + &self.mat_missing * vec_x_fill;
+        let mat_p = &self.mat_t * &state.mat_covariances.0 * self.mat_t.transpose()
+                  + &self.mat_missing * mat_p_fill * &self.mat_missing;
+        (vec_x, mat_p)
+    }
+}
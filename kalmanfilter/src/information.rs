@@ -0,0 +1,275 @@
+use std::convert::From;
+
+use alga::general::Real;
+use na::{DMatrix, DVector, convert};
+
+use kf::{KalmanFilter, KalmanFilterBuilder};
+use nt::{DiscreteSystemMatrix, DiscreteInputMatrix, SystemNoiseVarianceMatrix, StateVector,
+         CovarianceMatrix, InputVector, Measurement, MeasurementMatrixRow,
+         MeasurementNoiseVariance, MeasurementVector, MeasurementMatrix,
+         MeasurementNoiseCovarianceMatrix, InformationVector, InformationMatrix};
+
+
+/// Information Filter
+///
+/// Stores the information matrix Y = P^-1 and the information vector y = P^-1 x instead of
+/// x and P. Y = 0 represents the total absence of knowledge (P = infinity), and measurements
+/// are fused by simple addition:
+///
+/// ```math
+///     Y = Y + C^T R^-1 C
+///     y = y + C^T R^-1 y_meas
+/// ```
+///
+/// The time update needs a regular F (always true for F = exp(A dt)):
+///
+/// ```math
+///     M = F^-T Y F^-1
+///     Y = ( I + M Q )^-1 M
+///     y = ( I + M Q )^-1 ( F^-T y + M H u )
+/// ```
+pub struct InformationFilter<N : Real>
+{
+    num_states : usize,
+    num_inputs : usize,
+    mat_f : DiscreteSystemMatrix<N>,
+    mat_f_inv : DMatrix<N>,
+    mat_h : DiscreteInputMatrix<N>,
+    mat_q : SystemNoiseVarianceMatrix<N>,
+    vec_information : InformationVector<N>,
+    mat_information : InformationMatrix<N>,
+    singularity_tolerance : N,
+}
+
+pub struct InformationFilterBuilder<N : Real>
+{
+    filter : InformationFilter<N>,
+}
+
+pub struct BorrowedInformationState<'a, N : Real + 'a> {
+    pub vec_information : &'a InformationVector<N>,
+    pub mat_information : &'a InformationMatrix<N>,
+}
+
+impl<N : Real> InformationFilterBuilder<N> {
+    /// Starts without any information (Y = 0)
+    pub fn with_numstates_and_numinputs(num_states : usize, num_inputs : usize) -> InformationFilterBuilder<N> {
+        InformationFilterBuilder {
+            filter : InformationFilter {
+                num_states : num_states,
+                num_inputs : num_inputs,
+                mat_f : DiscreteSystemMatrix(DMatrix::identity(num_states, num_states)),
+                mat_f_inv : DMatrix::identity(num_states, num_states),
+                mat_h : DiscreteInputMatrix(DMatrix::zeros(num_states, num_inputs)),
+                mat_q : SystemNoiseVarianceMatrix(DMatrix::zeros(num_states, num_states)),
+                vec_information : InformationVector(DVector::zeros(num_states)),
+                mat_information : InformationMatrix(DMatrix::zeros(num_states, num_states)),
+                singularity_tolerance : N::default_epsilon() * convert::<f64, N>(num_states as f64),
+            }
+        }
+    }
+
+    /// Panics if F is singular
+    pub fn with_system_matrix(mut self, mat_f : DiscreteSystemMatrix<N>) -> Self {
+        assert_eq!(self.filter.num_states, mat_f.ncols());
+        assert_eq!(self.filter.num_states, mat_f.nrows());
+        self.filter.mat_f_inv = mat_f.0.clone().try_inverse()
+                                       .expect("The information filter needs a regular F");
+        self.filter.mat_f = mat_f;
+        self
+    }
+
+    pub fn with_input_matrix(mut self, mat_h : DiscreteInputMatrix<N>) -> Self {
+        assert_eq!(self.filter.num_inputs, mat_h.ncols());
+        assert_eq!(self.filter.num_states, mat_h.nrows());
+        self.filter.mat_h = mat_h;
+        self
+    }
+
+    pub fn with_system_noise_variances(mut self, mat_q : SystemNoiseVarianceMatrix<N>) -> Self {
+        assert_eq!(self.filter.num_states, mat_q.ncols());
+        assert_eq!(self.filter.num_states, mat_q.nrows());
+        self.filter.mat_q = mat_q;
+        self
+    }
+
+    pub fn with_initial_information(mut self,
+                                    vec_information : InformationVector<N>,
+                                    mat_information : InformationMatrix<N>) -> Self {
+        assert_eq!(self.filter.num_states, vec_information.len());
+        assert_eq!(self.filter.num_states, mat_information.ncols());
+        assert_eq!(self.filter.num_states, mat_information.nrows());
+        self.filter.vec_information = vec_information;
+        self.filter.mat_information = mat_information;
+        self
+    }
+
+    /// Y counts as singular if its smallest eigenvalue is at most `tolerance` times its largest
+    /// eigenvalue. Defaults to n times the machine epsilon of N, i.e. only Y that are singular
+    /// apart from rounding errors are rejected. Increase it to demand better determined states.
+    pub fn with_singularity_tolerance(mut self, tolerance : N) -> Self {
+        assert!(tolerance >= N::zero(), "Singularity tolerance must not be negative");
+        self.filter.singularity_tolerance = tolerance;
+        self
+    }
+}
+
+impl<N : Real> From<InformationFilterBuilder<N>> for InformationFilter<N> {
+    fn from(builder : InformationFilterBuilder<N>) -> InformationFilter<N> {
+        builder.filter
+    }
+}
+
+/// Panics if P or F is singular
+impl<N : Real> From<KalmanFilter<N>> for InformationFilter<N> {
+    fn from(filter : KalmanFilter<N>) -> InformationFilter<N> {
+        let state = filter.get_state();
+        let mat_information = state.mat_covariances.0.clone().try_inverse()
+                                   .expect("Covariance matrix P is singular");
+        let vec_information = &mat_information * &state.vec_state.0;
+        InformationFilterBuilder::with_numstates_and_numinputs(filter.get_num_states(),
+                                                               filter.get_num_inputs())
+            .with_system_matrix(filter.get_system_matrix().clone())
+            .with_input_matrix(filter.get_input_matrix().clone())
+            .with_system_noise_variances(filter.get_system_noise_variances().clone())
+            .with_initial_information(InformationVector(vec_information),
+                                      InformationMatrix(mat_information))
+            .into()
+    }
+}
+
+impl<N : Real> From<KalmanFilterBuilder<N>> for InformationFilter<N> {
+    fn from(builder : KalmanFilterBuilder<N>) -> InformationFilter<N> {
+        KalmanFilter::from(builder).into()
+    }
+}
+
+impl<N : Real> InformationFilter<N> {
+
+    pub fn get_information<'a>(&'a self) -> BorrowedInformationState<'a, N> {
+        BorrowedInformationState {
+            vec_information : &self.vec_information,
+            mat_information : &self.mat_information,
+        }
+    }
+
+    /// Returns x = Y^-1 y and P = Y^-1, or `None` if there is not enough information yet.
+    /// Y counts as singular if its smallest eigenvalue is at most n epsilon times its largest one
+    /// (see `with_singularity_tolerance()`), since rounding errors would dominate P otherwise.
+    /// Badly scaled but well determined states are therefore still returned.
+    pub fn get_state(&self) -> Option<(StateVector<N>, CovarianceMatrix<N>)> {
+        let eigen = self.mat_information.0.clone().symmetric_eigen();
+        let lambda_max = eigen.eigenvalues.iter().fold(N::zero(), |acc, &l| acc.max(l));
+        let lambda_min = eigen.eigenvalues.iter().fold(lambda_max, |acc, &l| acc.min(l));
+        if lambda_min <= lambda_max * self.singularity_tolerance {
+            return None;
+        }
+
+        // P = V diag(lambda)^-1 V^T
+        let mat_v = eigen.eigenvectors;
+        let mat_p = &mat_v * DMatrix::from_diagonal(&eigen.eigenvalues.map(|l| l.recip()))
+                  * mat_v.transpose();
+        Some((StateVector(&mat_p * &self.vec_information.0), CovarianceMatrix(mat_p)))
+    }
+
+    /// Converts into a `KalmanFilter` with default settings, or returns `None` if there is not
+    /// enough information yet (singular Y).
+    pub fn to_kalman_filter(&self) -> Option<KalmanFilter<N>> {
+        self.get_state().map(|(vec_state, mat_p)| {
+            KalmanFilterBuilder::with_numstates_and_numinputs(self.num_states, self.num_inputs)
+                .with_system_matrix(self.mat_f.clone())
+                .with_input_matrix(self.mat_h.clone())
+                .with_system_noise_variances(self.mat_q.clone())
+                .with_initial_state(vec_state, mat_p)
+                .into()
+        })
+    }
+
+    pub fn predict<'a>(&'a mut self, u : &InputVector<N>) -> BorrowedInformationState<'a, N> {
+        assert_eq!(self.num_inputs, u.0.len());
+        let mat_f_inv_t = self.mat_f_inv.transpose();
+
+        // M = F^-T Y F^-1
+        let mat_m = &mat_f_inv_t * &self.mat_information.0 * &self.mat_f_inv;
+
+        // ( I + M Q ) has only eigenvalues >= 1 and is therefore always regular
+        let mat_i_mq = DMatrix::identity(self.num_states, self.num_states) + &mat_m * &self.mat_q.0;
+        let lu = mat_i_mq.lu();
+
+        // y = ( I + M Q )^-1 ( F^-T y + M H u )
+        let vec_rhs = &mat_f_inv_t * &self.vec_information.0 + &mat_m * (&self.mat_h.0 * &u.0);
+        self.vec_information.0 = lu.solve(&vec_rhs).expect("I + M Q is singular");
+
+        // Y = ( I + M Q )^-1 M
+        let mat_information = lu.solve(&mat_m).expect("I + M Q is singular");
+        // Remove the asymmetry introduced by rounding
+        self.mat_information.0 = (&mat_information + mat_information.transpose()) * convert::<f64, N>(0.5);
+
+        BorrowedInformationState {
+            vec_information : &self.vec_information,
+            mat_information : &self.mat_information,
+        }
+    }
+
+    pub fn measure<'a>(&'a mut self,
+                       y : Measurement<N>,
+                       rvec_c : MeasurementMatrixRow<N>,
+                       r : MeasurementNoiseVariance<N>)
+                    -> BorrowedInformationState<'a, N> {
+
+        assert_eq!(self.num_states, rvec_c.0.len());
+        let vec_c_r_inv : DVector<N> = rvec_c.0.transpose() * r.0.recip();
+
+        // Y = Y + C^T R^-1 C
+        self.mat_information.0 += &vec_c_r_inv * &rvec_c.0;
+
+        // y = y + C^T R^-1 y_meas
+        self.vec_information.0 += vec_c_r_inv * y.0;
+
+        BorrowedInformationState {
+            vec_information : &self.vec_information,
+            mat_information : &self.mat_information,
+        }
+    }
+
+    pub fn measure_vector<'a>(&'a mut self,
+                              vec_y : MeasurementVector<N>,
+                              mat_c : MeasurementMatrix<N>,
+                              mat_r : MeasurementNoiseCovarianceMatrix<N>)
+                           -> BorrowedInformationState<'a, N> {
+
+        let num_measurements = vec_y.len();
+        assert_eq!(num_measurements, mat_c.nrows());
+        assert_eq!(self.num_states, mat_c.ncols());
+        assert_eq!(num_measurements, mat_r.nrows());
+        assert_eq!(num_measurements, mat_r.ncols());
+
+        // C^T R^-1 = ( R^-1 C )^T
+        let mat_c_r_inv = mat_r.0.cholesky()
+                               .expect("Measurement noise covariance R is not positive definite")
+                               .solve(&mat_c.0)
+                               .transpose();
+
+        // Y = Y + C^T R^-1 C
+        self.mat_information.0 += &mat_c_r_inv * &mat_c.0;
+
+        // y = y + C^T R^-1 y_meas
+        self.vec_information.0 += mat_c_r_inv * vec_y.0;
+
+        BorrowedInformationState {
+            vec_information : &self.vec_information,
+            mat_information : &self.mat_information,
+        }
+    }
+
+    /// Fuses information from an independent source, e.g. another information filter
+    pub fn add_information(&mut self,
+                           vec_information : &InformationVector<N>,
+                           mat_information : &InformationMatrix<N>) {
+        assert_eq!(self.num_states, vec_information.len());
+        assert_eq!(self.num_states, mat_information.ncols());
+        assert_eq!(self.num_states, mat_information.nrows());
+        self.vec_information.0 += &vec_information.0;
+        self.mat_information.0 += &mat_information.0;
+    }
+}
@@ -1,13 +1,15 @@
 
 use std::convert::From;
+use std::mem;
 
 use alga::general::Real;
-use na::{DMatrix, DVector};
-use std::ops::Mul;
+use na::{DMatrix, DVector, convert};
 
 use nt::{DiscreteSystemMatrix, DiscreteInputMatrix, SystemNoiseVarianceMatrix, StateVector,
          CovarianceMatrix, InputVector, Measurement, MeasurementMatrixRow,
-         MeasurementNoiseVariance};
+         MeasurementNoiseVariance, MeasurementVector, MeasurementMatrix,
+         MeasurementNoiseCovarianceMatrix, InnovationVector, InnovationCovarianceMatrix,
+         KalmanGainMatrix, ConstraintMatrix, ConstraintVector};
 
 
 pub struct KalmanFilter<N : Real>
@@ -19,6 +21,95 @@ pub struct KalmanFilter<N : Real>
     mat_q : SystemNoiseVarianceMatrix<N>,
     vec_state : StateVector<N>,
     mat_p : CovarianceMatrix<N>,
+    gate : InnovationGate<N>,
+    covariance_update : CovarianceUpdate,
+    /// `None` if the log-likelihood is not accumulated
+    log_likelihood : Option<N>,
+    /// Indices of the states that are excluded from the measurement update
+    consider_states : Vec<usize>,
+    state_constraints : StateConstraints<N>,
+}
+
+/// Formula used for the covariance update in `measure()` and `measure_vector()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CovarianceUpdate {
+    /// P = P - K C P
+    Standard,
+    /// P = (I - K C) P (I - K C)^T + K R K^T
+    ///
+    /// Keeps P symmetric and positive semidefinite even with rounding errors in K.
+    Joseph,
+    /// P = P - K C P, followed by P = (P + P^T) / 2
+    Symmetrized,
+}
+
+/// Outlier detection based on the normalized innovation squared (NIS), which is chi-square
+/// distributed with m degrees of freedom for an m-dimensional measurement.
+///
+/// `thresholds[m - 1]` is the chi-square threshold for m-dimensional measurements,
+/// for example `vec![3.84, 5.99, 7.81]` for a 95% gate of up to three measurements at once.
+/// The thresholds are checked when the filter is built: they must not be empty and have to
+/// be positive. Measuring more values at once than there are thresholds panics before the
+/// state is changed.
+///
+/// The `*Probability` variants take the gate probability instead, e.g. 0.95, and compute the
+/// chi-square quantile for any measurement dimension.
+#[derive(Clone)]
+pub enum InnovationGate<N : Real> {
+    Disabled,
+    /// Measurements outside of the gate are reported as outliers, but still processed
+    Report(Vec<N>),
+    /// Measurements outside of the gate are reported and skipped
+    Reject(Vec<N>),
+    /// Like `Report`, with the threshold given as gate probability in (0, 1)
+    ReportProbability(N),
+    /// Like `Reject`, with the threshold given as gate probability in (0, 1)
+    RejectProbability(N),
+}
+
+/// Linear constraints on the state, enforced after every measurement update that has not been
+/// rejected by the innovation gate. The constraints should be consistent with the system model,
+/// the prediction does not enforce them.
+///
+/// Nonlinear constraints can be linearized around the current estimate and updated with
+/// `KalmanFilter::set_state_constraints()` before each measurement, e.g. a unit norm |x| = 1
+/// becomes x_k|k-1^T x = |x_k|k-1|.
+///
+/// Consider states are not corrected: the rows and columns of P belonging to them are treated as
+/// zero, so the constraints are satisfied by the other states only. The smoothers and the
+/// stochastic cloning filter enforce the constraints on their past states as well.
+#[derive(Clone)]
+pub enum StateConstraints<N : Real> {
+    None,
+    /// D x = d, enforced by projecting the estimate onto the constraint surface with the
+    /// weight P^-1:
+    ///
+    /// ```math
+    ///     x = x - P D^T (D P D^T)^-1 (D x - d)
+    ///     P = P - P D^T (D P D^T)^-1 D P
+    /// ```
+    ///
+    /// A pseudo-inverse is used, so constraints that P already satisfies exactly and linearly
+    /// dependent constraints (D P D^T singular) are allowed.
+    EqualityProjection(ConstraintMatrix<N>, ConstraintVector<N>),
+    /// D x = d, enforced as a perfect measurement d = D x with R = 0, using the configured
+    /// `CovarianceUpdate`. This gives the same estimate as the projection, also with the
+    /// pseudo-inverse of a singular D P D^T.
+    EqualityPseudoMeasurement(ConstraintMatrix<N>, ConstraintVector<N>),
+    /// D x <= d, enforced by projecting the estimate as above onto the constraints that are
+    /// active at the solution, which an active-set method determines. P is not changed.
+    /// Panics if the constraints cannot be satisfied.
+    InequalityActiveSet(ConstraintMatrix<N>, ConstraintVector<N>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GatingDecision {
+    /// Within the gate, or gating is disabled
+    Accepted,
+    /// Outside of the gate, but processed (`InnovationGate::Report`)
+    Outlier,
+    /// Outside of the gate and not processed (`InnovationGate::Reject`)
+    Rejected,
 }
 
 pub struct KalmanFilterBuilder<N : Real>
@@ -37,6 +128,11 @@ impl<N : Real> KalmanFilterBuilder<N> {
                 mat_q : SystemNoiseVarianceMatrix(DMatrix::zeros(num_states, num_states)),
                 vec_state : StateVector(DVector::zeros(num_states)),
                 mat_p : CovarianceMatrix(DMatrix::identity(num_inputs, num_inputs)),
+                gate : InnovationGate::Disabled,
+                covariance_update : CovarianceUpdate::Standard,
+                log_likelihood : None,
+                consider_states : Vec::new(),
+                state_constraints : StateConstraints::None,
             }
         }
     }
@@ -70,10 +166,93 @@ impl<N : Real> KalmanFilterBuilder<N> {
         self.filter.mat_p = mat_covariances;
         self
     }
+
+    pub fn with_innovation_gate(mut self, gate : InnovationGate<N>) -> Self {
+        self.filter.gate = gate;
+        self
+    }
+
+    pub fn with_covariance_update(mut self, covariance_update : CovarianceUpdate) -> Self {
+        self.filter.covariance_update = covariance_update;
+        self
+    }
+
+    /// Schmidt-Kalman filter: the states with the given indices are "consider" states (e.g. sensor
+    /// biases) that are not updated by measurements. Their rows of K are set to zero, so their
+    /// estimates and covariances only change during `predict()`, but their uncertainty still
+    /// enters S and the cross covariances with the estimated states are kept consistent.
+    ///
+    /// Since K is no longer optimal, the covariance update always uses the Joseph form.
+    pub fn with_consider_states(mut self, consider_states : Vec<usize>) -> Self {
+        assert!(consider_states.iter().all(|&i| i < self.filter.num_states));
+        self.filter.consider_states = consider_states;
+        self
+    }
+
+    pub fn with_state_constraints(mut self, state_constraints : StateConstraints<N>) -> Self {
+        self.filter.set_state_constraints(state_constraints);
+        self
+    }
+
+    /// Sums up the log-likelihoods of all processed measurements, see `get_log_likelihood()`
+    pub fn with_log_likelihood_accumulation(mut self) -> Self {
+        self.filter.log_likelihood = Some(N::zero());
+        self
+    }
+}
+
+impl<N : Real> InnovationGate<N> {
+    fn validate(&self) {
+        match *self {
+            InnovationGate::Disabled => {},
+            InnovationGate::Report(ref thresholds) | InnovationGate::Reject(ref thresholds) => {
+                assert!(!thresholds.is_empty(), "Innovation gate without thresholds");
+                assert!(thresholds.iter().all(|&threshold| threshold > N::zero()),
+                        "Innovation gate thresholds must be positive");
+            },
+            InnovationGate::ReportProbability(probability) |
+            InnovationGate::RejectProbability(probability) => {
+                assert!(probability > N::zero() && probability < N::one(),
+                        "Innovation gate probability must be in (0, 1)");
+            },
+        }
+    }
+
+    /// Panics if there is no threshold for `num_measurements` measurements at once
+    fn check_dimension(&self, num_measurements : usize) {
+        match *self {
+            InnovationGate::Report(ref thresholds) | InnovationGate::Reject(ref thresholds) => {
+                assert!(num_measurements <= thresholds.len(),
+                        "The innovation gate has thresholds for up to {} measurements at once, \
+                         but {} were given", thresholds.len(), num_measurements);
+            },
+            _ => {},
+        }
+    }
+
+    fn decide(&self, nis : N, num_measurements : usize) -> GatingDecision {
+        let (threshold, decision) = match *self {
+            InnovationGate::Disabled => return GatingDecision::Accepted,
+            InnovationGate::Report(ref thresholds) =>
+                (thresholds[num_measurements - 1], GatingDecision::Outlier),
+            InnovationGate::Reject(ref thresholds) =>
+                (thresholds[num_measurements - 1], GatingDecision::Rejected),
+            InnovationGate::ReportProbability(probability) =>
+                (chi_square_quantile(probability, num_measurements), GatingDecision::Outlier),
+            InnovationGate::RejectProbability(probability) =>
+                (chi_square_quantile(probability, num_measurements), GatingDecision::Rejected),
+        };
+        if nis > threshold {
+            decision
+        } else {
+            GatingDecision::Accepted
+        }
+    }
 }
 
 impl<N : Real> From<KalmanFilterBuilder<N>> for KalmanFilter<N> {
     fn from(builder : KalmanFilterBuilder<N>) -> KalmanFilter<N> {
+        builder.filter.gate.validate();
         builder.filter
     }
 }
@@ -83,8 +262,123 @@ pub struct BorrowedSystemState<'a, N : Real + 'a> {
     pub mat_covariances : &'a CovarianceMatrix<N>,
 }
 
+/// The updated system state together with the intermediate results of the measurement update.
+/// For scalar measurements, the innovation and S have dimension 1 and K is a single column.
+pub struct MeasurementUpdate<'a, N : Real + 'a> {
+    pub vec_state : &'a StateVector<N>,
+    pub mat_covariances : &'a CovarianceMatrix<N>,
+    /// residual = y - C x (using the predicted x)
+    pub vec_innovation : InnovationVector<N>,
+    /// S = C P C^T + R
+    pub mat_s : InnovationCovarianceMatrix<N>,
+    /// K = P C^T S^-1
+    pub mat_k : KalmanGainMatrix<N>,
+    /// Normalized innovation squared: residual^T S^-1 residual
+    pub nis : N,
+    /// If `Rejected`, state and covariances have not been changed
+    pub gating : GatingDecision,
+}
+
+impl<'a, N : Real> MeasurementUpdate<'a, N> {
+    /// ln p( y | all previous measurements ) = -1/2 ( nis + m ln(2 pi) + ln det S ),
+    /// with m the number of measurements
+    pub fn log_likelihood(&self) -> N {
+        let mat_l = self.mat_s.0.clone()
+                        .cholesky()
+                        .expect("Innovation covariance S is not positive definite")
+                        .unpack();
+        gaussian_log_likelihood(self.nis, &mat_l)
+    }
+}
+
+/// -1/2 ( nis + m ln(2 pi) + ln det S ), with S = L L^T
+fn gaussian_log_likelihood<N : Real>(nis : N, mat_l : &DMatrix<N>) -> N {
+    let num_measurements = mat_l.nrows();
+    // ln det S = 2 SUM_i ln L_ii
+    let mut ln_det_s = N::zero();
+    for i in 0..num_measurements {
+        ln_det_s += mat_l[(i, i)].ln();
+    }
+    ln_det_s *= convert(2.0);
+    let ln_2pi : N = convert((2.0 * ::std::f64::consts::PI).ln());
+    -(nis + ln_2pi * convert(num_measurements as f64) + ln_det_s) * convert(0.5)
+}
+
 impl<N : Real> KalmanFilter<N> {
 
+    pub fn get_state<'a>(&'a self) -> BorrowedSystemState<'a, N> {
+        BorrowedSystemState {
+            vec_state : &self.vec_state,
+            mat_covariances : &self.mat_p,
+        }
+    }
+
+    pub fn set_state(&mut self, vec_state : StateVector<N>, mat_covariances : CovarianceMatrix<N>) {
+        assert_eq!(self.num_states, vec_state.len());
+        assert_eq!(self.num_states, mat_covariances.ncols());
+        assert_eq!(self.num_states, mat_covariances.nrows());
+        self.vec_state = vec_state;
+        self.mat_p = mat_covariances;
+    }
+
+    pub fn get_system_matrix(&self) -> &DiscreteSystemMatrix<N> {
+        &self.mat_f
+    }
+
+    pub fn get_input_matrix(&self) -> &DiscreteInputMatrix<N> {
+        &self.mat_h
+    }
+
+    pub fn get_system_noise_variances(&self) -> &SystemNoiseVarianceMatrix<N> {
+        &self.mat_q
+    }
+
+    pub fn set_system_noise_variances(&mut self, mat_q : SystemNoiseVarianceMatrix<N>) {
+        assert_eq!(self.num_states, mat_q.ncols());
+        assert_eq!(self.num_states, mat_q.nrows());
+        self.mat_q = mat_q;
+    }
+
+    pub fn get_num_states(&self) -> usize {
+        self.num_states
+    }
+
+    pub fn get_num_inputs(&self) -> usize {
+        self.num_inputs
+    }
+
+    pub fn set_state_constraints(&mut self, state_constraints : StateConstraints<N>) {
+        match state_constraints {
+            StateConstraints::None => {},
+            StateConstraints::EqualityProjection(ref mat_d, ref vec_d) |
+            StateConstraints::EqualityPseudoMeasurement(ref mat_d, ref vec_d) |
+            StateConstraints::InequalityActiveSet(ref mat_d, ref vec_d) => {
+                assert_eq!(self.num_states, mat_d.ncols());
+                assert_eq!(mat_d.nrows(), vec_d.len());
+            },
+        }
+        self.state_constraints = state_constraints;
+    }
+
+    pub fn get_consider_states(&self) -> &[usize] {
+        &self.consider_states
+    }
+
+    /// ln p( y_1, ..., y_k ) = SUM_i ln p( y_i | y_1, ..., y_i-1 ), the sum of the log-likelihoods
+    /// of all measurements since the filter has been built or `reset_log_likelihood()` has been
+    /// called. `None` if the builder did not enable the accumulation. Rejected measurements are
+    /// not included.
+    pub fn get_log_likelihood(&self) -> Option<N> {
+        self.log_likelihood
+    }
+
+    /// Restarts the accumulation at 0, if enabled
+    pub fn reset_log_likelihood(&mut self) {
+        if self.log_likelihood.is_some() {
+            self.log_likelihood = Some(N::zero());
+        }
+    }
+
     pub fn predict<'a>(&'a mut self, u : &InputVector<N>) -> BorrowedSystemState<'a, N> {
         assert_eq!(self.num_inputs, u.0.len());
         self.vec_state = StateVector( &self.mat_f.0 * &self.vec_state.0 + &self.mat_h.0 * &u.0 );
@@ -100,32 +394,475 @@ impl<N : Real> KalmanFilter<N> {
                        y : Measurement<N>,
                        rvec_c : MeasurementMatrixRow<N>,
                        r : MeasurementNoiseVariance<N>)
-                    -> BorrowedSystemState<'a, N> {
+                    -> MeasurementUpdate<'a, N> {
 
         assert_eq!(self.num_states, rvec_c.0.len());
         // The matrix measurement formula is : vec_y = mat_c vec_x + vec_r
         // If we measure only one line of that y vector, named scalar_y, we get
         //                                  scalar_y = rvec_c * vec_x + scalar_r
+        let mat_c = DMatrix::from_row_slice(1, self.num_states, rvec_c.0.as_slice());
+        self.measure_vector(MeasurementVector(DVector::from_element(1, y.0)),
+                            MeasurementMatrix(mat_c),
+                            MeasurementNoiseCovarianceMatrix(DMatrix::from_element(1, 1, r.0)))
+    }
 
-        // S = C P C^T + R
-        let s : N = (&rvec_c.0).mul(&self.mat_p.0).dot(&rvec_c.0) + r.0;
+    /// Processes multiple measurements at once. Unlike sequential calls of `measure()`, this
+    /// also works with correlated measurement noise (non-diagonal R).
+    pub fn measure_vector<'a>(&'a mut self,
+                              vec_y : MeasurementVector<N>,
+                              mat_c : MeasurementMatrix<N>,
+                              mat_r : MeasurementNoiseCovarianceMatrix<N>)
+                           -> MeasurementUpdate<'a, N> {
+        let innovation = self.update_state(vec_y, mat_c, mat_r);
+        self.measurement_update(innovation)
+    }
 
-        // K = P C^T S^-1 // k is column vector
-        let vec_k : DVector<N> = (&self.mat_p.0 * &rvec_c.0.transpose()) * s.recip();
+    /// `measure_vector()` without borrowing the updated state
+    pub(crate) fn update_state(&mut self,
+                               vec_y : MeasurementVector<N>,
+                               mat_c : MeasurementMatrix<N>,
+                               mat_r : MeasurementNoiseCovarianceMatrix<N>) -> Innovation<N> {
+        let num_measurements = vec_y.len();
+        assert_eq!(num_measurements, mat_c.nrows());
+        assert_eq!(self.num_states, mat_c.ncols());
+        assert_eq!(num_measurements, mat_r.nrows());
+        assert_eq!(num_measurements, mat_r.ncols());
+        self.gate.check_dimension(num_measurements);
 
-        // residual = y - C x
-        let residual = y.0 - (&rvec_c.0 * &self.vec_state.0)[(0, 0)];
+        let mut vec_x = mem::replace(&mut self.vec_state.0, DVector::zeros(0));
+        let mut mat_p = mem::replace(&mut self.mat_p.0, DMatrix::zeros(0, 0));
+        let innovation = self.update(&mut vec_x, &mut mat_p, &vec_y.0, &mat_c.0, &mat_r.0);
+        self.vec_state.0 = vec_x;
+        self.mat_p.0 = mat_p;
+        innovation
+    }
 
-        // x = x + K residual
-        self.vec_state.0 += &vec_k * residual;
+    /// Measurement update of the state augmented with the states x_a (e.g. clones of earlier
+    /// states), their covariance P_aa and the cross covariance P_ka with the current state.
+    /// `mat_c` is [ C_k C_a ]. Gating, covariance update, log-likelihood and state constraints
+    /// apply as in `measure_vector()`. The augmented states have to consist of copies of the
+    /// state, consider states are excluded from the update in each copy.
+    pub(crate) fn measure_augmented<'a>(&'a mut self,
+                                        vec_y : MeasurementVector<N>,
+                                        mat_c : &DMatrix<N>,
+                                        mat_r : MeasurementNoiseCovarianceMatrix<N>,
+                                        vec_x_a : &mut DVector<N>,
+                                        mat_p_a : &mut DMatrix<N>,
+                                        mat_p_cross : &mut DMatrix<N>)
+                                     -> MeasurementUpdate<'a, N> {
+        let n = self.num_states;
+        let n_a = vec_x_a.len();
+        assert_eq!(n + n_a, mat_c.ncols());
+        assert_eq!(vec_y.len(), mat_c.nrows());
 
-        // P = P - K C P
-        self.mat_p.0 = &self.mat_p.0 - &vec_k * &rvec_c.0 * &self.mat_p.0;
+        let (mut vec_x, mut mat_p) = augment(&self.vec_state.0, &self.mat_p.0,
+                                             vec_x_a, mat_p_a, mat_p_cross);
 
-        BorrowedSystemState {
+        let innovation = self.update(&mut vec_x, &mut mat_p, &vec_y.0, mat_c, &mat_r.0);
+
+        self.vec_state.0 = vec_x.rows(0, n).clone_owned();
+        *vec_x_a = vec_x.rows(n, n_a).clone_owned();
+        self.mat_p.0 = mat_p.slice((0, 0), (n, n)).clone_owned();
+        *mat_p_cross = mat_p.slice((0, n), (n, n_a)).clone_owned();
+        *mat_p_a = mat_p.slice((n, n), (n_a, n_a)).clone_owned();
+        self.measurement_update(innovation)
+    }
+
+    /// Applies a measurement update of the current state to the state augmented with other states
+    /// x_a, see `augment()`. x and P are the augmented state before the update of the current
+    /// state, which gave `innovation`, and `mat_c` is [ C 0 ]. The gain of x_a is
+    /// K_a = P_ak C^T S^-1 without the consider states, and P is updated in Joseph form in that
+    /// case, since the gain is not optimal then.
+    pub(crate) fn update_augmented(&self,
+                                   vec_x : &mut DVector<N>,
+                                   mat_p : &mut DMatrix<N>,
+                                   mat_c : &DMatrix<N>,
+                                   mat_r : &DMatrix<N>,
+                                   innovation : &Innovation<N>) {
+        let chol_s = innovation.mat_s.clone()
+                               .cholesky()
+                               .expect("Innovation covariance S is not positive definite");
+        let mut mat_k = chol_s.solve(&(mat_c * &*mat_p)).transpose();
+        for i in self.consider_indices(mat_k.nrows()) {
+            mat_k.row_mut(i).fill(N::zero());
+        }
+        *vec_x += &mat_k * &innovation.vec_residual;
+        self.update_covariance(mat_p, &mat_k, mat_c, mat_r);
+    }
+
+    /// A `MeasurementUpdate` with the current state
+    pub(crate) fn measurement_update<'a>(&'a self, innovation : Innovation<N>) -> MeasurementUpdate<'a, N> {
+        MeasurementUpdate {
             vec_state : &self.vec_state,
             mat_covariances : &self.mat_p,
+            vec_innovation : InnovationVector(innovation.vec_residual),
+            mat_s : InnovationCovarianceMatrix(innovation.mat_s),
+            mat_k : KalmanGainMatrix(innovation.mat_k),
+            nis : innovation.nis,
+            gating : innovation.gating,
         }
     }
+
+    /// Measurement update of x and P, which are the state of the filter or the augmented state
+    /// of `measure_augmented()`
+    fn update(&mut self,
+              vec_x : &mut DVector<N>,
+              mat_p : &mut DMatrix<N>,
+              vec_y : &DVector<N>,
+              mat_c : &DMatrix<N>,
+              mat_r : &DMatrix<N>) -> Innovation<N> {
+        let num_measurements = vec_y.len();
+
+        let mat_cp = mat_c * &*mat_p;
+
+        // S = C P C^T + R
+        let mat_s = &mat_cp * mat_c.transpose() + mat_r;
+        let chol_s = mat_s.clone()
+                          .cholesky()
+                          .expect("Innovation covariance S is not positive definite");
+
+        // K = P C^T S^-1
+        // Since P and S are symmetric, K^T is the solution of  S K^T = C P
+        let mut mat_k = chol_s.solve(&mat_cp).transpose();
+        for i in self.consider_indices(mat_k.nrows()) {
+            mat_k.row_mut(i).fill(N::zero());
+        }
+
+        // residual = y - C x
+        let vec_residual = vec_y - mat_c * &*vec_x;
+
+        // NIS = residual^T S^-1 residual
+        let nis = vec_residual.dot(&chol_s.solve(&vec_residual));
+        let gating = self.gate.decide(nis, num_measurements);
+
+        if gating != GatingDecision::Rejected {
+            if let Some(ref mut log_likelihood) = self.log_likelihood {
+                *log_likelihood += gaussian_log_likelihood(nis, chol_s.l_dirty());
+            }
+
+            // x = x + K residual
+            *vec_x += &mat_k * &vec_residual;
+
+            // P = P - K C P
+            self.update_covariance(mat_p, &mat_k, mat_c, mat_r);
+
+            self.apply_state_constraints(vec_x, mat_p);
+        }
+
+        Innovation {
+            vec_residual : vec_residual,
+            mat_s : mat_s,
+            mat_k : mat_k,
+            nis : nis,
+            gating : gating,
+        }
+    }
+
+    /// Indices of the consider states in a state of dimension `dim`, which consists of one or
+    /// more copies of the state
+    fn consider_indices(&self, dim : usize) -> Vec<usize> {
+        let mut indices = Vec::new();
+        for offset in (0..dim).filter(|i| i % self.num_states == 0) {
+            indices.extend(self.consider_states.iter().map(|&i| offset + i));
+        }
+        indices
+    }
+
+    /// Enforces the state constraints on x and P, which consist of one or more copies of the
+    /// state. The constraints hold for every copy and are enforced jointly, with the cross
+    /// covariances between the copies. Consider states are not corrected.
+    pub(crate) fn apply_state_constraints(&self, vec_x : &mut DVector<N>, mat_p : &mut DMatrix<N>) {
+        let (mat_d, vec_d) = match self.state_constraints {
+            StateConstraints::None => return,
+            StateConstraints::EqualityProjection(ref mat_d, ref vec_d) |
+            StateConstraints::EqualityPseudoMeasurement(ref mat_d, ref vec_d) |
+            StateConstraints::InequalityActiveSet(ref mat_d, ref vec_d) => {
+                let num_copies = vec_x.len() / self.num_states;
+                let num_constraints = mat_d.nrows();
+                let mut mat_d_copies = DMatrix::zeros(num_copies * num_constraints, vec_x.len());
+                let mut vec_d_copies = DVector::zeros(num_copies * num_constraints);
+                for i in 0..num_copies {
+                    mat_d_copies.slice_mut((i * num_constraints, i * self.num_states),
+                                           (num_constraints, self.num_states))
+                                .copy_from(&mat_d.0);
+                    vec_d_copies.rows_mut(i * num_constraints, num_constraints).copy_from(&vec_d.0);
+                }
+                (mat_d_copies, vec_d_copies)
+            },
+        };
+
+        // The constraints are solved with the solve-for states only
+        let mut mat_p_solve_for = mat_p.clone();
+        for i in self.consider_indices(vec_x.len()) {
+            mat_p_solve_for.row_mut(i).fill(N::zero());
+            mat_p_solve_for.column_mut(i).fill(N::zero());
+        }
+
+        match self.state_constraints {
+            StateConstraints::None => {},
+            StateConstraints::EqualityProjection(..) => {
+                let mat_k = constraint_gain(&mat_p_solve_for, &mat_d);
+                *vec_x -= &mat_k * (&mat_d * &*vec_x - vec_d);
+                // P = (I - K D) P (I - K D)^T, which is P - P D^T (D P D^T)^+ D P without
+                // consider states
+                let dim = vec_x.len();
+                let mat_i_kd = DMatrix::identity(dim, dim) - &mat_k * &mat_d;
+                let mat_p_c = &mat_i_kd * &*mat_p * mat_i_kd.transpose();
+                *mat_p = (&mat_p_c + mat_p_c.transpose()) * convert::<f64, N>(0.5);
+            },
+            StateConstraints::EqualityPseudoMeasurement(..) => {
+                let num_constraints = mat_d.nrows();
+                let mat_k = constraint_gain(&mat_p_solve_for, &mat_d);
+                *vec_x += &mat_k * (vec_d - &mat_d * &*vec_x);
+                self.update_covariance(mat_p, &mat_k, &mat_d,
+                                       &DMatrix::zeros(num_constraints, num_constraints));
+            },
+            StateConstraints::InequalityActiveSet(..) => {
+                *vec_x = project_on_inequality_constraints(vec_x, &mat_p_solve_for, &mat_d, &vec_d);
+            },
+        }
+    }
+
+    fn update_covariance(&self, mat_p : &mut DMatrix<N>, mat_k : &DMatrix<N>, mat_c : &DMatrix<N>,
+                         mat_r : &DMatrix<N>) {
+        let covariance_update = if self.consider_states.is_empty() {
+            self.covariance_update
+        } else {
+            CovarianceUpdate::Joseph
+        };
+        match covariance_update {
+            CovarianceUpdate::Standard => {
+                let mat_kcp = mat_k * mat_c * &*mat_p;
+                *mat_p -= mat_kcp;
+            },
+            CovarianceUpdate::Joseph => {
+                let dim = mat_p.nrows();
+                let mat_i_kc = DMatrix::identity(dim, dim) - mat_k * mat_c;
+                *mat_p = &mat_i_kc * &*mat_p * mat_i_kc.transpose()
+                       + mat_k * mat_r * mat_k.transpose();
+            },
+            CovarianceUpdate::Symmetrized => {
+                let mat_p_updated = &*mat_p - mat_k * mat_c * &*mat_p;
+                *mat_p = (&mat_p_updated + mat_p_updated.transpose()) * convert::<f64, N>(0.5);
+            },
+        }
+    }
+}
+
+/// Intermediate results of a measurement update
+pub(crate) struct Innovation<N : Real> {
+    pub(crate) vec_residual : DVector<N>,
+    pub(crate) mat_s : DMatrix<N>,
+    pub(crate) mat_k : DMatrix<N>,
+    pub(crate) nis : N,
+    pub(crate) gating : GatingDecision,
 }
 
+/// The augmented state [ x_k x_a ] with the covariance [ P_k P_ka ; P_ka^T P_a ]
+pub(crate) fn augment<N : Real>(vec_x_k : &DVector<N>,
+                                mat_p_k : &DMatrix<N>,
+                                vec_x_a : &DVector<N>,
+                                mat_p_a : &DMatrix<N>,
+                                mat_p_cross : &DMatrix<N>) -> (DVector<N>, DMatrix<N>) {
+    let n = vec_x_k.len();
+    let n_a = vec_x_a.len();
+    let mut vec_x = DVector::zeros(n + n_a);
+    vec_x.rows_mut(0, n).copy_from(vec_x_k);
+    vec_x.rows_mut(n, n_a).copy_from(vec_x_a);
+    let mut mat_p = DMatrix::zeros(n + n_a, n + n_a);
+    mat_p.slice_mut((0, 0), (n, n)).copy_from(mat_p_k);
+    mat_p.slice_mut((0, n), (n, n_a)).copy_from(mat_p_cross);
+    mat_p.slice_mut((n, 0), (n_a, n)).copy_from(&mat_p_cross.transpose());
+    mat_p.slice_mut((n, n), (n_a, n_a)).copy_from(mat_p_a);
+    (vec_x, mat_p)
+}
+
+/// K = P D^T (D P D^T)^+
+fn constraint_gain<N : Real>(mat_p : &DMatrix<N>, mat_d : &DMatrix<N>) -> DMatrix<N> {
+    let mat_pd = mat_p * mat_d.transpose();
+    let mat_dpd_inv = symmetric_pseudo_inverse(mat_d * &mat_pd);
+    mat_pd * mat_dpd_inv
+}
+
+/// Minimizes (x_c - x)^T P^-1 (x_c - x) subject to D x_c <= d with a primal active-set method.
+/// For the active constraints A, x_c = x - P D_A^T l with the Lagrange multipliers
+/// l = (D_A P D_A^T)^+ (D_A x - d_A), which are nonnegative at the solution.
+///
+/// Panics if the constraints cannot be satisfied (infeasible for the solve-for states) or the
+/// active set does not converge.
+fn project_on_inequality_constraints<N : Real>(vec_x : &DVector<N>,
+                                               mat_p : &DMatrix<N>,
+                                               mat_d : &DMatrix<N>,
+                                               vec_d : &DVector<N>) -> DVector<N> {
+    let num_constraints = mat_d.nrows();
+    let tolerance : N = convert(1e-12);
+    let mut active : Vec<usize> = Vec::new();
+    let mut vec_x_c = vec_x.clone();
+    let mut converged = false;
+
+    for _ in 0..(10 * (num_constraints + 1)) {
+        // Add the most violated constraint
+        let vec_violation = mat_d * &vec_x_c - vec_d;
+        let mut most_violated = None;
+        let mut max_violation = N::zero();
+        for i in (0..num_constraints).filter(|i| !active.contains(i)) {
+            if vec_violation[i] > tolerance * (N::one() + vec_d[i].abs()) && vec_violation[i] > max_violation {
+                most_violated = Some(i);
+                max_violation = vec_violation[i];
+            }
+        }
+
+        match most_violated {
+            Some(i) => active.push(i),
+            None => {
+                // Drop the constraint with the most negative multiplier, if any
+                let vec_lambda = inequality_multipliers(vec_x, mat_p, mat_d, vec_d, &active).1;
+                let mut most_negative = None;
+                let mut min_lambda = -tolerance;
+                for (j, &lambda) in vec_lambda.iter().enumerate() {
+                    if lambda < min_lambda {
+                        most_negative = Some(j);
+                        min_lambda = lambda;
+                    }
+                }
+                match most_negative {
+                    Some(j) => { active.remove(j); },
+                    None => {
+                        converged = true;
+                        break;
+                    },
+                }
+            },
+        }
+
+        vec_x_c = inequality_multipliers(vec_x, mat_p, mat_d, vec_d, &active).0;
+    }
+
+    assert!(converged, "Inequality constraints cannot be satisfied, the active set did not converge");
+    // The active constraints are only solved in the least-squares sense if they conflict
+    let vec_violation = mat_d * &vec_x_c - vec_d;
+    let feasibility_tolerance : N = convert(1e-9);
+    assert!((0..num_constraints).all(|i| vec_violation[i] <= feasibility_tolerance * (N::one() + vec_d[i].abs())),
+            "Inequality constraints cannot be satisfied");
+    vec_x_c
+}
+
+/// x_c and l for the active constraints
+fn inequality_multipliers<N : Real>(vec_x : &DVector<N>,
+                                    mat_p : &DMatrix<N>,
+                                    mat_d : &DMatrix<N>,
+                                    vec_d : &DVector<N>,
+                                    active : &[usize]) -> (DVector<N>, DVector<N>) {
+    if active.is_empty() {
+        return (vec_x.clone(), DVector::zeros(0));
+    }
+    let mut mat_d_a = DMatrix::zeros(active.len(), mat_d.ncols());
+    let mut vec_d_a = DVector::zeros(active.len());
+    for (j, &i) in active.iter().enumerate() {
+        mat_d_a.row_mut(j).copy_from(&mat_d.row(i));
+        vec_d_a[j] = vec_d[i];
+    }
+    let mat_pd = mat_p * mat_d_a.transpose();
+    let vec_lambda = symmetric_pseudo_inverse(&mat_d_a * &mat_pd) * (&mat_d_a * vec_x - vec_d_a);
+    (vec_x - mat_pd * &vec_lambda, vec_lambda)
+}
+
+/// Chi-square quantile: the x with P(k/2, x/2) = probability for k degrees of freedom, found by
+/// bisection
+fn chi_square_quantile<N : Real>(probability : N, dof : usize) -> N {
+    let a : N = convert::<f64, N>(dof as f64) * convert(0.5);
+    let mut upper = convert::<f64, N>(dof as f64 + 1.);
+    while regularized_lower_gamma(a, upper * convert(0.5)) < probability {
+        upper *= convert(2.);
+    }
+    let mut lower = N::zero();
+    for _ in 0..200 {
+        let x = (lower + upper) * convert(0.5);
+        if regularized_lower_gamma(a, x * convert(0.5)) < probability {
+            lower = x;
+        } else {
+            upper = x;
+        }
+        if upper - lower <= upper * N::default_epsilon() {
+            break;
+        }
+    }
+    (lower + upper) * convert(0.5)
+}
+
+/// Regularized lower incomplete gamma function P(a, x), by its series for x < a + 1 and by the
+/// continued fraction of Q(a, x) = 1 - P(a, x) otherwise (Numerical Recipes, ch. 6.2)
+fn regularized_lower_gamma<N : Real>(a : N, x : N) -> N {
+    if x <= N::zero() {
+        return N::zero();
+    }
+    let prefactor = (a * x.ln() - x - ln_gamma(a)).exp();
+    if x < a + N::one() {
+        let mut ap = a;
+        let mut term = a.recip();
+        let mut sum = term;
+        for _ in 0..1000 {
+            ap += N::one();
+            term *= x / ap;
+            sum += term;
+            if term.abs() <= sum.abs() * N::default_epsilon() {
+                break;
+            }
+        }
+        sum * prefactor
+    } else {
+        // Modified Lentz's method
+        let tiny : N = convert(1e-300);
+        let mut b = x + N::one() - a;
+        let mut c = tiny.recip();
+        let mut d = b.recip();
+        let mut h = d;
+        for i in 1..1000 {
+            let an = -convert::<f64, N>(i as f64) * (convert::<f64, N>(i as f64) - a);
+            b += convert(2.);
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = d.recip();
+            let delta = d * c;
+            h *= delta;
+            if (delta - N::one()).abs() <= N::default_epsilon() {
+                break;
+            }
+        }
+        N::one() - prefactor * h
+    }
+}
+
+/// ln Γ(x) for x > 0, Lanczos approximation with g = 7
+fn ln_gamma<N : Real>(x : N) -> N {
+    const COEFFICIENTS : [f64; 9] = [0.999_999_999_999_809_9, 676.520_368_121_885_1,
+                                     -1_259.139_216_722_402_8, 771.323_428_777_653_1,
+                                     -176.615_029_162_140_6, 12.507_343_278_686_905,
+                                     -0.138_571_095_265_720_12, 9.984_369_578_019_572e-6,
+                                     1.505_632_735_149_311_6e-7];
+    let x = x - N::one();
+    let mut sum : N = convert(COEFFICIENTS[0]);
+    for (i, &coefficient) in COEFFICIENTS.iter().enumerate().skip(1) {
+        sum += convert::<f64, N>(coefficient) / (x + convert(i as f64));
+    }
+    let t = x + convert(7.5);
+    convert::<f64, N>(0.5 * (2. * ::std::f64::consts::PI).ln()) + (x + convert(0.5)) * t.ln() - t
+        + sum.ln()
+}
+
+/// Pseudo-inverse of a symmetric positive semidefinite matrix, eigenvalues below 1e-12 times the
+/// largest are treated as 0
+fn symmetric_pseudo_inverse<N : Real>(mat_a : DMatrix<N>) -> DMatrix<N> {
+    let eigen = mat_a.symmetric_eigen();
+    let lambda_max = eigen.eigenvalues.iter().fold(N::zero(), |acc, &l| acc.max(l));
+    let threshold = lambda_max * convert(1e-12);
+    let vec_lambda_inv = eigen.eigenvalues.map(|l| if l > threshold { l.recip() } else { N::zero() });
+    let mat_v = eigen.eigenvectors;
+    &mat_v * DMatrix::from_diagonal(&vec_lambda_inv) * mat_v.transpose()
+}
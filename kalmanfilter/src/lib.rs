@@ -4,9 +4,27 @@ extern crate alga;
 extern crate nalgebra as na;
 extern crate num;
 extern crate generic_array;
+extern crate rand;
 
 pub mod systems;
 pub mod kf;
+pub mod ekf;
+pub mod sckf;
+pub mod srkf;
+pub mod udkf;
+pub mod information;
+pub mod ukf;
+pub mod ckf;
+pub mod enkf;
+pub mod pf;
+pub mod rts;
+pub mod fixedlag;
+pub mod fixedpoint;
+pub mod imm;
+pub mod mmae;
+pub mod adaptive;
+pub mod em;
+pub mod als;
 
 pub mod nt {
     use na::{Real, DMatrix, DVector, RowDVector};
@@ -50,12 +68,37 @@ pub mod nt {
     newtype!(InputVector, DVector);
 
     newtype!(SystemNoiseVarianceMatrix);
+    newtype!(SystemNoiseSquareRootMatrix);
     newtype!(StateVector, DVector);
     newtype!(CovarianceMatrix);
+    newtype!(CovarianceSquareRootMatrix);
+    newtype!(CovarianceFactorU);
+    newtype!(CovarianceFactorD, DVector);
 
     newtype!(Measurement, N);
     newtype!(MeasurementNoiseVariance, N);
     newtype!(MeasurementMatrixRow, RowDVector);
+    newtype!(MeasurementVector, DVector);
+    newtype!(MeasurementMatrix);
+    newtype!(MeasurementNoiseCovarianceMatrix);
+
+    newtype!(InnovationVector, DVector);
+    newtype!(InnovationCovarianceMatrix);
+    newtype!(KalmanGainMatrix);
+
+    newtype!(ConstraintMatrix);
+    newtype!(ConstraintVector, DVector);
+
+    newtype!(InformationVector, DVector);
+    newtype!(InformationMatrix);
+
+    newtype!(EnsembleMatrix);
+    newtype!(ParticleMatrix);
+    newtype!(ParticleWeights, DVector);
+
+    newtype!(ModeProbabilities, DVector);
+    newtype!(ModeTransitionMatrix);
+    newtype!(HypothesisProbabilities, DVector);
 
     pub trait SystemMatrix<N : Real> { fn matrix(&self) -> &DMatrix<N>; }
     impl<N:Real> SystemMatrix<N> for DiscreteSystemMatrix<N> {
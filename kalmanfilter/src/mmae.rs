@@ -0,0 +1,212 @@
+use std::convert::From;
+
+use alga::general::Real;
+use na::{DMatrix, DVector, convert};
+
+use kf::KalmanFilter;
+use imm::update_probabilities;
+use nt::{StateVector, CovarianceMatrix, InputVector, Measurement, MeasurementMatrixRow,
+         MeasurementNoiseVariance, MeasurementVector, MeasurementMatrix,
+         MeasurementNoiseCovarianceMatrix, InnovationVector, InnovationCovarianceMatrix,
+         HypothesisProbabilities};
+
+
+/// Multiple model adaptive estimator (static multiple model filter bank)
+///
+/// Runs one `KalmanFilter` per hypothesis (e.g. a parameter value or a fault), all on the
+/// same state space. In contrast to the `InteractingMultipleModel`, the true hypothesis does not
+/// change over time, so the filters do not interact. After each measurement the posterior
+/// probability of hypothesis j is updated with the likelihood of its innovation v_j
+///
+/// ```math
+///     L_j   = N( v_j ; 0, S_j )
+///     mu_j  = mu_j L_j / SUM_i mu_i L_i
+/// ```
+///
+/// The probabilities can be bounded from below, so a hypothesis that has been ruled out can
+/// still be detected later (e.g. a fault that occurs during operation).
+pub struct MultipleModelAdaptiveEstimator<N : Real>
+{
+    hypotheses : Vec<KalmanFilter<N>>,
+    probabilities : HypothesisProbabilities<N>,
+    log_likelihoods : Vec<N>,
+    min_probability : N,
+}
+
+pub struct MultipleModelAdaptiveEstimatorBuilder<N : Real>
+{
+    hypotheses : Vec<KalmanFilter<N>>,
+    probabilities : Option<HypothesisProbabilities<N>>,
+    min_probability : N,
+}
+
+/// The innovation of one hypothesis filter for a measurement
+pub struct HypothesisInnovation<N : Real> {
+    pub vec_innovation : InnovationVector<N>,
+    pub mat_s : InnovationCovarianceMatrix<N>,
+    /// ln p( y | all previous measurements, hypothesis )
+    pub log_likelihood : N,
+}
+
+impl<N : Real> MultipleModelAdaptiveEstimatorBuilder<N> {
+    pub fn new() -> Self {
+        MultipleModelAdaptiveEstimatorBuilder {
+            hypotheses : Vec::new(),
+            probabilities : None,
+            min_probability : N::zero(),
+        }
+    }
+
+    pub fn with_hypothesis(mut self, filter : KalmanFilter<N>) -> Self {
+        self.hypotheses.push(filter);
+        self
+    }
+
+    /// Prior probabilities of the hypotheses, uniform if not given
+    pub fn with_prior_probabilities(mut self, probabilities : HypothesisProbabilities<N>) -> Self {
+        self.probabilities = Some(probabilities);
+        self
+    }
+
+    /// Lower bound for the probability of every hypothesis, 0 if not given
+    pub fn with_minimum_probability(mut self, min_probability : N) -> Self {
+        self.min_probability = min_probability;
+        self
+    }
+}
+
+impl<N : Real> From<MultipleModelAdaptiveEstimatorBuilder<N>> for MultipleModelAdaptiveEstimator<N> {
+    fn from(builder : MultipleModelAdaptiveEstimatorBuilder<N>) -> MultipleModelAdaptiveEstimator<N> {
+        let num_hypotheses = builder.hypotheses.len();
+        assert!(num_hypotheses >= 1);
+        let num_states = builder.hypotheses[0].get_num_states();
+        assert!(builder.hypotheses.iter().all(|filter| filter.get_num_states() == num_states));
+        let probabilities = builder.probabilities.unwrap_or_else(|| {
+            HypothesisProbabilities(DVector::from_element(num_hypotheses,
+                                                          convert::<f64, N>(num_hypotheses as f64).recip()))
+        });
+        assert_eq!(num_hypotheses, probabilities.len());
+        assert!(builder.min_probability * convert(num_hypotheses as f64) < N::one());
+        MultipleModelAdaptiveEstimator {
+            hypotheses : builder.hypotheses,
+            probabilities : probabilities,
+            log_likelihoods : vec![N::zero(); num_hypotheses],
+            min_probability : builder.min_probability,
+        }
+    }
+}
+
+impl<N : Real> MultipleModelAdaptiveEstimator<N> {
+
+    pub fn get_num_hypotheses(&self) -> usize {
+        self.hypotheses.len()
+    }
+
+    pub fn get_probabilities(&self) -> &HypothesisProbabilities<N> {
+        &self.probabilities
+    }
+
+    /// Sum of the log-likelihoods of all measurements so far, per hypothesis
+    pub fn get_log_likelihoods(&self) -> &[N] {
+        &self.log_likelihoods
+    }
+
+    /// Index of the hypothesis with the highest probability
+    pub fn get_most_probable(&self) -> usize {
+        let mut best = 0;
+        for (i, &mu) in self.probabilities.iter().enumerate() {
+            if mu > self.probabilities[best] {
+                best = i;
+            }
+        }
+        best
+    }
+
+    /// The filter of hypothesis i
+    pub fn get_filter(&self, i : usize) -> &KalmanFilter<N> {
+        &self.hypotheses[i]
+    }
+
+    /// Combined estimate
+    ///
+    /// ```math
+    ///     x = SUM_i mu_i x_i
+    ///     P = SUM_i mu_i ( P_i + (x_i - x) (x_i - x)^T )
+    /// ```
+    pub fn get_state(&self) -> (StateVector<N>, CovarianceMatrix<N>) {
+        let n = self.hypotheses[0].get_num_states();
+        let mut vec_x = DVector::zeros(n);
+        for (filter, &mu) in self.hypotheses.iter().zip(self.probabilities.iter()) {
+            vec_x += &filter.get_state().vec_state.0 * mu;
+        }
+        let mut mat_p = DMatrix::zeros(n, n);
+        for (filter, &mu) in self.hypotheses.iter().zip(self.probabilities.iter()) {
+            let state = filter.get_state();
+            let vec_dx = &state.vec_state.0 - &vec_x;
+            mat_p += (&state.mat_covariances.0 + &vec_dx * vec_dx.transpose()) * mu;
+        }
+        (StateVector(vec_x), CovarianceMatrix(mat_p))
+    }
+
+    pub fn predict(&mut self, u : &InputVector<N>) {
+        for filter in self.hypotheses.iter_mut() {
+            filter.predict(u);
+        }
+    }
+
+    /// Returns the innovation of every hypothesis
+    pub fn measure(&mut self,
+                   y : Measurement<N>,
+                   rvec_c : MeasurementMatrixRow<N>,
+                   r : MeasurementNoiseVariance<N>)
+                -> Vec<HypothesisInnovation<N>> {
+        let innovations : Vec<_> = self.hypotheses.iter_mut()
+            .map(|filter| {
+                let update = filter.measure(Measurement(y.0), rvec_c.clone(),
+                                            MeasurementNoiseVariance(r.0));
+                HypothesisInnovation {
+                    log_likelihood : update.log_likelihood(),
+                    vec_innovation : update.vec_innovation,
+                    mat_s : update.mat_s,
+                }
+            })
+            .collect();
+        self.update_probabilities(&innovations);
+        innovations
+    }
+
+    /// Returns the innovation of every hypothesis
+    pub fn measure_vector(&mut self,
+                          vec_y : MeasurementVector<N>,
+                          mat_c : MeasurementMatrix<N>,
+                          mat_r : MeasurementNoiseCovarianceMatrix<N>)
+                       -> Vec<HypothesisInnovation<N>> {
+        let innovations : Vec<_> = self.hypotheses.iter_mut()
+            .map(|filter| {
+                let update = filter.measure_vector(vec_y.clone(), mat_c.clone(), mat_r.clone());
+                HypothesisInnovation {
+                    log_likelihood : update.log_likelihood(),
+                    vec_innovation : update.vec_innovation,
+                    mat_s : update.mat_s,
+                }
+            })
+            .collect();
+        self.update_probabilities(&innovations);
+        innovations
+    }
+
+    fn update_probabilities(&mut self, innovations : &[HypothesisInnovation<N>]) {
+        let log_likelihoods : Vec<_> = innovations.iter().map(|i| i.log_likelihood).collect();
+        for (sum, &l) in self.log_likelihoods.iter_mut().zip(log_likelihoods.iter()) {
+            *sum += l;
+        }
+        update_probabilities(&mut self.probabilities.0, &log_likelihoods);
+
+        if self.min_probability > N::zero() {
+            let min_probability = self.min_probability;
+            self.probabilities.0.apply(|mu| mu.max(min_probability));
+            let sum = self.probabilities.iter().fold(N::zero(), |acc, &mu| acc + mu);
+            self.probabilities.0 /= sum;
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use std::convert::From;
+
+use alga::general::Real;
+use na::{DMatrix, DVector, convert};
+use rand::{Rng, StdRng, SeedableRng};
+use rand::distributions::{Normal, IndependentSample};
+
+use srkf::square_root;
+use nt::{StateVector, CovarianceMatrix, InputVector, MeasurementVector, ParticleMatrix,
+         ParticleWeights};
+
+
+/// Draws x_{k+1} ~ p( x_{k+1} | x_k, u_k )
+pub type ProcessSampler<N> = Box<Fn(&StateVector<N>, &InputVector<N>, &mut StdRng) -> StateVector<N>>;
+/// p( y_k | x_k ), up to a constant factor
+pub type MeasurementLikelihood<N> = Box<Fn(&StateVector<N>, &MeasurementVector<N>) -> N>;
+/// A point prediction of x_{k+1} given x_k and u_k, typically E[ x_{k+1} | x_k, u_k ]
+pub type PointPrediction<N> = Box<Fn(&StateVector<N>, &InputVector<N>) -> StateVector<N>>;
+
+/// Resampling scheme
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resampling {
+    /// Independent draws from the weight distribution
+    Multinomial,
+    /// One draw per stratum [i/N, (i+1)/N)
+    Stratified,
+    /// One random offset shared by all strata [i/N, (i+1)/N). Lowest variance in practice.
+    Systematic,
+    /// floor(N w_i) deterministic copies, the remainder is drawn multinomially
+    Residual,
+}
+
+impl Resampling {
+    /// Returns the indices of the particles to keep (sorted, with repetitions). The weights must
+    /// be normalized.
+    pub fn indices<N : Real>(&self, weights : &ParticleWeights<N>, rng : &mut StdRng) -> Vec<usize> {
+        let n = weights.len();
+        let n_real : N = convert(n as f64);
+        match *self {
+            Resampling::Multinomial => {
+                let mut vec_u : Vec<N> = (0..n).map(|_| convert(rng.gen::<f64>())).collect();
+                vec_u.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                select_sorted(&weights.0, &vec_u)
+            },
+            Resampling::Stratified => {
+                let vec_u : Vec<N> = (0..n).map(|i| {
+                    (convert::<f64, N>(i as f64 + rng.gen::<f64>())) / n_real
+                }).collect();
+                select_sorted(&weights.0, &vec_u)
+            },
+            Resampling::Systematic => {
+                let offset = rng.gen::<f64>();
+                let vec_u : Vec<N> = (0..n).map(|i| {
+                    (convert::<f64, N>(i as f64 + offset)) / n_real
+                }).collect();
+                select_sorted(&weights.0, &vec_u)
+            },
+            Resampling::Residual => {
+                let mut indices = Vec::with_capacity(n);
+                let mut vec_residual = weights.0.clone();
+                for i in 0..n {
+                    let copies = (weights[i] * n_real).floor();
+                    vec_residual[i] = weights[i] * n_real - copies;
+                    let copies : f64 = copies.to_subset().expect("Weights must be finite");
+                    for _ in 0..(copies as usize) {
+                        indices.push(i);
+                    }
+                }
+                let num_remaining = n - indices.len();
+                if num_remaining > 0 {
+                    let sum = vec_residual.iter().fold(N::zero(), |acc, &w| acc + w);
+                    let vec_residual = vec_residual / sum;
+                    let mut vec_u : Vec<N> = (0..num_remaining).map(|_| convert(rng.gen::<f64>())).collect();
+                    vec_u.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                    indices.extend(select_sorted(&vec_residual, &vec_u));
+                    indices.sort();
+                }
+                indices
+            },
+        }
+    }
+}
+
+/// Inverse CDF lookup of sorted uniforms u in [0, 1)
+fn select_sorted<N : Real>(vec_weights : &DVector<N>, vec_u : &[N]) -> Vec<usize> {
+    let n = vec_weights.len();
+    let mut indices = Vec::with_capacity(vec_u.len());
+    let mut i = 0;
+    let mut cumulative = vec_weights[0];
+    for &u in vec_u {
+        // The last particle also catches rounding errors of the cumulative sum
+        while u >= cumulative && i < n - 1 {
+            i += 1;
+            cumulative += vec_weights[i];
+        }
+        indices.push(i);
+    }
+    indices
+}
+
+/// Particle filter
+///
+/// Represents the state distribution by weighted particles (the columns of a `ParticleMatrix`).
+/// Without a point prediction it is the bootstrap filter: particles are propagated by
+/// sampling the process model and weighted by the measurement likelihood. With a point
+/// prediction, `step()` performs the auxiliary particle filter update (Pitt and Shephard),
+/// which preselects the particles that are likely to explain the next measurement.
+///
+/// After each weighting the particles are resampled if the effective sample size
+/// 1 / sum( w_i^2 ) falls below `resampling_threshold * N`.
+pub struct ParticleFilter<N : Real>
+{
+    num_states : usize,
+    num_inputs : usize,
+    fn_sample_f : ProcessSampler<N>,
+    fn_likelihood : MeasurementLikelihood<N>,
+    fn_point_prediction : Option<PointPrediction<N>>,
+    resampling : Resampling,
+    resampling_threshold : N,
+    particles : ParticleMatrix<N>,
+    weights : ParticleWeights<N>,
+    rng : StdRng,
+}
+
+pub struct ParticleFilterBuilder<N : Real>
+{
+    num_states : usize,
+    num_inputs : usize,
+    fn_sample_f : ProcessSampler<N>,
+    fn_likelihood : MeasurementLikelihood<N>,
+    fn_point_prediction : Option<PointPrediction<N>>,
+    resampling : Resampling,
+    resampling_threshold : N,
+    num_particles : usize,
+    seed : usize,
+    vec_state : StateVector<N>,
+    mat_p : CovarianceMatrix<N>,
+    particles : Option<ParticleMatrix<N>>,
+}
+
+pub struct ParticleMeasurementUpdate<N : Real> {
+    /// Effective sample size after weighting, before a possible resampling
+    pub effective_sample_size : N,
+    pub resampled : bool,
+    /// The likelihoods of all particles were zero (e.g. underflow for a measurement far off the
+    /// particles), so the weights were reset to uniform weights
+    pub weights_vanished : bool,
+}
+
+impl<N : Real> ParticleFilterBuilder<N> {
+    /// Defaults to `f(x, u) = x` without noise, a constant likelihood, systematic resampling
+    /// at an effective sample size below N/2, 1000 particles and seed 0.
+    pub fn with_numstates_and_numinputs(num_states : usize, num_inputs : usize) -> ParticleFilterBuilder<N> {
+        ParticleFilterBuilder {
+            num_states : num_states,
+            num_inputs : num_inputs,
+            fn_sample_f : Box::new(|x, _, _| x.clone()),
+            fn_likelihood : Box::new(|_, _| N::one()),
+            fn_point_prediction : None,
+            resampling : Resampling::Systematic,
+            resampling_threshold : convert(0.5),
+            num_particles : 1000,
+            seed : 0,
+            vec_state : StateVector(DVector::zeros(num_states)),
+            mat_p : CovarianceMatrix(DMatrix::identity(num_states, num_states)),
+            particles : None,
+        }
+    }
+
+    pub fn with_process_sampler<F>(mut self, fn_sample_f : F) -> Self
+        where F : Fn(&StateVector<N>, &InputVector<N>, &mut StdRng) -> StateVector<N> + 'static {
+        self.fn_sample_f = Box::new(fn_sample_f);
+        self
+    }
+
+    pub fn with_measurement_likelihood<L>(mut self, fn_likelihood : L) -> Self
+        where L : Fn(&StateVector<N>, &MeasurementVector<N>) -> N + 'static {
+        self.fn_likelihood = Box::new(fn_likelihood);
+        self
+    }
+
+    /// Turns `step()` into the auxiliary particle filter update
+    pub fn with_point_prediction<G>(mut self, fn_point_prediction : G) -> Self
+        where G : Fn(&StateVector<N>, &InputVector<N>) -> StateVector<N> + 'static {
+        self.fn_point_prediction = Some(Box::new(fn_point_prediction));
+        self
+    }
+
+    pub fn with_resampling(mut self, resampling : Resampling) -> Self {
+        self.resampling = resampling;
+        self
+    }
+
+    /// Resample if the effective sample size is below `threshold * N`. 1 resamples after every
+    /// measurement, 0 never.
+    pub fn with_resampling_threshold(mut self, threshold : N) -> Self {
+        assert!(threshold >= N::zero() && threshold <= N::one());
+        self.resampling_threshold = threshold;
+        self
+    }
+
+    /// Number of particles, used when sampling the initial particles
+    pub fn with_num_particles(mut self, num_particles : usize) -> Self {
+        assert!(num_particles >= 1);
+        self.num_particles = num_particles;
+        self
+    }
+
+    /// Seed of the random number generator, which is also handed to the process sampler
+    pub fn with_seed(mut self, seed : usize) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// The initial particles are sampled from N(x, P)
+    pub fn with_initial_state(mut self, vec_state : StateVector<N>, mat_covariances : CovarianceMatrix<N>) -> Self {
+        assert_eq!(self.num_states, vec_state.len());
+        assert_eq!(self.num_states, mat_covariances.ncols());
+        assert_eq!(self.num_states, mat_covariances.nrows());
+        self.vec_state = vec_state;
+        self.mat_p = mat_covariances;
+        self.particles = None;
+        self
+    }
+
+    /// Uses the given particles (one per column, equally weighted) instead of sampling them
+    pub fn with_initial_particles(mut self, particles : ParticleMatrix<N>) -> Self {
+        assert_eq!(self.num_states, particles.nrows());
+        assert!(particles.ncols() >= 1);
+        self.num_particles = particles.ncols();
+        self.particles = Some(particles);
+        self
+    }
+}
+
+impl<N : Real> From<ParticleFilterBuilder<N>> for ParticleFilter<N> {
+    fn from(builder : ParticleFilterBuilder<N>) -> ParticleFilter<N> {
+        let mut rng = StdRng::from_seed(&[builder.seed][..]);
+        let num_particles = builder.num_particles;
+        let particles = match builder.particles {
+            Some(particles) => particles,
+            None => {
+                let normal = Normal::new(0.0, 1.0);
+                let mut mat_z = DMatrix::zeros(builder.num_states, num_particles);
+                for i in 0..builder.num_states {
+                    for j in 0..num_particles {
+                        mat_z[(i, j)] = convert(normal.ind_sample(&mut rng));
+                    }
+                }
+                let mut mat_x = square_root(&builder.mat_p.0) * mat_z;
+                for i in 0..num_particles {
+                    let vec_x_i = mat_x.column(i) + &builder.vec_state.0;
+                    mat_x.set_column(i, &vec_x_i);
+                }
+                ParticleMatrix(mat_x)
+            }
+        };
+        ParticleFilter {
+            num_states : builder.num_states,
+            num_inputs : builder.num_inputs,
+            fn_sample_f : builder.fn_sample_f,
+            fn_likelihood : builder.fn_likelihood,
+            fn_point_prediction : builder.fn_point_prediction,
+            resampling : builder.resampling,
+            resampling_threshold : builder.resampling_threshold,
+            particles : particles,
+            weights : uniform_weights(num_particles),
+            rng : rng,
+        }
+    }
+}
+
+impl<N : Real> ParticleFilter<N> {
+
+    /// The particles, one per column
+    pub fn get_particles(&self) -> &ParticleMatrix<N> {
+        &self.particles
+    }
+
+    /// Normalized weights of the particles
+    pub fn get_weights(&self) -> &ParticleWeights<N> {
+        &self.weights
+    }
+
+    /// Weighted mean and covariance of the particles
+    pub fn get_state(&self) -> (StateVector<N>, CovarianceMatrix<N>) {
+        let vec_mean = &self.particles.0 * &self.weights.0;
+        let mut mat_p = DMatrix::zeros(self.num_states, self.num_states);
+        for i in 0..self.particles.ncols() {
+            let vec_dx = self.particles.column(i) - &vec_mean;
+            mat_p += &vec_dx * vec_dx.transpose() * self.weights[i];
+        }
+        (StateVector(vec_mean), CovarianceMatrix(mat_p))
+    }
+
+    /// 1 / sum( w_i^2 ), between 1 and N
+    pub fn effective_sample_size(&self) -> N {
+        self.weights.iter().fold(N::zero(), |acc, &w| acc + w * w).recip()
+    }
+
+    /// Propagates every particle by sampling the process model
+    pub fn predict<'a>(&'a mut self, u : &InputVector<N>) -> &'a ParticleMatrix<N> {
+        assert_eq!(self.num_inputs, u.0.len());
+        for i in 0..self.particles.ncols() {
+            let vec_x = (self.fn_sample_f)(&StateVector(self.particles.column(i).clone_owned()),
+                                           u, &mut self.rng);
+            assert_eq!(self.num_states, vec_x.len());
+            self.particles.set_column(i, &vec_x.0);
+        }
+        &self.particles
+    }
+
+    /// Weights the particles by the measurement likelihood and resamples if necessary
+    pub fn measure(&mut self, vec_y : &MeasurementVector<N>) -> ParticleMeasurementUpdate<N> {
+        for i in 0..self.particles.ncols() {
+            let likelihood = (self.fn_likelihood)(&StateVector(self.particles.column(i).clone_owned()),
+                                                  vec_y);
+            self.weights[i] *= likelihood;
+        }
+        let weights_vanished = !normalize(&mut self.weights);
+        self.resample_if_needed(weights_vanished)
+    }
+
+    /// Bootstrap `predict()` and `measure()`, or the auxiliary particle filter update if a point
+    /// prediction is configured.
+    pub fn step(&mut self, u : &InputVector<N>, vec_y : &MeasurementVector<N>) -> ParticleMeasurementUpdate<N> {
+        if self.fn_point_prediction.is_none() {
+            self.predict(u);
+            return self.measure(vec_y);
+        }
+        assert_eq!(self.num_inputs, u.0.len());
+        let m = self.particles.ncols();
+
+        // First stage: w_i p( y | mu_i ) with the point predictions mu_i
+        let mut vec_first_stage = DVector::zeros(m);
+        {
+            let fn_point_prediction = self.fn_point_prediction.as_ref().unwrap();
+            for i in 0..m {
+                let vec_mu = fn_point_prediction(&StateVector(self.particles.column(i).clone_owned()), u);
+                vec_first_stage[i] = (self.fn_likelihood)(&vec_mu, vec_y);
+            }
+        }
+        let mut weights = ParticleWeights(self.weights.component_mul(&vec_first_stage));
+        if !normalize(&mut weights) {
+            // No point prediction explains the measurement, select by the current weights
+            // as the bootstrap filter does
+            weights = self.weights.clone();
+            vec_first_stage.fill(N::one());
+        }
+        let indices = self.resampling.indices(&weights, &mut self.rng);
+
+        // Propagate the selected particles, second stage weights p( y | x_i ) / p( y | mu_k(i) )
+        let mut mat_x = DMatrix::zeros(self.num_states, m);
+        for (i, &k) in indices.iter().enumerate() {
+            let vec_x = (self.fn_sample_f)(&StateVector(self.particles.column(k).clone_owned()),
+                                           u, &mut self.rng);
+            assert_eq!(self.num_states, vec_x.len());
+            // Rounding in the resampling can select a particle with zero first stage weight
+            self.weights[i] = if vec_first_stage[k] > N::zero() {
+                (self.fn_likelihood)(&vec_x, vec_y) / vec_first_stage[k]
+            } else {
+                N::zero()
+            };
+            mat_x.set_column(i, &vec_x.0);
+        }
+        self.particles.0 = mat_x;
+        let weights_vanished = !normalize(&mut self.weights);
+        self.resample_if_needed(weights_vanished)
+    }
+
+    /// Resamples unconditionally. All weights are equal afterwards.
+    pub fn resample(&mut self) {
+        let indices = self.resampling.indices(&self.weights, &mut self.rng);
+        let mut mat_x = DMatrix::zeros(self.num_states, indices.len());
+        for (i, &k) in indices.iter().enumerate() {
+            mat_x.set_column(i, &self.particles.column(k).clone_owned());
+        }
+        self.particles.0 = mat_x;
+        self.weights = uniform_weights(indices.len());
+    }
+
+    fn resample_if_needed(&mut self, weights_vanished : bool) -> ParticleMeasurementUpdate<N> {
+        let effective_sample_size = self.effective_sample_size();
+        let num_particles : N = convert(self.particles.ncols() as f64);
+        let resampled = effective_sample_size < self.resampling_threshold * num_particles;
+        if resampled {
+            self.resample();
+        }
+        ParticleMeasurementUpdate {
+            effective_sample_size : effective_sample_size,
+            resampled : resampled,
+            weights_vanished : weights_vanished,
+        }
+    }
+}
+
+fn uniform_weights<N : Real>(num_particles : usize) -> ParticleWeights<N> {
+    ParticleWeights(DVector::from_element(num_particles, convert::<f64, N>(num_particles as f64).recip()))
+}
+
+/// Returns false if all weights are zero, which are then replaced by uniform weights
+fn normalize<N : Real>(weights : &mut ParticleWeights<N>) -> bool {
+    let sum = weights.iter().fold(N::zero(), |acc, &w| acc + w);
+    if sum > N::zero() {
+        weights.0 /= sum;
+        true
+    } else {
+        *weights = uniform_weights(weights.len());
+        false
+    }
+}
@@ -0,0 +1,170 @@
+use std::convert::From;
+
+use alga::general::Real;
+use na::DMatrix;
+
+use kf::{KalmanFilter, KalmanFilterBuilder, BorrowedSystemState, MeasurementUpdate};
+use nt::{DiscreteSystemMatrix, StateVector, CovarianceMatrix, InputVector, Measurement,
+         MeasurementMatrixRow, MeasurementNoiseVariance, MeasurementVector, MeasurementMatrix,
+         MeasurementNoiseCovarianceMatrix};
+
+
+/// Rauch-Tung-Striebel fixed-interval smoother
+///
+/// Runs a `KalmanFilter` forward and records the prior x_k|k-1, P_k|k-1, the posterior
+/// x_k|k, P_k|k and the F used for every timestep. `smooth()` then runs the backward pass
+///
+/// ```math
+///     C_k    = P_k|k F^T P_k+1|k^-1
+///     x_k|K  = x_k|k + C_k ( x_k+1|K - x_k+1|k )
+///     P_k|K  = P_k|k + C_k ( P_k+1|K - P_k+1|k ) C_k^T
+/// ```
+///
+/// The lag-one cross covariances follow as P_k+1,k|K = P_k+1|K C_k^T.
+///
+/// Timestep 0 is the initial state of the filter, every `predict()` starts a new timestep.
+/// Any number of measurements (including none) can be processed per timestep.
+pub struct RauchTungStriebelSmoother<N : Real>
+{
+    filter : KalmanFilter<N>,
+    steps : Vec<RecordedStep<N>>,
+}
+
+struct RecordedStep<N : Real>
+{
+    /// F from the previous timestep to this one, `None` for timestep 0
+    mat_f : Option<DiscreteSystemMatrix<N>>,
+    vec_prior : StateVector<N>,
+    mat_p_prior : CovarianceMatrix<N>,
+    vec_posterior : StateVector<N>,
+    mat_p_posterior : CovarianceMatrix<N>,
+}
+
+pub struct SmoothedState<N : Real> {
+    pub vec_state : StateVector<N>,
+    pub mat_covariances : CovarianceMatrix<N>,
+    /// P_k+1,k|K = E[ (x_k+1 - x_k+1|K) (x_k - x_k|K)^T ], `None` for the last timestep
+    pub mat_cross_covariances : Option<DMatrix<N>>,
+}
+
+impl<N : Real> From<KalmanFilter<N>> for RauchTungStriebelSmoother<N> {
+    fn from(filter : KalmanFilter<N>) -> RauchTungStriebelSmoother<N> {
+        let step = {
+            let state = filter.get_state();
+            RecordedStep {
+                mat_f : None,
+                vec_prior : state.vec_state.clone(),
+                mat_p_prior : state.mat_covariances.clone(),
+                vec_posterior : state.vec_state.clone(),
+                mat_p_posterior : state.mat_covariances.clone(),
+            }
+        };
+        RauchTungStriebelSmoother {
+            filter : filter,
+            steps : vec![step],
+        }
+    }
+}
+
+impl<N : Real> From<KalmanFilterBuilder<N>> for RauchTungStriebelSmoother<N> {
+    fn from(builder : KalmanFilterBuilder<N>) -> RauchTungStriebelSmoother<N> {
+        KalmanFilter::from(builder).into()
+    }
+}
+
+impl<N : Real> RauchTungStriebelSmoother<N> {
+
+    /// The filtered state of the last timestep
+    pub fn get_state<'a>(&'a self) -> BorrowedSystemState<'a, N> {
+        self.filter.get_state()
+    }
+
+    /// Number of recorded timesteps, including the initial state
+    pub fn get_num_steps(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn predict<'a>(&'a mut self, u : &InputVector<N>) -> BorrowedSystemState<'a, N> {
+        self.filter.predict(u);
+        let state = self.filter.get_state();
+        self.steps.push(RecordedStep {
+            mat_f : Some(self.filter.get_system_matrix().clone()),
+            vec_prior : state.vec_state.clone(),
+            mat_p_prior : state.mat_covariances.clone(),
+            vec_posterior : state.vec_state.clone(),
+            mat_p_posterior : state.mat_covariances.clone(),
+        });
+        state
+    }
+
+    pub fn measure<'a>(&'a mut self,
+                       y : Measurement<N>,
+                       rvec_c : MeasurementMatrixRow<N>,
+                       r : MeasurementNoiseVariance<N>)
+                    -> MeasurementUpdate<'a, N> {
+        let update = self.filter.measure(y, rvec_c, r);
+        record_posterior(&mut self.steps, &update);
+        update
+    }
+
+    pub fn measure_vector<'a>(&'a mut self,
+                              vec_y : MeasurementVector<N>,
+                              mat_c : MeasurementMatrix<N>,
+                              mat_r : MeasurementNoiseCovarianceMatrix<N>)
+                           -> MeasurementUpdate<'a, N> {
+        let update = self.filter.measure_vector(vec_y, mat_c, mat_r);
+        record_posterior(&mut self.steps, &update);
+        update
+    }
+
+    /// Runs the backward pass over all recorded timesteps and returns the smoothed states,
+    /// starting with timestep 0. The recording is kept, so the forward pass can be continued.
+    pub fn smooth(&self) -> Vec<SmoothedState<N>> {
+        let num_steps = self.steps.len();
+        let mut smoothed = Vec::with_capacity(num_steps);
+        let last = &self.steps[num_steps - 1];
+        smoothed.push(SmoothedState {
+            vec_state : last.vec_posterior.clone(),
+            mat_covariances : last.mat_p_posterior.clone(),
+            mat_cross_covariances : None,
+        });
+
+        for k in (0..(num_steps - 1)).rev() {
+            let step = &self.steps[k];
+            let next = &self.steps[k + 1];
+            let mat_f = next.mat_f.as_ref().unwrap();
+            let (vec_x, mat_p, mat_p_cross) = {
+                let next_smoothed = smoothed.last().unwrap();
+
+                // C = P_k|k F^T P_k+1|k^-1, calculated as solution of  P_k+1|k C^T = F P_k|k
+                let mat_c = next.mat_p_prior.0.clone()
+                                .cholesky()
+                                .expect("Predicted covariance P is not positive definite")
+                                .solve(&(&mat_f.0 * &step.mat_p_posterior.0))
+                                .transpose();
+
+                let vec_x = &step.vec_posterior.0
+                          + &mat_c * (&next_smoothed.vec_state.0 - &next.vec_prior.0);
+                let mat_p = &step.mat_p_posterior.0
+                          + &mat_c * (&next_smoothed.mat_covariances.0 - &next.mat_p_prior.0)
+                                   * mat_c.transpose();
+                let mat_p_cross = &next_smoothed.mat_covariances.0 * mat_c.transpose();
+                (vec_x, mat_p, mat_p_cross)
+            };
+            smoothed.push(SmoothedState {
+                vec_state : StateVector(vec_x),
+                mat_covariances : CovarianceMatrix(mat_p),
+                mat_cross_covariances : Some(mat_p_cross),
+            });
+        }
+
+        smoothed.reverse();
+        smoothed
+    }
+}
+
+fn record_posterior<N : Real>(steps : &mut Vec<RecordedStep<N>>, update : &MeasurementUpdate<N>) {
+    let step = steps.last_mut().unwrap();
+    step.vec_posterior = update.vec_state.clone();
+    step.mat_p_posterior = update.mat_covariances.clone();
+}
@@ -0,0 +1,155 @@
+use std::convert::From;
+
+use alga::general::Real;
+use na::{DMatrix, DVector, RowDVector};
+
+use kf::{KalmanFilter, KalmanFilterBuilder, BorrowedSystemState, MeasurementUpdate};
+use nt::{StateVector, CovarianceMatrix, InputVector, Measurement, MeasurementMatrixRow,
+         MeasurementNoiseVariance, MeasurementVector, MeasurementNoiseCovarianceMatrix};
+
+
+/// Stochastic Cloning Kalman Filter
+///
+/// Relative measurements (e.g. odometry) relate the current state x_k to the state x_l of
+/// some earlier timestep l:
+///
+/// ```math
+///     y  =  C_k x_k  +  C_l x_l  +  r
+/// ```
+///
+/// For this, the state and covariance at timestep l are cloned into the augmented state
+///
+/// ```math
+///     [ x_k ]          [ P_kk  P_kl ]
+///     [ x_l ]   with   [ P_lk  P_ll ]
+/// ```
+///
+/// The augmented system is never formed as a whole. Since the clone does not change during
+/// predict, only P_kk (like `KalmanFilter`) and the cross covariance P_kl = F P_kl have to be
+/// propagated.
+pub struct StochasticCloningKalmanFilter<N : Real>
+{
+    filter : KalmanFilter<N>,
+    clone : Option<ClonedState<N>>,
+}
+
+struct ClonedState<N : Real>
+{
+    vec_state : StateVector<N>,
+    mat_p : CovarianceMatrix<N>,
+    /// P_kl, covariance between current state (rows) and cloned state (columns)
+    mat_p_cross : DMatrix<N>,
+}
+
+impl<N : Real> From<KalmanFilter<N>> for StochasticCloningKalmanFilter<N> {
+    fn from(filter : KalmanFilter<N>) -> StochasticCloningKalmanFilter<N> {
+        StochasticCloningKalmanFilter {
+            filter : filter,
+            clone : None,
+        }
+    }
+}
+
+impl<N : Real> From<KalmanFilterBuilder<N>> for StochasticCloningKalmanFilter<N> {
+    fn from(builder : KalmanFilterBuilder<N>) -> StochasticCloningKalmanFilter<N> {
+        KalmanFilter::from(builder).into()
+    }
+}
+
+impl<N : Real> StochasticCloningKalmanFilter<N> {
+
+    /// Clones the current state. An already existing clone is replaced.
+    pub fn clone_state(&mut self) {
+        let state = self.filter.get_state();
+        self.clone = Some(ClonedState {
+            vec_state : state.vec_state.clone(),
+            mat_p : state.mat_covariances.clone(),
+            mat_p_cross : state.mat_covariances.0.clone(),
+        });
+    }
+
+    pub fn drop_clone(&mut self) {
+        self.clone = None;
+    }
+
+    pub fn has_clone(&self) -> bool {
+        self.clone.is_some()
+    }
+
+    pub fn get_state<'a>(&'a self) -> BorrowedSystemState<'a, N> {
+        self.filter.get_state()
+    }
+
+    pub fn get_clone<'a>(&'a self) -> Option<BorrowedSystemState<'a, N>> {
+        self.clone.as_ref().map(|clone| {
+            BorrowedSystemState {
+                vec_state : &clone.vec_state,
+                mat_covariances : &clone.mat_p,
+            }
+        })
+    }
+
+    pub fn predict<'a>(&'a mut self, u : &InputVector<N>) -> BorrowedSystemState<'a, N> {
+        if let Some(ref mut clone) = self.clone {
+            // P_kl = F P_kl
+            clone.mat_p_cross = &self.filter.get_system_matrix().0 * &clone.mat_p_cross;
+        }
+        self.filter.predict(u)
+    }
+
+    /// Measurement of the current state only: y = C_k x_k + r
+    ///
+    /// If a clone exists, it is updated as well since it is correlated with the current state.
+    pub fn measure<'a>(&'a mut self,
+                       y : Measurement<N>,
+                       rvec_c : MeasurementMatrixRow<N>,
+                       r : MeasurementNoiseVariance<N>)
+                    -> MeasurementUpdate<'a, N> {
+        if self.clone.is_none() {
+            self.filter.measure(y, rvec_c, r)
+        } else {
+            let rvec_c_clone = RowDVector::zeros(rvec_c.len());
+            self.measure_augmented(y, &rvec_c.0, &rvec_c_clone, r)
+        }
+    }
+
+    /// Relative measurement: y = C_k x_k + C_l x_l + r
+    ///
+    /// Panics if no clone exists. The clone is kept, so that multiple measurement rows can
+    /// relate to the same clone. Call `drop_clone()` afterwards.
+    pub fn measure_relative<'a>(&'a mut self,
+                                y : Measurement<N>,
+                                rvec_c_current : MeasurementMatrixRow<N>,
+                                rvec_c_clone : MeasurementMatrixRow<N>,
+                                r : MeasurementNoiseVariance<N>)
+                             -> MeasurementUpdate<'a, N> {
+        assert!(self.clone.is_some(), "measure_relative() needs a cloned state");
+        self.measure_augmented(y, &rvec_c_current.0, &rvec_c_clone.0, r)
+    }
+
+    /// Update of the augmented state [x_k; x_l] with C = [ C_k C_l ]. Gating, covariance
+    /// update, consider states, log-likelihood and state constraints of the filter apply.
+    fn measure_augmented<'a>(&'a mut self,
+                             y : Measurement<N>,
+                             rvec_c_k : &RowDVector<N>,
+                             rvec_c_l : &RowDVector<N>,
+                             r : MeasurementNoiseVariance<N>)
+                          -> MeasurementUpdate<'a, N> {
+        let num_states = self.filter.get_num_states();
+        assert_eq!(num_states, rvec_c_k.len());
+        assert_eq!(num_states, rvec_c_l.len());
+
+        let mut mat_c = DMatrix::zeros(1, 2 * num_states);
+        mat_c.columns_mut(0, num_states).copy_from(rvec_c_k);
+        mat_c.columns_mut(num_states, num_states).copy_from(rvec_c_l);
+
+        let clone = self.clone.as_mut().unwrap();
+        self.filter.measure_augmented(MeasurementVector(DVector::from_element(1, y.0)),
+                                      &mat_c,
+                                      MeasurementNoiseCovarianceMatrix(
+                                          DMatrix::from_element(1, 1, r.0)),
+                                      &mut clone.vec_state.0,
+                                      &mut clone.mat_p.0,
+                                      &mut clone.mat_p_cross)
+    }
+}
@@ -0,0 +1,144 @@
+use std::convert::From;
+
+use alga::general::Real;
+use na::{DMatrix, DVector};
+
+use kf::{KalmanFilter, KalmanFilterBuilder};
+use nt::{DiscreteSystemMatrix, DiscreteInputMatrix, StateVector, CovarianceMatrix,
+         CovarianceSquareRootMatrix, InputVector, Measurement, MeasurementMatrixRow,
+         MeasurementNoiseVariance};
+
+
+/// Square Root Kalman Filter
+///
+/// Instead of P, a square root S with P = S S^T is propagated. This effectively doubles the
+/// numerical precision of the covariance and P can never lose its symmetry or positive
+/// semidefiniteness.
+///
+/// ```math
+///     Time update (QR decomposition):
+///         [ (F S)^T ]                                     S_Q S_Q^T = Q
+///         [  S_Q^T  ]  =  Q_qr R    ==>    S = R^T        (since F P F^T + Q = R^T R)
+///
+///     Measurement update (Potter):
+///         phi   = S^T C^T
+///         a     = 1 / ( phi^T phi + r )
+///         gamma = a / ( 1 + sqrt(a r) )
+///         K     = a S phi
+///         S     = S - gamma S phi phi^T
+/// ```
+pub struct SquareRootKalmanFilter<N : Real>
+{
+    num_states : usize,
+    num_inputs : usize,
+    mat_f : DiscreteSystemMatrix<N>,
+    mat_h : DiscreteInputMatrix<N>,
+    /// S_Q with S_Q S_Q^T = Q
+    mat_q_sqrt : DMatrix<N>,
+    vec_state : StateVector<N>,
+    mat_s : CovarianceSquareRootMatrix<N>,
+}
+
+pub struct BorrowedSquareRootSystemState<'a, N : Real + 'a> {
+    pub vec_state : &'a StateVector<N>,
+    pub mat_covariances_sqrt : &'a CovarianceSquareRootMatrix<N>,
+}
+
+impl<N : Real> From<KalmanFilter<N>> for SquareRootKalmanFilter<N> {
+    fn from(filter : KalmanFilter<N>) -> SquareRootKalmanFilter<N> {
+        let state = filter.get_state();
+        SquareRootKalmanFilter {
+            num_states : filter.get_num_states(),
+            num_inputs : filter.get_num_inputs(),
+            mat_f : filter.get_system_matrix().clone(),
+            mat_h : filter.get_input_matrix().clone(),
+            mat_q_sqrt : square_root(&filter.get_system_noise_variances().0),
+            vec_state : state.vec_state.clone(),
+            mat_s : CovarianceSquareRootMatrix(square_root(&state.mat_covariances.0)),
+        }
+    }
+}
+
+impl<N : Real> From<KalmanFilterBuilder<N>> for SquareRootKalmanFilter<N> {
+    fn from(builder : KalmanFilterBuilder<N>) -> SquareRootKalmanFilter<N> {
+        KalmanFilter::from(builder).into()
+    }
+}
+
+impl<N : Real> SquareRootKalmanFilter<N> {
+
+    pub fn get_state<'a>(&'a self) -> BorrowedSquareRootSystemState<'a, N> {
+        BorrowedSquareRootSystemState {
+            vec_state : &self.vec_state,
+            mat_covariances_sqrt : &self.mat_s,
+        }
+    }
+
+    /// P = S S^T
+    pub fn get_covariances(&self) -> CovarianceMatrix<N> {
+        CovarianceMatrix(&self.mat_s.0 * self.mat_s.0.transpose())
+    }
+
+    pub fn predict<'a>(&'a mut self, u : &InputVector<N>) -> BorrowedSquareRootSystemState<'a, N> {
+        assert_eq!(self.num_inputs, u.0.len());
+        let n = self.num_states;
+        self.vec_state = StateVector( &self.mat_f.0 * &self.vec_state.0 + &self.mat_h.0 * &u.0 );
+
+        let mut mat_m = DMatrix::zeros(2 * n, n);
+        mat_m.rows_mut(0, n).copy_from(&(&self.mat_f.0 * &self.mat_s.0).transpose());
+        mat_m.rows_mut(n, n).copy_from(&self.mat_q_sqrt.transpose());
+        self.mat_s = CovarianceSquareRootMatrix(mat_m.qr().unpack_r().transpose());
+
+        BorrowedSquareRootSystemState {
+            vec_state : &self.vec_state,
+            mat_covariances_sqrt : &self.mat_s,
+        }
+    }
+
+    pub fn measure<'a>(&'a mut self,
+                       y : Measurement<N>,
+                       rvec_c : MeasurementMatrixRow<N>,
+                       r : MeasurementNoiseVariance<N>)
+                    -> BorrowedSquareRootSystemState<'a, N> {
+
+        assert_eq!(self.num_states, rvec_c.0.len());
+
+        // phi = S^T C^T
+        let vec_phi : DVector<N> = self.mat_s.0.transpose() * rvec_c.0.transpose();
+
+        // a = 1 / ( phi^T phi + r ) = 1 / S_innovation
+        let a = (vec_phi.dot(&vec_phi) + r.0).recip();
+        let gamma = a / (N::one() + (a * r.0).sqrt());
+
+        // K = a S phi
+        let vec_s_phi : DVector<N> = &self.mat_s.0 * &vec_phi;
+        let vec_k = &vec_s_phi * a;
+
+        // residual = y - C x
+        let residual = y.0 - (&rvec_c.0 * &self.vec_state.0)[(0, 0)];
+
+        // x = x + K residual
+        self.vec_state.0 += &vec_k * residual;
+
+        // S = S - gamma S phi phi^T
+        self.mat_s.0 -= &vec_s_phi * vec_phi.transpose() * gamma;
+
+        BorrowedSquareRootSystemState {
+            vec_state : &self.vec_state,
+            mat_covariances_sqrt : &self.mat_s,
+        }
+    }
+}
+
+/// Returns a matrix A with A A^T = M for a symmetric positive semidefinite M.
+///
+/// This is the lower Cholesky factor if it exists. Semidefinite matrices (e.g. Q = 0) fall back
+/// to A = V sqrt(D) with the eigendecomposition M = V D V^T.
+pub(crate) fn square_root<N : Real>(mat_m : &DMatrix<N>) -> DMatrix<N> {
+    if let Some(chol) = mat_m.clone().cholesky() {
+        return chol.unpack();
+    }
+    let eigen = mat_m.clone().symmetric_eigen();
+    let vec_sqrt_d = eigen.eigenvalues.map(|l| l.max(N::zero()).sqrt());
+    eigen.eigenvectors * DMatrix::from_diagonal(&vec_sqrt_d)
+}
@@ -0,0 +1,204 @@
+use std::convert::From;
+
+use alga::general::Real;
+use na::{DMatrix, DVector};
+
+use kf::{KalmanFilter, KalmanFilterBuilder};
+use nt::{DiscreteSystemMatrix, DiscreteInputMatrix, StateVector, CovarianceMatrix,
+         CovarianceFactorU, CovarianceFactorD, InputVector, Measurement, MeasurementMatrixRow,
+         MeasurementNoiseVariance};
+
+
+/// UD factorized Kalman Filter (Bierman-Thornton)
+///
+/// The covariance is stored as P = U D U^T with a unit upper triangular U and a diagonal D.
+/// The time update uses Thornton's modified weighted Gram-Schmidt orthogonalization, the
+/// scalar measurement update is Bierman's algorithm. Both never form P.
+pub struct UDKalmanFilter<N : Real>
+{
+    num_states : usize,
+    num_inputs : usize,
+    mat_f : DiscreteSystemMatrix<N>,
+    mat_h : DiscreteInputMatrix<N>,
+    /// Q = U_Q D_Q U_Q^T
+    mat_q_u : DMatrix<N>,
+    vec_q_d : DVector<N>,
+    vec_state : StateVector<N>,
+    mat_u : CovarianceFactorU<N>,
+    vec_d : CovarianceFactorD<N>,
+}
+
+pub struct BorrowedUDSystemState<'a, N : Real + 'a> {
+    pub vec_state : &'a StateVector<N>,
+    pub mat_u : &'a CovarianceFactorU<N>,
+    pub vec_d : &'a CovarianceFactorD<N>,
+}
+
+impl<N : Real> From<KalmanFilter<N>> for UDKalmanFilter<N> {
+    fn from(filter : KalmanFilter<N>) -> UDKalmanFilter<N> {
+        let (mat_q_u, vec_q_d) = ud_factorize(&filter.get_system_noise_variances().0);
+        let state = filter.get_state();
+        let (mat_u, vec_d) = ud_factorize(&state.mat_covariances.0);
+        UDKalmanFilter {
+            num_states : filter.get_num_states(),
+            num_inputs : filter.get_num_inputs(),
+            mat_f : filter.get_system_matrix().clone(),
+            mat_h : filter.get_input_matrix().clone(),
+            mat_q_u : mat_q_u,
+            vec_q_d : vec_q_d,
+            vec_state : state.vec_state.clone(),
+            mat_u : CovarianceFactorU(mat_u),
+            vec_d : CovarianceFactorD(vec_d),
+        }
+    }
+}
+
+impl<N : Real> From<KalmanFilterBuilder<N>> for UDKalmanFilter<N> {
+    fn from(builder : KalmanFilterBuilder<N>) -> UDKalmanFilter<N> {
+        KalmanFilter::from(builder).into()
+    }
+}
+
+impl<N : Real> UDKalmanFilter<N> {
+
+    pub fn get_state<'a>(&'a self) -> BorrowedUDSystemState<'a, N> {
+        BorrowedUDSystemState {
+            vec_state : &self.vec_state,
+            mat_u : &self.mat_u,
+            vec_d : &self.vec_d,
+        }
+    }
+
+    /// Replaces state and covariance. P is factorized into U D U^T.
+    pub fn set_state(&mut self, vec_state : StateVector<N>, mat_covariances : CovarianceMatrix<N>) {
+        assert_eq!(self.num_states, vec_state.len());
+        assert_eq!(self.num_states, mat_covariances.ncols());
+        assert_eq!(self.num_states, mat_covariances.nrows());
+        let (mat_u, vec_d) = ud_factorize(&mat_covariances.0);
+        self.vec_state = vec_state;
+        self.mat_u = CovarianceFactorU(mat_u);
+        self.vec_d = CovarianceFactorD(vec_d);
+    }
+
+    /// P = U D U^T
+    pub fn get_covariances(&self) -> CovarianceMatrix<N> {
+        CovarianceMatrix(&self.mat_u.0 * DMatrix::from_diagonal(&self.vec_d.0) * self.mat_u.0.transpose())
+    }
+
+    /// Thornton time update
+    pub fn predict<'a>(&'a mut self, u : &InputVector<N>) -> BorrowedUDSystemState<'a, N> {
+        assert_eq!(self.num_inputs, u.0.len());
+        let n = self.num_states;
+        self.vec_state = StateVector( &self.mat_f.0 * &self.vec_state.0 + &self.mat_h.0 * &u.0 );
+
+        // F P F^T + Q = W D_W W^T   with   W = [ F U , U_Q ]   and   D_W = diag( D, D_Q )
+        let mut mat_w = DMatrix::zeros(n, 2 * n);
+        mat_w.columns_mut(0, n).copy_from(&(&self.mat_f.0 * &self.mat_u.0));
+        mat_w.columns_mut(n, n).copy_from(&self.mat_q_u);
+        let mut vec_d_w = DVector::zeros(2 * n);
+        vec_d_w.rows_mut(0, n).copy_from(&self.vec_d.0);
+        vec_d_w.rows_mut(n, n).copy_from(&self.vec_q_d);
+
+        // Modified weighted Gram-Schmidt orthogonalization of the rows of W, last row first
+        let mat_u = &mut self.mat_u.0;
+        let vec_d = &mut self.vec_d.0;
+        mat_u.fill(N::zero());
+        for j in (0..n).rev() {
+            let rvec_w_j = mat_w.row(j).clone_owned();
+            let vec_c = rvec_w_j.transpose().component_mul(&vec_d_w);
+            vec_d[j] = (&rvec_w_j * &vec_c)[(0, 0)];
+            mat_u[(j, j)] = N::one();
+            if vec_d[j] <= N::zero() {
+                vec_d[j] = N::zero();
+                continue;
+            }
+            for i in 0..j {
+                let u_ij = (mat_w.row(i) * &vec_c)[(0, 0)] / vec_d[j];
+                mat_u[(i, j)] = u_ij;
+                let rvec_w_i = mat_w.row(i) - &rvec_w_j * u_ij;
+                mat_w.row_mut(i).copy_from(&rvec_w_i);
+            }
+        }
+
+        BorrowedUDSystemState {
+            vec_state : &self.vec_state,
+            mat_u : &self.mat_u,
+            vec_d : &self.vec_d,
+        }
+    }
+
+    /// Bierman measurement update
+    pub fn measure<'a>(&'a mut self,
+                       y : Measurement<N>,
+                       rvec_c : MeasurementMatrixRow<N>,
+                       r : MeasurementNoiseVariance<N>)
+                    -> BorrowedUDSystemState<'a, N> {
+
+        assert_eq!(self.num_states, rvec_c.0.len());
+        let n = self.num_states;
+        let mat_u = &mut self.mat_u.0;
+        let vec_d = &mut self.vec_d.0;
+
+        // f = U^T C^T,  v = D f
+        let vec_f : DVector<N> = mat_u.transpose() * rvec_c.0.transpose();
+        let vec_v : DVector<N> = vec_f.component_mul(vec_d);
+
+        // b accumulates the unscaled gain, alpha the innovation variance S
+        let mut vec_b : DVector<N> = DVector::zeros(n);
+        let mut alpha = r.0;
+        for j in 0..n {
+            let alpha_prev = alpha;
+            alpha += vec_f[j] * vec_v[j];
+            vec_d[j] *= alpha_prev / alpha;
+            vec_b[j] = vec_v[j];
+            let lambda = -vec_f[j] / alpha_prev;
+            for i in 0..j {
+                let u_ij = mat_u[(i, j)];
+                mat_u[(i, j)] = u_ij + vec_b[i] * lambda;
+                vec_b[i] += u_ij * vec_v[j];
+            }
+        }
+
+        // K = b / S
+        let vec_k = vec_b / alpha;
+
+        // residual = y - C x
+        let residual = y.0 - (&rvec_c.0 * &self.vec_state.0)[(0, 0)];
+
+        // x = x + K residual
+        self.vec_state.0 += &vec_k * residual;
+
+        BorrowedUDSystemState {
+            vec_state : &self.vec_state,
+            mat_u : &self.mat_u,
+            vec_d : &self.vec_d,
+        }
+    }
+}
+
+/// Factorizes a symmetric positive semidefinite M into U D U^T, with U unit upper triangular
+/// and D diagonal (returned as vector).
+fn ud_factorize<N : Real>(mat_m : &DMatrix<N>) -> (DMatrix<N>, DVector<N>) {
+    assert_eq!(mat_m.nrows(), mat_m.ncols());
+    let n = mat_m.nrows();
+    let mut mat_u = DMatrix::identity(n, n);
+    let mut vec_d = DVector::zeros(n);
+
+    for j in (0..n).rev() {
+        let mut d_j = mat_m[(j, j)];
+        for k in (j + 1)..n {
+            d_j -= vec_d[k] * mat_u[(j, k)] * mat_u[(j, k)];
+        }
+        vec_d[j] = d_j.max(N::zero());
+
+        for i in 0..j {
+            let mut u_ij = mat_m[(i, j)];
+            for k in (j + 1)..n {
+                u_ij -= vec_d[k] * mat_u[(i, k)] * mat_u[(j, k)];
+            }
+            mat_u[(i, j)] = if vec_d[j] > N::zero() { u_ij / vec_d[j] } else { N::zero() };
+        }
+    }
+
+    (mat_u, vec_d)
+}
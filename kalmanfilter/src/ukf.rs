@@ -0,0 +1,310 @@
+use std::convert::From;
+
+use alga::general::Real;
+use na::{DMatrix, DVector, convert};
+
+use kf::BorrowedSystemState;
+use ekf::{StateFunction, OutputFunction};
+use nt::{SystemNoiseVarianceMatrix, StateVector, CovarianceMatrix, InputVector, Measurement,
+         MeasurementNoiseVariance, MeasurementVector, MeasurementNoiseCovarianceMatrix};
+
+
+/// Selection of the sigma points used for the unscented transformation
+#[derive(Clone, Debug)]
+pub enum SigmaPoints<N : Real> {
+    /// Julier's original set of 2n+1 points. `kappa = 3 - n` matches the fourth moment of a
+    /// Gaussian, but for n > 3 the negative center weight can make the covariance indefinite.
+    Julier { kappa : N },
+    /// Scaled set of 2n+1 points by van der Merwe. Typical values are `alpha = 1e-3`,
+    /// `beta = 2` (optimal for Gaussians) and `kappa = 0`.
+    MerweScaled { alpha : N, beta : N, kappa : N },
+    /// Spherical simplex set of n+2 points (Julier 2003), with the weight `w0` (0 <= w0 < 1)
+    /// of the center point.
+    Simplex { w0 : N },
+}
+
+/// Sigma points in the form of deviations from the mean (in units of sqrt(P)) and their weights
+pub(crate) struct UnitSigmaPoints<N : Real> {
+    /// Each column is one point
+    mat_points : DMatrix<N>,
+    /// Weights for the mean
+    weights_m : Vec<N>,
+    /// Weights for the covariance
+    weights_c : Vec<N>,
+}
+
+impl<N : Real> SigmaPoints<N> {
+    fn unit_points(&self, n : usize) -> UnitSigmaPoints<N> {
+        let n_real : N = convert(n as f64);
+        match *self {
+            SigmaPoints::Julier { kappa } => {
+                symmetric_unit_points(n, n_real + kappa, kappa / (n_real + kappa), N::zero())
+            },
+            SigmaPoints::MerweScaled { alpha, beta, kappa } => {
+                let lambda = alpha * alpha * (n_real + kappa) - n_real;
+                symmetric_unit_points(n, n_real + lambda, lambda / (n_real + lambda),
+                                      N::one() - alpha * alpha + beta)
+            },
+            SigmaPoints::Simplex { w0 } => {
+                let w1 = (N::one() - w0) / (n_real + N::one());
+                let mut mat_points = DMatrix::zeros(n, n + 2);
+                for j in 1..(n + 1) {
+                    let j_real : N = convert(j as f64);
+                    let denom = (j_real * (j_real + N::one()) * w1).sqrt();
+                    for i in 1..(j + 1) {
+                        mat_points[(j - 1, i)] = -N::one() / denom;
+                    }
+                    mat_points[(j - 1, j + 1)] = j_real / denom;
+                }
+                let mut weights = vec![w1; n + 2];
+                weights[0] = w0;
+                UnitSigmaPoints {
+                    mat_points : mat_points,
+                    weights_m : weights.clone(),
+                    weights_c : weights,
+                }
+            },
+        }
+    }
+}
+
+impl<N : Real> UnitSigmaPoints<N> {
+    /// Third-degree spherical-radial cubature rule: 2n points +-sqrt(n) e_i with equal weights
+    pub(crate) fn cubature(n : usize) -> UnitSigmaPoints<N> {
+        let mut points = symmetric_unit_points(n, convert(n as f64), N::zero(), N::zero());
+        points.mat_points = points.mat_points.remove_column(0);
+        points.weights_m.remove(0);
+        points.weights_c.remove(0);
+        points
+    }
+}
+
+/// Points 0, +-sqrt(scale) e_i with the center weight `w0` and `w0 + extra_w0_c` for the
+/// covariance
+fn symmetric_unit_points<N : Real>(n : usize, scale : N, w0 : N, extra_w0_c : N) -> UnitSigmaPoints<N> {
+    let two : N = convert(2.0);
+    let mut mat_points = DMatrix::zeros(n, 2 * n + 1);
+    for i in 0..n {
+        mat_points[(i, 1 + i)] = scale.sqrt();
+        mat_points[(i, 1 + n + i)] = -scale.sqrt();
+    }
+    let mut weights_m = vec![(two * scale).recip(); 2 * n + 1];
+    weights_m[0] = w0;
+    let mut weights_c = weights_m.clone();
+    weights_c[0] = w0 + extra_w0_c;
+    UnitSigmaPoints {
+        mat_points : mat_points,
+        weights_m : weights_m,
+        weights_c : weights_c,
+    }
+}
+
+pub struct UnscentedKalmanFilter<N : Real>
+{
+    num_states : usize,
+    num_inputs : usize,
+    fn_f : StateFunction<N>,
+    fn_c : OutputFunction<N>,
+    unit_points : UnitSigmaPoints<N>,
+    mat_q : SystemNoiseVarianceMatrix<N>,
+    vec_state : StateVector<N>,
+    mat_p : CovarianceMatrix<N>,
+}
+
+pub struct UnscentedKalmanFilterBuilder<N : Real>
+{
+    filter : UnscentedKalmanFilter<N>,
+}
+
+impl<N : Real> UnscentedKalmanFilterBuilder<N> {
+    /// Defaults to `f(x, u) = x`, `c(x) = x` and `SigmaPoints::Julier { kappa : max(3 - n, 0) }`.
+    pub fn with_numstates_and_numinputs(num_states : usize, num_inputs : usize) -> UnscentedKalmanFilterBuilder<N> {
+        let kappa = convert((3.0 - num_states as f64).max(0.0));
+        UnscentedKalmanFilterBuilder {
+            filter : UnscentedKalmanFilter {
+                num_states : num_states,
+                num_inputs : num_inputs,
+                fn_f : Box::new(|x, _| x.clone()),
+                fn_c : Box::new(|x| MeasurementVector(x.0.clone())),
+                unit_points : SigmaPoints::Julier { kappa : kappa }.unit_points(num_states),
+                mat_q : SystemNoiseVarianceMatrix(DMatrix::zeros(num_states, num_states)),
+                vec_state : StateVector(DVector::zeros(num_states)),
+                mat_p : CovarianceMatrix(DMatrix::identity(num_states, num_states)),
+            }
+        }
+    }
+
+    pub fn with_state_function<F>(mut self, fn_f : F) -> Self
+        where F : Fn(&StateVector<N>, &InputVector<N>) -> StateVector<N> + 'static {
+        self.filter.fn_f = Box::new(fn_f);
+        self
+    }
+
+    pub fn with_output_function<C>(mut self, fn_c : C) -> Self
+        where C : Fn(&StateVector<N>) -> MeasurementVector<N> + 'static {
+        self.filter.fn_c = Box::new(fn_c);
+        self
+    }
+
+    pub fn with_sigma_points(mut self, sigma_points : SigmaPoints<N>) -> Self {
+        self.filter.unit_points = sigma_points.unit_points(self.filter.num_states);
+        self
+    }
+
+    pub(crate) fn with_unit_sigma_points(mut self, unit_points : UnitSigmaPoints<N>) -> Self {
+        self.filter.unit_points = unit_points;
+        self
+    }
+
+    pub fn with_system_noise_variances(mut self, mat_q : SystemNoiseVarianceMatrix<N>) -> Self {
+        assert_eq!(self.filter.num_states, mat_q.ncols());
+        assert_eq!(self.filter.num_states, mat_q.nrows());
+        self.filter.mat_q = mat_q;
+        self
+    }
+
+    pub fn with_initial_state(mut self, vec_state : StateVector<N>, mat_covariances : CovarianceMatrix<N>) -> Self {
+        assert_eq!(self.filter.num_states, vec_state.len());
+        assert_eq!(self.filter.num_states, mat_covariances.ncols());
+        assert_eq!(self.filter.num_states, mat_covariances.nrows());
+        self.filter.vec_state = vec_state;
+        self.filter.mat_p = mat_covariances;
+        self
+    }
+}
+
+impl<N : Real> From<UnscentedKalmanFilterBuilder<N>> for UnscentedKalmanFilter<N> {
+    fn from(builder : UnscentedKalmanFilterBuilder<N>) -> UnscentedKalmanFilter<N> {
+        builder.filter
+    }
+}
+
+impl<N : Real> UnscentedKalmanFilter<N> {
+
+    pub fn get_state<'a>(&'a self) -> BorrowedSystemState<'a, N> {
+        BorrowedSystemState {
+            vec_state : &self.vec_state,
+            mat_covariances : &self.mat_p,
+        }
+    }
+
+    /// Sigma points x + sqrt(P) X_i, one per column
+    fn sigma_points(&self) -> DMatrix<N> {
+        let mat_sqrt_p = self.mat_p.0.clone()
+                             .cholesky()
+                             .expect("Covariance matrix P is not positive definite")
+                             .unpack();
+        let mut mat_chi = &mat_sqrt_p * &self.unit_points.mat_points;
+        for i in 0..mat_chi.ncols() {
+            let vec_chi_i = mat_chi.column(i) + &self.vec_state.0;
+            mat_chi.set_column(i, &vec_chi_i);
+        }
+        mat_chi
+    }
+
+    pub fn predict<'a>(&'a mut self, u : &InputVector<N>) -> BorrowedSystemState<'a, N> {
+        assert_eq!(self.num_inputs, u.0.len());
+        let mat_chi = self.sigma_points();
+
+        let mut mat_chi_pred = DMatrix::zeros(self.num_states, mat_chi.ncols());
+        for i in 0..mat_chi.ncols() {
+            let vec_x = (self.fn_f)(&StateVector(mat_chi.column(i).clone_owned()), u);
+            assert_eq!(self.num_states, vec_x.len());
+            mat_chi_pred.set_column(i, &vec_x.0);
+        }
+
+        let (vec_x, mat_p) = unscented_mean_and_covariance(&mat_chi_pred, &self.unit_points);
+        self.vec_state = StateVector(vec_x);
+        self.mat_p = CovarianceMatrix(mat_p + &self.mat_q.0);
+
+        BorrowedSystemState {
+            vec_state : &self.vec_state,
+            mat_covariances : &self.mat_p,
+        }
+    }
+
+    /// Processes the measurement of the single output `index` of `c(x)`.
+    pub fn measure<'a>(&'a mut self,
+                       y : Measurement<N>,
+                       index : usize,
+                       r : MeasurementNoiseVariance<N>)
+                    -> BorrowedSystemState<'a, N> {
+        self.update(&DVector::from_element(1, y.0), &DMatrix::from_element(1, 1, r.0), Some(index));
+        BorrowedSystemState {
+            vec_state : &self.vec_state,
+            mat_covariances : &self.mat_p,
+        }
+    }
+
+    /// Processes the measurement of all outputs of `c(x)` at once.
+    pub fn measure_vector<'a>(&'a mut self,
+                              vec_y : MeasurementVector<N>,
+                              mat_r : MeasurementNoiseCovarianceMatrix<N>)
+                           -> BorrowedSystemState<'a, N> {
+        self.update(&vec_y.0, &mat_r.0, None);
+        BorrowedSystemState {
+            vec_state : &self.vec_state,
+            mat_covariances : &self.mat_p,
+        }
+    }
+
+    fn update(&mut self, vec_y : &DVector<N>, mat_r : &DMatrix<N>, index : Option<usize>) {
+        let num_measurements = vec_y.len();
+        assert_eq!(num_measurements, mat_r.nrows());
+        assert_eq!(num_measurements, mat_r.ncols());
+
+        let mat_chi = self.sigma_points();
+        let mut mat_gamma = DMatrix::zeros(num_measurements, mat_chi.ncols());
+        for i in 0..mat_chi.ncols() {
+            let vec_c_x = (self.fn_c)(&StateVector(mat_chi.column(i).clone_owned()));
+            match index {
+                Some(index) => mat_gamma[(0, i)] = vec_c_x[index],
+                None => {
+                    assert_eq!(num_measurements, vec_c_x.len());
+                    mat_gamma.set_column(i, &vec_c_x.0);
+                }
+            }
+        }
+
+        let (vec_y_pred, mat_s) = unscented_mean_and_covariance(&mat_gamma, &self.unit_points);
+        // S = P_yy + R
+        let mat_s = mat_s + mat_r;
+
+        // P_xy = SUM_i ( w_i (chi_i - x) (gamma_i - y_pred)^T )
+        let mut mat_p_xy = DMatrix::zeros(self.num_states, num_measurements);
+        for i in 0..mat_chi.ncols() {
+            let vec_dx = mat_chi.column(i) - &self.vec_state.0;
+            let vec_dy = mat_gamma.column(i) - &vec_y_pred;
+            mat_p_xy += vec_dx * vec_dy.transpose() * self.unit_points.weights_c[i];
+        }
+
+        // K = P_xy S^-1, calculated as solution of  S K^T = P_xy^T
+        let mat_k = mat_s.clone()
+                         .cholesky()
+                         .expect("Innovation covariance S is not positive definite")
+                         .solve(&mat_p_xy.transpose())
+                         .transpose();
+
+        // x = x + K ( y - y_pred )
+        self.vec_state.0 += &mat_k * (vec_y - vec_y_pred);
+
+        // P = P - K S K^T
+        self.mat_p.0 -= &mat_k * mat_s * mat_k.transpose();
+    }
+}
+
+/// Weighted mean and covariance of the transformed sigma points (one per column)
+fn unscented_mean_and_covariance<N : Real>(mat_points : &DMatrix<N>,
+                                           unit_points : &UnitSigmaPoints<N>)
+                                           -> (DVector<N>, DMatrix<N>) {
+    let mut vec_mean = DVector::zeros(mat_points.nrows());
+    for i in 0..mat_points.ncols() {
+        vec_mean += mat_points.column(i) * unit_points.weights_m[i];
+    }
+    let mut mat_cov = DMatrix::zeros(mat_points.nrows(), mat_points.nrows());
+    for i in 0..mat_points.ncols() {
+        let vec_d = mat_points.column(i) - &vec_mean;
+        mat_cov += &vec_d * vec_d.transpose() * unit_points.weights_c[i];
+    }
+    (vec_mean, mat_cov)
+}
@@ -0,0 +1,169 @@
+#![allow(dead_code)]
+
+extern crate kalmanfilter;
+extern crate nalgebra as na;
+extern crate num;
+extern crate rand;
+
+mod helpers;
+
+use kalmanfilter::kf::{KalmanFilterBuilder, KalmanFilter, InnovationGate, GatingDecision};
+use kalmanfilter::adaptive::{AdaptiveKalmanFilterBuilder, AdaptiveKalmanFilter, NoiseEstimation,
+                             AdaptedNoise};
+use kalmanfilter::nt;
+
+use na::{DMatrix, DVector};
+use rand::{StdRng, SeedableRng};
+use rand::distributions::{Normal, IndependentSample};
+
+
+/// x_k+1 = 0.95 x_k + w_k,  y_k = x_k + v_k
+fn mk_kf(q : f64) -> KalmanFilter<f64> {
+    KalmanFilterBuilder
+        ::with_numstates_and_numinputs(1, 1)
+        .with_system_matrix(nt::DiscreteSystemMatrix(DMatrix::from_element(1, 1, 0.95)))
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(DMatrix::from_element(1, 1, q)))
+        .with_initial_state(nt::StateVector(DVector::zeros(1)),
+                            nt::CovarianceMatrix(DMatrix::from_element(1, 1, 1.)))
+        .into()
+}
+
+fn run(filter : &mut AdaptiveKalmanFilter<f64>, q : f64, r : f64, num_steps : usize, seed : usize) {
+    let mut rng = StdRng::from_seed(&[seed][..]);
+    let process_noise = Normal::new(0., q.sqrt());
+    let measurement_noise = Normal::new(0., r.sqrt());
+    let u = nt::InputVector(DVector::zeros(1));
+
+    let mut x = 0.;
+    for _ in 0..num_steps {
+        x = 0.95 * x + process_noise.ind_sample(&mut rng);
+        let y = x + measurement_noise.ind_sample(&mut rng);
+        filter.predict(&u);
+        filter.measure_vector(nt::MeasurementVector(DVector::from_element(1, y)),
+                              nt::MeasurementMatrix(DMatrix::from_element(1, 1, 1.)));
+    }
+}
+
+#[test]
+fn sage_husa_estimates_measurement_noise() {
+    let mut filter : AdaptiveKalmanFilter<f64> = AdaptiveKalmanFilterBuilder
+        ::new(mk_kf(0.1), nt::MeasurementNoiseCovarianceMatrix(DMatrix::from_element(1, 1, 2.)))
+        .with_estimation(NoiseEstimation::SageHusa { fading_factor : 0.998 })
+        .with_adapted_noise(AdaptedNoise::MeasurementNoise)
+        .with_measurement_noise_bounds(0.01, Some(10.))
+        .into();
+
+    run(&mut filter, 0.1, 0.5, 5000, 1);
+
+    let r = filter.get_measurement_noise_variances().0[(0, 0)];
+    assert!((r - 0.5).abs() < 0.15, "R = {}", r);
+    assert_eq!(0.1, filter.get_system_noise_variances().0[(0, 0)]);
+}
+
+#[test]
+fn covariance_matching_estimates_system_noise() {
+    let mut filter : AdaptiveKalmanFilter<f64> = AdaptiveKalmanFilterBuilder
+        ::new(mk_kf(0.001), nt::MeasurementNoiseCovarianceMatrix(DMatrix::from_element(1, 1, 0.05)))
+        .with_estimation(NoiseEstimation::CovarianceMatching { window : 500 })
+        .with_adapted_noise(AdaptedNoise::SystemNoise)
+        .with_system_noise_bounds(1e-4, None)
+        .into();
+
+    run(&mut filter, 0.2, 0.05, 3000, 2);
+
+    let q = filter.get_system_noise_variances().0[(0, 0)];
+    assert!((q - 0.2).abs() < 0.06, "Q = {}", q);
+    assert_eq!(0.05, filter.get_measurement_noise_variances().0[(0, 0)]);
+}
+
+/// A rejected measurement must leave the innovation window as if it had never been seen
+#[test]
+fn covariance_matching_keeps_window_on_rejection() {
+    let mk_filter = || -> AdaptiveKalmanFilter<f64> {
+        let kf = KalmanFilterBuilder
+            ::with_numstates_and_numinputs(1, 1)
+            .with_system_matrix(nt::DiscreteSystemMatrix(DMatrix::from_element(1, 1, 0.95)))
+            .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(DMatrix::from_element(1, 1, 0.1)))
+            .with_initial_state(nt::StateVector(DVector::zeros(1)),
+                                nt::CovarianceMatrix(DMatrix::from_element(1, 1, 1.)))
+            .with_innovation_gate(InnovationGate::Reject(vec![25.]))
+            .into();
+        AdaptiveKalmanFilterBuilder
+            ::new(kf, nt::MeasurementNoiseCovarianceMatrix(DMatrix::from_element(1, 1, 0.5)))
+            .with_estimation(NoiseEstimation::CovarianceMatching { window : 3 })
+            .with_adapted_noise(AdaptedNoise::SystemNoise)
+            .into()
+    };
+    let mut filters = vec![mk_filter(), mk_filter()];
+    let u = nt::InputVector(DVector::zeros(1));
+    let mat_c = nt::MeasurementMatrix(DMatrix::from_element(1, 1, 1.));
+
+    for (i, &y) in [0.3, -0.2, 0.5, 100., 0.1, -0.4].iter().enumerate() {
+        for (j, filter) in filters.iter_mut().enumerate() {
+            filter.predict(&u);
+            if i == 3 && j == 1 {
+                continue;
+            }
+            let update = filter.measure_vector(nt::MeasurementVector(DVector::from_element(1, y)),
+                                               mat_c.clone());
+            assert_eq!(i == 3, update.gating == GatingDecision::Rejected);
+        }
+        assert_eq!(filters[0].get_system_noise_variances().0,
+                   filters[1].get_system_noise_variances().0);
+    }
+}
+
+/// An outlier must be gated with the prior R, adapting R to it first would widen its own gate
+#[test]
+fn outlier_is_rejected_while_adapting_measurement_noise() {
+    for &adapted_noise in &[AdaptedNoise::MeasurementNoise, AdaptedNoise::Both] {
+        let kf = KalmanFilterBuilder
+            ::with_numstates_and_numinputs(1, 1)
+            .with_system_matrix(nt::DiscreteSystemMatrix(DMatrix::from_element(1, 1, 0.95)))
+            .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(DMatrix::from_element(1, 1, 0.1)))
+            .with_initial_state(nt::StateVector(DVector::zeros(1)),
+                                nt::CovarianceMatrix(DMatrix::from_element(1, 1, 1.)))
+            .with_innovation_gate(InnovationGate::Reject(vec![25.]))
+            .into();
+        let mut filter : AdaptiveKalmanFilter<f64> = AdaptiveKalmanFilterBuilder
+            ::new(kf, nt::MeasurementNoiseCovarianceMatrix(DMatrix::from_element(1, 1, 0.5)))
+            .with_estimation(NoiseEstimation::CovarianceMatching { window : 1 })
+            .with_adapted_noise(adapted_noise)
+            .into();
+        let u = nt::InputVector(DVector::zeros(1));
+        let mat_c = nt::MeasurementMatrix(DMatrix::from_element(1, 1, 1.));
+
+        filter.predict(&u);
+        let vec_x = filter.get_state().vec_state.0.clone();
+        let mat_q = filter.get_system_noise_variances().0.clone();
+        let update = filter.measure_vector(nt::MeasurementVector(DVector::from_element(1, 100.)),
+                                           mat_c.clone());
+        assert_eq!(GatingDecision::Rejected, update.gating);
+        assert_eq!(vec_x, update.vec_state.0);
+        assert_eq!(0.5, filter.get_measurement_noise_variances().0[(0, 0)]);
+        assert_eq!(mat_q, filter.get_system_noise_variances().0);
+
+        let update = filter.measure_vector(nt::MeasurementVector(DVector::from_element(1, 0.3)),
+                                           mat_c);
+        assert_eq!(GatingDecision::Accepted, update.gating);
+        assert!(filter.get_measurement_noise_variances().0[(0, 0)] != 0.5);
+    }
+}
+
+/// Without explicit bounds, R keeps a positive floor even if the innovations vanish
+#[test]
+fn measurement_noise_stays_regular_by_default() {
+    let mut filter : AdaptiveKalmanFilter<f64> = AdaptiveKalmanFilterBuilder
+        ::new(mk_kf(0.), nt::MeasurementNoiseCovarianceMatrix(DMatrix::from_element(1, 1, 1.)))
+        .with_estimation(NoiseEstimation::CovarianceMatching { window : 1 })
+        .with_adapted_noise(AdaptedNoise::MeasurementNoise)
+        .into();
+    let u = nt::InputVector(DVector::zeros(1));
+
+    for _ in 0..50 {
+        filter.predict(&u);
+        filter.measure_vector(nt::MeasurementVector(DVector::zeros(1)),
+                              nt::MeasurementMatrix(DMatrix::from_element(1, 1, 1.)));
+        assert!(filter.get_measurement_noise_variances().0[(0, 0)] >= 1e-3);
+    }
+}
@@ -0,0 +1,102 @@
+#![allow(dead_code)]
+
+extern crate kalmanfilter;
+extern crate nalgebra as na;
+extern crate num;
+extern crate rand;
+
+mod helpers;
+
+use kalmanfilter::kf::{KalmanFilterBuilder, KalmanFilter};
+use kalmanfilter::als::{AutocovarianceLeastSquaresBuilder, AutocovarianceLeastSquares,
+                        NoiseStructure};
+use kalmanfilter::nt;
+
+use na::{DMatrix, DVector};
+use rand::{StdRng, SeedableRng};
+use rand::distributions::{Normal, IndependentSample};
+
+
+/// x_k+1 = F x_k + H u_k + w_k,  y_k = C x_k + v_k, with Q = diag(0.05, 0.1), R = diag(0.2, 0.1)
+fn simulate(mat_c : &DMatrix<f64>, num_steps : usize, seed : usize)
+            -> (Vec<nt::InputVector<f64>>, Vec<nt::MeasurementVector<f64>>) {
+    let mut rng = StdRng::from_seed(&[seed][..]);
+    let mat_f = DMatrix::from_row_slice(2, 2, &[0.9, 0.2, 0., 0.7]);
+    let mat_h = DMatrix::from_row_slice(2, 1, &[0., 1.]);
+    let std_w = [0.05f64.sqrt(), 0.1f64.sqrt()];
+    let std_v = [0.2f64.sqrt(), 0.1f64.sqrt()];
+    let normal = Normal::new(0., 1.);
+
+    let mut vec_x = DVector::zeros(2);
+    let mut inputs = Vec::with_capacity(num_steps);
+    let mut measurements = Vec::with_capacity(num_steps);
+    for k in 0..num_steps {
+        let vec_u = DVector::from_element(1, if (k / 25) % 2 == 0 { 1. } else { -1. });
+        let vec_w = DVector::from_iterator(2, std_w.iter().map(|s| s * normal.ind_sample(&mut rng)));
+        let vec_v = DVector::from_iterator(mat_c.nrows(),
+                                           std_v.iter().take(mat_c.nrows())
+                                                .map(|s| s * normal.ind_sample(&mut rng)));
+        vec_x = &mat_f * &vec_x + &mat_h * &vec_u + vec_w;
+        inputs.push(nt::InputVector(vec_u));
+        measurements.push(nt::MeasurementVector(mat_c * &vec_x + vec_v));
+    }
+    (inputs, measurements)
+}
+
+fn mk_kf() -> KalmanFilter<f64> {
+    KalmanFilterBuilder
+        ::with_numstates_and_numinputs(2, 1)
+        .with_system_matrix(nt::DiscreteSystemMatrix(DMatrix::from_row_slice(2, 2, &[0.9, 0.2, 0., 0.7])))
+        .with_input_matrix(nt::DiscreteInputMatrix(DMatrix::from_row_slice(2, 1, &[0., 1.])))
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(DMatrix::identity(2, 2)))
+        .with_initial_state(nt::StateVector(DVector::zeros(2)),
+                            nt::CovarianceMatrix(DMatrix::identity(2, 2)))
+        .into()
+}
+
+#[test]
+fn als_estimates_noise_covariances() {
+    let mat_c = DMatrix::identity(2, 2);
+    let (inputs, measurements) = simulate(&mat_c, 5000, 1);
+    let als : AutocovarianceLeastSquares<f64> = AutocovarianceLeastSquaresBuilder
+        ::new(&mk_kf(),
+              nt::MeasurementMatrix(mat_c),
+              nt::MeasurementNoiseCovarianceMatrix(DMatrix::identity(2, 2)))
+        .with_noise_structure(NoiseStructure::Diagonal)
+        .with_num_lags(5)
+        .with_discarded_steps(50)
+        .into();
+
+    let estimate = als.estimate(&inputs, &measurements);
+
+    assert!(estimate.identifiable);
+    let diff = (DMatrix::from_row_slice(2, 2, &[0.05, 0., 0., 0.1]) - &estimate.mat_q.0).abs();
+    assert!(helpers::max(&diff) < 0.04, "Q = {}", estimate.mat_q.0);
+    let diff = (DMatrix::from_row_slice(2, 2, &[0.2, 0., 0., 0.1]) - &estimate.mat_r.0).abs();
+    assert!(helpers::max(&diff) < 0.04, "R = {}", estimate.mat_r.0);
+}
+
+/// With only the first state measured, a full Q can not be separated from R
+#[test]
+fn als_reports_identifiability() {
+    let mat_c = DMatrix::from_row_slice(1, 2, &[1., 0.]);
+    let (inputs, measurements) = simulate(&mat_c, 5000, 2);
+    let builder = || AutocovarianceLeastSquaresBuilder
+        ::new(&mk_kf(),
+              nt::MeasurementMatrix(mat_c.clone()),
+              nt::MeasurementNoiseCovarianceMatrix(DMatrix::identity(1, 1)))
+        .with_num_lags(10);
+
+    let als : AutocovarianceLeastSquares<f64> = builder().into();
+    let estimate = als.estimate(&inputs, &measurements);
+    assert!(!estimate.identifiable);
+
+    let als : AutocovarianceLeastSquares<f64> = builder()
+        .with_noise_structure(NoiseStructure::Diagonal)
+        .into();
+    let estimate = als.estimate(&inputs, &measurements);
+    assert!(estimate.identifiable);
+    assert!(estimate.condition_number < 1e6);
+    assert!(estimate.mat_q.0.iter().all(|&q| q >= 0.));
+    assert!(estimate.mat_r.0[(0, 0)] >= 0.);
+}
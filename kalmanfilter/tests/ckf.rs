@@ -0,0 +1,75 @@
+#![allow(dead_code)]
+
+extern crate kalmanfilter;
+extern crate nalgebra as na;
+extern crate num;
+extern crate rand;
+
+mod helpers;
+
+use kalmanfilter::ckf::{CubatureKalmanFilterBuilder, CubatureKalmanFilter};
+use kalmanfilter::ukf::{UnscentedKalmanFilterBuilder, UnscentedKalmanFilter, SigmaPoints};
+use kalmanfilter::nt;
+
+use na::{DMatrix, DVector};
+
+
+fn pendulum(x : &nt::StateVector<f64>, u : &nt::InputVector<f64>) -> nt::StateVector<f64> {
+    let dt = 0.01;
+    nt::StateVector(DVector::from_row_slice(2, &[
+        x.0[0] + dt * x.0[1],
+        x.0[1] + dt * (-9.81 * x.0[0].sin() + u.0[0]),
+    ]))
+}
+
+fn horizontal_position(x : &nt::StateVector<f64>) -> nt::MeasurementVector<f64> {
+    nt::MeasurementVector(DVector::from_row_slice(1, &[x.0[0].sin()]))
+}
+
+/// The cubature rule equals the scaled unscented transformation with alpha = 1, beta = 0 and
+/// kappa = 0 (no center point), also for nonlinear models.
+#[test]
+fn ckf_equals_equivalent_ukf() {
+    let mat_q = nt::SystemNoiseVarianceMatrix(DMatrix::from_row_slice(2, 2, &[1e-4, 0., 0., 1e-4]));
+    let vec_x_init = nt::StateVector(DVector::from_row_slice(2, &[0.5, 0.]));
+    let mat_p_init = nt::CovarianceMatrix(DMatrix::from_row_slice(2, 2, &[0.1, 0., 0., 0.1]));
+
+    let mut ckf : CubatureKalmanFilter<f64> = CubatureKalmanFilterBuilder
+        ::with_numstates_and_numinputs(2, 1)
+        .with_state_function(pendulum)
+        .with_output_function(horizontal_position)
+        .with_system_noise_variances(mat_q.clone())
+        .with_initial_state(vec_x_init.clone(), mat_p_init.clone())
+        .into();
+
+    let mut ukf : UnscentedKalmanFilter<f64> = UnscentedKalmanFilterBuilder
+        ::with_numstates_and_numinputs(2, 1)
+        .with_state_function(pendulum)
+        .with_output_function(horizontal_position)
+        .with_sigma_points(SigmaPoints::MerweScaled { alpha : 1., beta : 0., kappa : 0. })
+        .with_system_noise_variances(mat_q)
+        .with_initial_state(vec_x_init, mat_p_init)
+        .into();
+
+    let mut vec_x = DVector::from_row_slice(2, &[0.3, 0.]);
+    let u = nt::InputVector(DVector::from_row_slice(1, &[0.,]));
+    for _ in 0..300 {
+        vec_x = pendulum(&nt::StateVector(vec_x), &u).0;
+        let y = horizontal_position(&nt::StateVector(vec_x.clone())).0[0];
+
+        ckf.predict(&u);
+        ukf.predict(&u);
+
+        let ckf_state = ckf.measure(nt::Measurement(y), 0, nt::MeasurementNoiseVariance(0.01));
+        let ukf_state = ukf.measure(nt::Measurement(y), 0, nt::MeasurementNoiseVariance(0.01));
+
+        let diff = &ckf_state.vec_state.0 - &ukf_state.vec_state.0;
+        assert!(helpers::max(&diff.abs()) < 1e-9);
+        let diff = &ckf_state.mat_covariances.0 - &ukf_state.mat_covariances.0;
+        assert!(helpers::max(&diff.abs()) < 1e-9);
+    }
+
+    // The filter has found the pendulum
+    let diff = &ckf.get_state().vec_state.0 - &vec_x;
+    assert!(helpers::max(&diff.abs()) < 0.05);
+}
@@ -0,0 +1,242 @@
+#![allow(dead_code)]
+
+extern crate kalmanfilter;
+extern crate nalgebra as na;
+extern crate num;
+extern crate rand;
+
+mod helpers;
+
+use kalmanfilter::kf::{KalmanFilterBuilder, KalmanFilter, StateConstraints};
+use kalmanfilter::fixedlag::FixedLagSmoother;
+use kalmanfilter::sckf::StochasticCloningKalmanFilter;
+use kalmanfilter::nt;
+
+use na::{DMatrix, DVector, RowDVector};
+
+
+fn mk_kf(mat_p : DMatrix<f64>, state_constraints : StateConstraints<f64>) -> KalmanFilter<f64> {
+    KalmanFilterBuilder
+        ::with_numstates_and_numinputs(2, 1)
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(DMatrix::from_row_slice(2, 2, &[0.1, 0., 0., 0.2])))
+        .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[0.5, 0.5])),
+                            nt::CovarianceMatrix(mat_p))
+        .with_state_constraints(state_constraints)
+        .into()
+}
+
+/// x1 - x2 = 0.5, e.g. two points on a rigid body
+#[test]
+fn equality_constraints() {
+    let mat_d = nt::ConstraintMatrix(DMatrix::from_row_slice(1, 2, &[1., -1.]));
+    let vec_d = nt::ConstraintVector(DVector::from_element(1, 0.5));
+    let mat_p = DMatrix::from_row_slice(2, 2, &[4., 0.5, 0.5, 1.]);
+    let mut filters = [
+        mk_kf(mat_p.clone(), StateConstraints::EqualityProjection(mat_d.clone(), vec_d.clone())),
+        mk_kf(mat_p.clone(), StateConstraints::EqualityPseudoMeasurement(mat_d.clone(), vec_d.clone())),
+    ];
+    let u = nt::InputVector(DVector::zeros(1));
+
+    for (k, y) in [3., 2.5, 2.8].iter().enumerate() {
+        for kf in filters.iter_mut() {
+            if k > 0 {
+                kf.predict(&u);
+            }
+            kf.measure(nt::Measurement(*y),
+                       nt::MeasurementMatrixRow(RowDVector::from_row_slice(2, &[1., 0.])),
+                       nt::MeasurementNoiseVariance(1.));
+            kf.measure_vector(nt::MeasurementVector(DVector::from_element(1, *y - 0.3)),
+                              nt::MeasurementMatrix(DMatrix::from_row_slice(1, 2, &[0., 1.])),
+                              nt::MeasurementNoiseCovarianceMatrix(DMatrix::identity(1, 1)));
+
+            let state = kf.get_state();
+            assert!(((&mat_d.0 * &state.vec_state.0)[0] - 0.5).abs() < 1e-9);
+            // No uncertainty left along the constraint
+            assert!((&mat_d.0 * &state.mat_covariances.0 * mat_d.0.transpose())[(0, 0)].abs() < 1e-9);
+        }
+        let diff = &filters[0].get_state().vec_state.0 - &filters[1].get_state().vec_state.0;
+        assert!(helpers::max(&diff.abs()) < 1e-9);
+        let diff = &filters[0].get_state().mat_covariances.0 - &filters[1].get_state().mat_covariances.0;
+        assert!(helpers::max(&diff.abs()) < 1e-9);
+    }
+}
+
+/// Nonnegative concentrations: -x <= 0
+#[test]
+fn inequality_constraints() {
+    let mat_d = DMatrix::from_row_slice(2, 2, &[-1., 0., 0., -1.]);
+    let vec_d = DVector::zeros(2);
+    let rvec_c = RowDVector::from_row_slice(2, &[1., 0.]);
+
+    for &(y, correlation) in [(-3., 0.8), (-3., -0.8), (0.2, 0.8), (-1., 0.)].iter() {
+        let mat_p = DMatrix::from_row_slice(2, 2, &[1., correlation, correlation, 1.]);
+        let mut kf_unconstrained = mk_kf(mat_p.clone(), StateConstraints::None);
+        let mut kf = mk_kf(mat_p.clone(),
+                           StateConstraints::InequalityActiveSet(nt::ConstraintMatrix(mat_d.clone()),
+                                                                 nt::ConstraintVector(vec_d.clone())));
+
+        let update = kf_unconstrained.measure(nt::Measurement(y), nt::MeasurementMatrixRow(rvec_c.clone()),
+                                              nt::MeasurementNoiseVariance(0.1));
+        let vec_x = update.vec_state.0.clone();
+        let mat_p = update.mat_covariances.0.clone();
+        let update = kf.measure(nt::Measurement(y), nt::MeasurementMatrixRow(rvec_c.clone()),
+                                nt::MeasurementNoiseVariance(0.1));
+
+        // Brute force: the closest feasible projection onto any subset of the constraints
+        let mat_p_inv = mat_p.clone().try_inverse().unwrap();
+        let mut best : Option<(f64, DVector<f64>)> = None;
+        for rows in [vec![], vec![0], vec![1], vec![0, 1]].iter() {
+            let mut vec_x_c = vec_x.clone();
+            if !rows.is_empty() {
+                let mat_d_a = DMatrix::from_iterator(rows.len(), 2,
+                    (0..2).flat_map(|j| rows.iter().map(move |&i| (i, j))).map(|(i, j)| mat_d[(i, j)]));
+                let mat_pd = &mat_p * mat_d_a.transpose();
+                let mat_dpd_inv = (&mat_d_a * &mat_pd).try_inverse().unwrap();
+                vec_x_c = &vec_x - &mat_pd * mat_dpd_inv * (&mat_d_a * &vec_x);
+            }
+            if (&mat_d * &vec_x_c).iter().any(|&v| v > 1e-9) {
+                continue;
+            }
+            let vec_dx = &vec_x_c - &vec_x;
+            let distance = vec_dx.dot(&(&mat_p_inv * &vec_dx));
+            if best.as_ref().map(|&(d, _)| distance < d).unwrap_or(true) {
+                best = Some((distance, vec_x_c));
+            }
+        }
+
+        let vec_expected = best.unwrap().1;
+        assert!(helpers::max(&(&vec_expected - &update.vec_state.0).abs()) < 1e-9,
+                "{} {}", vec_expected, update.vec_state.0);
+        assert!(update.vec_state.0.iter().all(|&x| x >= -1e-9));
+        assert_eq!(&mat_p, &update.mat_covariances.0);
+    }
+}
+
+/// Redundant constraints x1 <= 0, x2 <= 0, x1 + x2 <= 0 and x1 <= 0 again are degenerate at the
+/// vertex x = 0, but must give the projection onto x1 <= 0, x2 <= 0
+#[test]
+fn inequality_constraints_with_degenerate_active_set() {
+    let mat_d = DMatrix::from_row_slice(4, 2, &[1., 0., 0., 1., 1., 1., 1., 0.]);
+    let rvec_c = RowDVector::from_row_slice(2, &[1., 1.]);
+
+    for &correlation in [0.8, 0., -0.8].iter() {
+        let mat_p = DMatrix::from_row_slice(2, 2, &[1., correlation, correlation, 1.]);
+        let mut kf = mk_kf(mat_p.clone(),
+                           StateConstraints::InequalityActiveSet(nt::ConstraintMatrix(mat_d.clone()),
+                                                                 nt::ConstraintVector(DVector::zeros(4))));
+        let mut kf_reduced = mk_kf(mat_p,
+                                   StateConstraints::InequalityActiveSet(
+                                       nt::ConstraintMatrix(mat_d.rows(0, 2).clone_owned()),
+                                       nt::ConstraintVector(DVector::zeros(2))));
+
+        let vec_x = kf.measure(nt::Measurement(5.), nt::MeasurementMatrixRow(rvec_c.clone()),
+                               nt::MeasurementNoiseVariance(0.1)).vec_state.0.clone();
+        let update = kf_reduced.measure(nt::Measurement(5.), nt::MeasurementMatrixRow(rvec_c.clone()),
+                                        nt::MeasurementNoiseVariance(0.1));
+        assert!(helpers::max(&(&vec_x - &update.vec_state.0).abs()) < 1e-9,
+                "{} {}", vec_x, update.vec_state.0);
+        assert!((&mat_d * &vec_x).iter().all(|&v| v <= 1e-9));
+    }
+}
+
+/// x1 <= -1 and x1 >= 1 conflict
+#[test]
+#[should_panic(expected = "Inequality constraints cannot be satisfied")]
+fn inequality_constraints_conflicting() {
+    let mat_d = DMatrix::from_row_slice(2, 2, &[1., 0., -1., 0.]);
+    let mut kf = mk_kf(DMatrix::identity(2, 2),
+                       StateConstraints::InequalityActiveSet(nt::ConstraintMatrix(mat_d),
+                                                             nt::ConstraintVector(DVector::from_element(2, -1.))));
+    kf.measure(nt::Measurement(0.), nt::MeasurementMatrixRow(RowDVector::from_row_slice(2, &[1., 0.])),
+               nt::MeasurementNoiseVariance(1.));
+}
+
+/// Linearly dependent constraints (singular D P D^T) on a filter with the consider state x3:
+/// x1 - x2 = 0.5, 2 x1 - 2 x2 = 1 and x1 - x3 = 0.2 can only be satisfied by x1 and x2
+#[test]
+fn equality_constraints_with_consider_states() {
+    let mat_d = nt::ConstraintMatrix(DMatrix::from_row_slice(3, 3, &[1., -1., 0.,
+                                                                      2., -2., 0.,
+                                                                      1., 0., -1.]));
+    let vec_d = nt::ConstraintVector(DVector::from_row_slice(3, &[0.5, 1., 0.2]));
+    for state_constraints in vec![StateConstraints::EqualityProjection(mat_d.clone(), vec_d.clone()),
+                                  StateConstraints::EqualityPseudoMeasurement(mat_d.clone(), vec_d.clone())] {
+        let mut kf : KalmanFilter<f64> = KalmanFilterBuilder
+            ::with_numstates_and_numinputs(3, 1)
+            .with_initial_state(nt::StateVector(DVector::from_row_slice(3, &[0.5, 0.5, 0.3])),
+                                nt::CovarianceMatrix(DMatrix::from_row_slice(3, 3, &[2., 0.5, 0.3,
+                                                                                     0.5, 1., 0.2,
+                                                                                     0.3, 0.2, 1.])))
+            .with_consider_states(vec![2])
+            .with_state_constraints(state_constraints)
+            .into();
+
+        let state = kf.measure(nt::Measurement(1.),
+                               nt::MeasurementMatrixRow(RowDVector::from_row_slice(3, &[1., 1., 1.])),
+                               nt::MeasurementNoiseVariance(1.));
+        let vec_expected = DVector::from_row_slice(3, &[0.5, 0., 0.3]);
+        assert!(helpers::max(&(&state.vec_state.0 - vec_expected).abs()) < 1e-9,
+                "{}", state.vec_state.0);
+        assert_eq!(1., state.mat_covariances.0[(2, 2)]);
+    }
+}
+
+/// The smoothed states of the fixed-lag smoother satisfy the constraints as well, including the
+/// initial state which does not
+#[test]
+fn equality_constraints_fixed_lag() {
+    let mat_d = nt::ConstraintMatrix(DMatrix::from_row_slice(1, 2, &[1., -1.]));
+    let vec_d = nt::ConstraintVector(DVector::from_element(1, 0.5));
+    let mat_p = DMatrix::from_row_slice(2, 2, &[4., 0.5, 0.5, 1.]);
+    let mut smoother = FixedLagSmoother::new(
+        mk_kf(mat_p, StateConstraints::EqualityProjection(mat_d.clone(), vec_d.clone())), 3);
+    let u = nt::InputVector(DVector::zeros(1));
+
+    for y in [3., 2.5, 2.8, 2.9, 3.1].iter() {
+        smoother.predict(&u);
+        smoother.measure(nt::Measurement(*y),
+                         nt::MeasurementMatrixRow(RowDVector::from_row_slice(2, &[1., 0.])),
+                         nt::MeasurementNoiseVariance(1.));
+        for i in 0..4 {
+            if let Some(state) = smoother.get_smoothed_state(i) {
+                assert!(((&mat_d.0 * &state.vec_state.0)[0] - 0.5).abs() < 1e-9);
+                assert!((&mat_d.0 * &state.mat_covariances.0 * mat_d.0.transpose())[(0, 0)].abs() < 1e-9);
+            }
+        }
+    }
+}
+
+/// The lagged state violates the constraints (the initial state does), so its projection
+/// depends on the cross covariance with the current state and has to match the clone of the
+/// stochastic cloning filter
+#[test]
+fn constraints_fixed_lag_equal_stochastic_cloning() {
+    let mat_d = nt::ConstraintMatrix(DMatrix::from_row_slice(1, 2, &[1., -1.]));
+    let vec_d = nt::ConstraintVector(DVector::from_element(1, 0.5));
+    let mat_p = DMatrix::from_row_slice(2, 2, &[4., 0.5, 0.5, 1.]);
+    let u = nt::InputVector(DVector::zeros(1));
+
+    for state_constraints in vec![StateConstraints::EqualityProjection(mat_d.clone(), vec_d.clone()),
+                                  StateConstraints::EqualityPseudoMeasurement(mat_d.clone(), vec_d.clone()),
+                                  StateConstraints::InequalityActiveSet(mat_d.clone(), vec_d.clone())] {
+        let mut smoother = FixedLagSmoother::new(mk_kf(mat_p.clone(), state_constraints.clone()), 1);
+        let mut sckf = StochasticCloningKalmanFilter::from(mk_kf(mat_p.clone(), state_constraints));
+
+        sckf.clone_state();
+        smoother.predict(&u);
+        sckf.predict(&u);
+        smoother.measure(nt::Measurement(3.),
+                         nt::MeasurementMatrixRow(RowDVector::from_row_slice(2, &[1., 0.])),
+                         nt::MeasurementNoiseVariance(1.));
+        sckf.measure(nt::Measurement(3.),
+                     nt::MeasurementMatrixRow(RowDVector::from_row_slice(2, &[1., 0.])),
+                     nt::MeasurementNoiseVariance(1.));
+
+        let lagged = smoother.get_smoothed_state(1).unwrap();
+        let clone = sckf.get_clone().unwrap();
+        assert!(helpers::max(&(&lagged.vec_state.0 - &clone.vec_state.0).abs()) < 1e-9,
+                "{} {}", lagged.vec_state.0, clone.vec_state.0);
+        assert!(helpers::max(&(&lagged.mat_covariances.0 - &clone.mat_covariances.0).abs()) < 1e-9);
+        assert!((&mat_d.0 * &lagged.vec_state.0)[0] <= 0.5 + 1e-9);
+    }
+}
@@ -0,0 +1,118 @@
+#![allow(dead_code)]
+
+extern crate kalmanfilter;
+extern crate nalgebra as na;
+extern crate num;
+extern crate rand;
+
+mod helpers;
+
+use helpers::types::*;
+use helpers::model::*;
+use kalmanfilter::kf::{KalmanFilterBuilder, KalmanFilter};
+use kalmanfilter::ekf::{ExtendedKalmanFilterBuilder, ExtendedKalmanFilter, MeasurementLinearization};
+use kalmanfilter::nt;
+
+use na::{DMatrix, DVector};
+
+
+/// With linear f() and c() the EKF must behave exactly like the linear KF
+#[test]
+fn ekf_equals_kf_for_linear_model() {
+    let dt : TimeStep = 0.01;
+    let mut rw : DiscreteLinearModel = example_model_2states_regular_stable().into_discrete(dt, 1e-5).into();
+    let sim_time : usize = 2;
+    let steps = (sim_time as f64 / dt) as usize;
+
+    let mat_q = nt::SystemNoiseVarianceMatrix(DMatrix::from_row_slice(2, 2, &[0.01, 0., 0., 0.01]));
+    let vec_x_init = nt::StateVector(DVector::from_row_slice(2, &[0., 0.]));
+    let mat_p_init = nt::CovarianceMatrix(DMatrix::from_row_slice(2, 2, &[100., 0., 0., 100.]));
+
+    let mut kf : KalmanFilter<f64> = KalmanFilterBuilder
+        ::with_numstates_and_numinputs(rw.get_num_states(), rw.get_num_inputs())
+        .with_system_matrix(rw.get_system_matrix().clone())
+        .with_input_matrix(rw.get_input_matrix().clone())
+        .with_system_noise_variances(mat_q.clone())
+        .with_initial_state(vec_x_init.clone(), mat_p_init.clone())
+        .into();
+
+    let mat_f = rw.get_system_matrix().clone();
+    let mat_f2 = rw.get_system_matrix().clone();
+    let mat_h = rw.get_input_matrix().clone();
+    let mat_c = rw.get_measurement_matrix().0.clone();
+    let mat_c2 = rw.get_measurement_matrix().0.clone();
+
+    let mut ekf : ExtendedKalmanFilter<f64> = ExtendedKalmanFilterBuilder
+        ::with_numstates_and_numinputs(rw.get_num_states(), rw.get_num_inputs())
+        .with_state_function(move |x, u| nt::StateVector(&mat_f.0 * &x.0 + &mat_h.0 * &u.0),
+                             move |_, _| mat_f2.clone())
+        .with_output_function(move |x| nt::MeasurementVector(&mat_c * &x.0),
+                              move |_| nt::MeasurementMatrix(mat_c2.clone()))
+        .with_system_noise_variances(mat_q)
+        .with_initial_state(vec_x_init, mat_p_init)
+        .into();
+
+    for i in 0..steps {
+        let t = i as f64 * dt;
+        let u = if t <= 1. { 0. } else { 1. };
+        let u = nt::InputVector(DVector::from_row_slice(1, &[u,]));
+        let y = rw.step(&u);
+
+        kf.predict(&u);
+        ekf.predict(&u);
+
+        let kf_state = kf.measure(nt::Measurement(y.0[(0, 0)]),
+            nt::MeasurementMatrixRow( rw.get_measurement_matrix().0.row(0).clone_owned() ),
+            nt::MeasurementNoiseVariance( 0.1 ));
+        let ekf_state = ekf.measure(nt::Measurement(y.0[(0, 0)]), 0,
+            nt::MeasurementNoiseVariance( 0.1 ));
+
+        let diff = &ekf_state.vec_state.0 - &kf_state.vec_state.0;
+        assert!(helpers::max(&diff.abs()) < 1e-9);
+        let diff = &ekf_state.mat_covariances.0 - &kf_state.mat_covariances.0;
+        assert!(helpers::max(&diff.abs()) < 1e-9);
+    }
+}
+
+fn mk_bearing_filter(linearization : MeasurementLinearization<f64>) -> ExtendedKalmanFilter<f64> {
+    ExtendedKalmanFilterBuilder::<f64>
+        ::with_numstates_and_numinputs(2, 1)
+        .with_output_function(|x : &nt::StateVector<f64>| nt::MeasurementVector(DVector::from_element(1, x.0[1].atan2(x.0[0]))),
+                              |x : &nt::StateVector<f64>| {
+                                  let r2 = x.0[0] * x.0[0] + x.0[1] * x.0[1];
+                                  nt::MeasurementMatrix(DMatrix::from_row_slice(1, 2, &[-x.0[1] / r2, x.0[0] / r2]))
+                              })
+        .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[1., 0.])),
+                            nt::CovarianceMatrix(DMatrix::identity(2, 2)))
+        .with_measurement_linearization(linearization)
+        .into()
+}
+
+/// A precise bearing measurement far from the prior: the iterated update must converge to a
+/// state consistent with the measurement, the single linearization overshoots
+#[test]
+fn iekf_converges_for_nonlinear_measurement() {
+    let bearing = ::std::f64::consts::FRAC_PI_4;
+
+    let mut ekf = mk_bearing_filter(MeasurementLinearization::Single);
+    let update = ekf.measure(nt::Measurement(bearing), 0, nt::MeasurementNoiseVariance(1e-6));
+    assert_eq!(1, update.iterations);
+    let error = update.vec_state.0[1].atan2(update.vec_state.0[0]) - bearing;
+    assert!(error.abs() > 0.05);
+
+    let mut iekf = mk_bearing_filter(MeasurementLinearization::Iterated { max_iterations : 20, tolerance : 1e-10 });
+    let update = iekf.measure(nt::Measurement(bearing), 0, nt::MeasurementNoiseVariance(1e-6));
+    assert!(update.iterations > 1);
+    assert!(update.iterations <= 20);
+    let error = update.vec_state.0[1].atan2(update.vec_state.0[0]) - bearing;
+    assert!(error.abs() < 1e-3);
+    let vec_state = update.vec_state.0.clone();
+    assert_eq!(vec_state, iekf.get_state().vec_state.0);
+}
+
+#[test]
+#[should_panic(expected = "Output index 1 out of range, c(x) has 1 outputs")]
+fn ekf_rejects_invalid_output_index() {
+    let mut ekf = mk_bearing_filter(MeasurementLinearization::Single);
+    ekf.measure(nt::Measurement(0.), 1, nt::MeasurementNoiseVariance(1.));
+}
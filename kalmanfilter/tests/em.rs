@@ -0,0 +1,106 @@
+#![allow(dead_code)]
+
+extern crate kalmanfilter;
+extern crate nalgebra as na;
+extern crate num;
+extern crate rand;
+
+mod helpers;
+
+use kalmanfilter::kf::{KalmanFilterBuilder, KalmanFilter};
+use kalmanfilter::em::{ExpectationMaximizationBuilder, ExpectationMaximization, IdentifiedModel};
+use kalmanfilter::nt;
+
+use na::{DMatrix, DVector};
+use rand::{StdRng, SeedableRng};
+use rand::distributions::{Normal, IndependentSample};
+
+
+/// x_k+1 = F x_k + H u_k + w_k,  y_k = x_k + v_k, with Q = diag(0.05, 0.1), R = diag(0.2, 0.1)
+fn simulate(num_steps : usize, seed : usize) -> (Vec<nt::InputVector<f64>>, Vec<nt::MeasurementVector<f64>>) {
+    let mut rng = StdRng::from_seed(&[seed][..]);
+    let mat_f = DMatrix::from_row_slice(2, 2, &[0.9, 0.2, 0., 0.7]);
+    let mat_h = DMatrix::from_row_slice(2, 1, &[0., 1.]);
+    let std_w = [0.05f64.sqrt(), 0.1f64.sqrt()];
+    let std_v = [0.2f64.sqrt(), 0.1f64.sqrt()];
+    let normal = Normal::new(0., 1.);
+
+    let mut vec_x = DVector::zeros(2);
+    let mut inputs = Vec::with_capacity(num_steps);
+    let mut measurements = Vec::with_capacity(num_steps);
+    for k in 0..num_steps {
+        let vec_u = DVector::from_element(1, if (k / 25) % 2 == 0 { 1. } else { -1. });
+        let vec_w = DVector::from_iterator(2, std_w.iter().map(|s| s * normal.ind_sample(&mut rng)));
+        let vec_v = DVector::from_iterator(2, std_v.iter().map(|s| s * normal.ind_sample(&mut rng)));
+        vec_x = &mat_f * &vec_x + &mat_h * &vec_u + vec_w;
+        inputs.push(nt::InputVector(vec_u));
+        measurements.push(nt::MeasurementVector(&vec_x + vec_v));
+    }
+    (inputs, measurements)
+}
+
+fn mk_kf(mat_f : DMatrix<f64>, mat_h : DMatrix<f64>) -> KalmanFilter<f64> {
+    KalmanFilterBuilder
+        ::with_numstates_and_numinputs(2, 1)
+        .with_system_matrix(nt::DiscreteSystemMatrix(mat_f))
+        .with_input_matrix(nt::DiscreteInputMatrix(mat_h))
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(DMatrix::identity(2, 2)))
+        .with_initial_state(nt::StateVector(DVector::zeros(2)),
+                            nt::CovarianceMatrix(DMatrix::identity(2, 2)))
+        .into()
+}
+
+fn assert_close(expected : &[f64], actual : &DMatrix<f64>, tolerance : f64) {
+    let mat_expected = DMatrix::from_row_slice(actual.nrows(), actual.ncols(), expected);
+    let diff = (mat_expected - actual).abs();
+    assert!(helpers::max(&diff) < tolerance, "{}", actual);
+}
+
+fn assert_monotonic(model : &IdentifiedModel<f64>) {
+    for pair in model.log_likelihoods.windows(2) {
+        assert!(pair[1] >= pair[0] - 1e-6 * pair[0].abs());
+    }
+}
+
+#[test]
+fn em_identifies_noise_covariances() {
+    let (inputs, measurements) = simulate(1500, 1);
+    let filter = mk_kf(DMatrix::from_row_slice(2, 2, &[0.9, 0.2, 0., 0.7]),
+                       DMatrix::from_row_slice(2, 1, &[0., 1.]));
+    let em : ExpectationMaximization<f64> = ExpectationMaximizationBuilder
+        ::new(&filter,
+              nt::MeasurementMatrix(DMatrix::identity(2, 2)),
+              nt::MeasurementNoiseCovarianceMatrix(DMatrix::identity(2, 2)))
+        .with_max_iterations(300)
+        .into();
+
+    let model = em.identify(&inputs, &measurements);
+
+    assert!(model.converged);
+    assert_monotonic(&model);
+    assert_close(&[0.05, 0., 0., 0.1], &model.mat_q.0, 0.04);
+    assert_close(&[0.2, 0., 0., 0.1], &model.mat_r.0, 0.04);
+    assert_eq!(filter.get_system_matrix().0, model.mat_f.0);
+}
+
+#[test]
+fn em_identifies_system_and_input_matrix() {
+    let (inputs, measurements) = simulate(1500, 2);
+    let filter = mk_kf(DMatrix::from_row_slice(2, 2, &[0.5, 0., 0., 0.5]), DMatrix::zeros(2, 1));
+    let em : ExpectationMaximization<f64> = ExpectationMaximizationBuilder
+        ::new(&filter,
+              nt::MeasurementMatrix(DMatrix::identity(2, 2)),
+              nt::MeasurementNoiseCovarianceMatrix(DMatrix::identity(2, 2)))
+        .with_estimated_system_matrix()
+        .with_estimated_input_matrix()
+        .with_max_iterations(300)
+        .into();
+
+    let model = em.identify(&inputs, &measurements);
+
+    assert_monotonic(&model);
+    assert_close(&[0.9, 0.2, 0., 0.7], &model.mat_f.0, 0.05);
+    assert_close(&[0., 1.], &model.mat_h.0, 0.1);
+    assert_close(&[0.05, 0., 0., 0.1], &model.mat_q.0, 0.04);
+    assert_close(&[0.2, 0., 0., 0.1], &model.mat_r.0, 0.04);
+}
@@ -0,0 +1,148 @@
+#![allow(dead_code)]
+
+extern crate kalmanfilter;
+extern crate nalgebra as na;
+extern crate num;
+extern crate rand;
+
+mod helpers;
+
+use helpers::types::*;
+use helpers::model::*;
+use kalmanfilter::kf::{KalmanFilterBuilder, KalmanFilter};
+use kalmanfilter::enkf::{EnsembleKalmanFilterBuilder, EnsembleKalmanFilter, EnsembleAnalysis};
+use kalmanfilter::nt;
+
+use na::{DMatrix, DVector};
+
+
+fn mk_kf(rw : &DiscreteLinearModel, mat_q : &DMatrix<f64>) -> KalmanFilter<f64> {
+    KalmanFilterBuilder
+        ::with_numstates_and_numinputs(rw.get_num_states(), rw.get_num_inputs())
+        .with_system_matrix(rw.get_system_matrix().clone())
+        .with_input_matrix(rw.get_input_matrix().clone())
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(mat_q.clone()))
+        .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[0., 0.])),
+                            nt::CovarianceMatrix(DMatrix::from_row_slice(2, 2, &[100., 0., 0., 100.])))
+        .into()
+}
+
+fn mk_enkf_builder(rw : &DiscreteLinearModel, mat_q : &DMatrix<f64>) -> EnsembleKalmanFilterBuilder<f64> {
+    let mat_f = rw.get_system_matrix().clone();
+    let mat_h = rw.get_input_matrix().clone();
+    let mat_c = rw.get_measurement_matrix().0.clone();
+    EnsembleKalmanFilterBuilder
+        ::with_numstates_and_numinputs(rw.get_num_states(), rw.get_num_inputs())
+        .with_state_function(move |x, u| nt::StateVector(&mat_f.0 * &x.0 + &mat_h.0 * &u.0))
+        .with_output_function(move |x| nt::MeasurementVector(&mat_c * &x.0))
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(mat_q.clone()))
+}
+
+/// For a linear model without system noise the ETKF is exact if the initial ensemble has
+/// exactly the mean and covariance of the KF
+#[test]
+fn etkf_equals_kf_without_system_noise() {
+    let dt : TimeStep = 0.01;
+    let mut rw : DiscreteLinearModel = example_model_2states_regular_stable().into_discrete(dt, 1e-5).into();
+    let mat_q = DMatrix::zeros(2, 2);
+
+    let mut kf = mk_kf(&rw, &mat_q);
+
+    // Three members with mean 0 and sample covariance 100 I: rows orthonormal and orthogonal
+    // to (1, 1, 1), scaled by sqrt(100 (m - 1))
+    let s = (200f64).sqrt();
+    let mat_e = DMatrix::from_row_slice(2, 3, &[1. / 2f64.sqrt(), -1. / 2f64.sqrt(), 0.,
+                                                1. / 6f64.sqrt(), 1. / 6f64.sqrt(), -2. / 6f64.sqrt()]);
+    let mut enkf : EnsembleKalmanFilter<f64> = mk_enkf_builder(&rw, &mat_q)
+        .with_analysis(EnsembleAnalysis::Transform)
+        .with_initial_ensemble(nt::EnsembleMatrix(mat_e * s))
+        .into();
+
+    for i in 0..200 {
+        let t = i as f64 * dt;
+        let u = if t <= 1. { 0. } else { 1. };
+        let u = nt::InputVector(DVector::from_row_slice(1, &[u,]));
+        let y = rw.step(&u);
+
+        kf.predict(&u);
+        enkf.predict(&u);
+
+        kf.measure(nt::Measurement(y.0[(0, 0)]),
+            nt::MeasurementMatrixRow(rw.get_measurement_matrix().0.row(0).clone_owned()),
+            nt::MeasurementNoiseVariance(0.1));
+        enkf.measure(nt::Measurement(y.0[(0, 0)]), 0, nt::MeasurementNoiseVariance(0.1));
+
+        let diff = &enkf.get_mean().0 - &kf.get_state().vec_state.0;
+        assert!(helpers::max(&diff.abs()) < 1e-8);
+        let diff = &enkf.get_covariances().0 - &kf.get_state().mat_covariances.0;
+        assert!(helpers::max(&diff.abs()) < 1e-8);
+    }
+}
+
+/// The stochastic EnKF approximates the KF for large ensembles and is reproducible by its seed
+#[test]
+fn stochastic_enkf_approximates_kf() {
+    let dt : TimeStep = 0.01;
+    let mut rw : DiscreteLinearModel = example_model_2states_regular_stable().into_discrete(dt, 1e-5).into();
+    let mat_q = DMatrix::from_row_slice(2, 2, &[0.01, 0.002, 0.002, 0.01]);
+    let mat_p_init = nt::CovarianceMatrix(DMatrix::from_row_slice(2, 2, &[100., 0., 0., 100.]));
+    let vec_x_init = nt::StateVector(DVector::from_row_slice(2, &[0., 0.]));
+
+    let mut kf = mk_kf(&rw, &mat_q);
+    let mut enkfs : Vec<EnsembleKalmanFilter<f64>> = (0..2).map(|_| {
+        mk_enkf_builder(&rw, &mat_q)
+            .with_ensemble_size(1000)
+            .with_seed(42)
+            .with_initial_state(vec_x_init.clone(), mat_p_init.clone())
+            .into()
+    }).collect();
+
+    for i in 0..200 {
+        let t = i as f64 * dt;
+        let u = if t <= 1. { 0. } else { 1. };
+        let u = nt::InputVector(DVector::from_row_slice(1, &[u,]));
+        let y = rw.step(&u);
+
+        kf.predict(&u);
+        kf.measure(nt::Measurement(y.0[(0, 0)]),
+            nt::MeasurementMatrixRow(rw.get_measurement_matrix().0.row(0).clone_owned()),
+            nt::MeasurementNoiseVariance(0.1));
+        for enkf in enkfs.iter_mut() {
+            enkf.predict(&u);
+            enkf.measure(nt::Measurement(y.0[(0, 0)]), 0, nt::MeasurementNoiseVariance(0.1));
+        }
+
+        assert_eq!(enkfs[0].get_ensemble().0, enkfs[1].get_ensemble().0);
+    }
+
+    let kf_state = kf.get_state();
+    let diff = &enkfs[0].get_mean().0 - &kf_state.vec_state.0;
+    let vec_sigma = kf_state.mat_covariances.0.diagonal().map(|v| v.sqrt());
+    assert!(helpers::max(&diff.abs().component_div(&vec_sigma)) < 0.2);
+    let diff = &enkfs[0].get_covariances().0 - &kf_state.mat_covariances.0;
+    assert!(helpers::max(&diff.abs()) < 0.2 * helpers::max(&kf_state.mat_covariances.0.abs()));
+}
+
+/// Noise given by a low-rank square root A of Q = A A^T only moves the members along A
+#[test]
+fn enkf_with_low_rank_system_noise() {
+    let num_states = 500;
+    let vec_a = DVector::from_fn(num_states, |i, _| (i as f64 * 0.01).sin());
+    let mut enkf : EnsembleKalmanFilter<f64> = EnsembleKalmanFilterBuilder
+        ::with_numstates_and_numinputs(num_states, 1)
+        .with_system_noise_square_root(nt::SystemNoiseSquareRootMatrix(
+            DMatrix::from_column_slice(num_states, 1, vec_a.as_slice())))
+        .with_initial_ensemble(nt::EnsembleMatrix(DMatrix::zeros(num_states, 1000)))
+        .into();
+
+    let u = nt::InputVector(DVector::from_row_slice(1, &[0.,]));
+    let ensemble = enkf.predict(&u);
+    let mut sum_squares = 0.;
+    for i in 0..ensemble.ncols() {
+        let w = ensemble[(1, i)] / vec_a[1];
+        let diff = ensemble.column(i) - &vec_a * w;
+        assert!(helpers::max(&diff.abs()) < 1e-12);
+        sum_squares += w * w;
+    }
+    assert!((sum_squares / ensemble.ncols() as f64 - 1.).abs() < 0.1);
+}
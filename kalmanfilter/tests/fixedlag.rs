@@ -0,0 +1,141 @@
+#![allow(dead_code)]
+
+extern crate kalmanfilter;
+extern crate nalgebra as na;
+extern crate num;
+extern crate rand;
+
+mod helpers;
+
+use helpers::types::*;
+use helpers::model::*;
+use kalmanfilter::kf::{KalmanFilterBuilder, KalmanFilter};
+use kalmanfilter::rts::RauchTungStriebelSmoother;
+use kalmanfilter::fixedlag::FixedLagSmoother;
+use kalmanfilter::nt;
+
+use na::{DMatrix, DVector, RowDVector};
+
+
+fn mk_builder(rw : &DiscreteLinearModel) -> KalmanFilterBuilder<f64> {
+    KalmanFilterBuilder
+        ::with_numstates_and_numinputs(rw.get_num_states(), rw.get_num_inputs())
+        .with_system_matrix(rw.get_system_matrix().clone())
+        .with_input_matrix(rw.get_input_matrix().clone())
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(
+            DMatrix::from_row_slice(2, 2, &[0.01, 0.002, 0.002, 0.01])))
+        .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[0., 0.])),
+                            nt::CovarianceMatrix(
+                                DMatrix::from_row_slice(2, 2, &[100., 0., 0., 100.])))
+}
+
+/// x_k-L|k must equal the RTS estimate over the timesteps 0..k
+#[test]
+fn fixed_lag_equals_rts() {
+    let dt : TimeStep = 0.01;
+    let mut rw : DiscreteLinearModel = example_model_2states_regular_stable().into_discrete(dt, 1e-5).into();
+    let lag = 5;
+
+    let mut rts : RauchTungStriebelSmoother<f64> = mk_builder(&rw).into();
+    let mut fls = FixedLagSmoother::from_builder(mk_builder(&rw), lag);
+
+    for i in 0..50 {
+        let t = i as f64 * dt;
+        let u = if t <= 0.2 { 0. } else { 1. };
+        let u = nt::InputVector(DVector::from_row_slice(1, &[u,]));
+        let y = rw.step(&u);
+
+        rts.predict(&u);
+        fls.predict(&u);
+        let rvec_c = rw.get_measurement_matrix().0.row(0).clone_owned();
+        rts.measure(nt::Measurement(y.0[(0, 0)]), nt::MeasurementMatrixRow(rvec_c.clone()),
+                    nt::MeasurementNoiseVariance(0.1));
+        fls.measure(nt::Measurement(y.0[(0, 0)]), nt::MeasurementMatrixRow(rvec_c),
+                    nt::MeasurementNoiseVariance(0.1));
+
+        let smoothed = rts.smooth();
+        let k = i + 1;
+        for j in 0..(lag + 1) {
+            match fls.get_smoothed_state(j) {
+                None => assert!(j > k),
+                Some(state) => {
+                    let diff = &state.vec_state.0 - &smoothed[k - j].vec_state.0;
+                    assert!(helpers::max(&diff.abs()) < 1e-9);
+                    let diff = &state.mat_covariances.0 - &smoothed[k - j].mat_covariances.0;
+                    assert!(helpers::max(&diff.abs()) < 1e-9);
+                }
+            }
+        }
+        assert_eq!(k >= lag, fls.get_delayed_state().is_some());
+    }
+}
+
+/// [ x x ] with the covariance [ P P ; P P ]
+fn with_copy(vec_x : &DVector<f64>, mat_p : &DMatrix<f64>) -> (nt::StateVector<f64>, nt::CovarianceMatrix<f64>) {
+    let n = vec_x.len();
+    let mut vec_x_a = DVector::zeros(2 * n);
+    vec_x_a.rows_mut(0, n).copy_from(vec_x);
+    vec_x_a.rows_mut(n, n).copy_from(vec_x);
+    let mut mat_p_a = DMatrix::zeros(2 * n, 2 * n);
+    for &(r, c) in &[(0, 0), (0, n), (n, 0), (n, n)] {
+        mat_p_a.slice_mut((r, c), (n, n)).copy_from(mat_p);
+    }
+    (nt::StateVector(vec_x_a), nt::CovarianceMatrix(mat_p_a))
+}
+
+/// With consider states, x_k-1|k must equal the Kalman filter of the augmented state
+/// [ x_k x_k-1 ] with the consider states of both copies
+#[test]
+fn fixed_lag_with_consider_states_equals_augmented_filter() {
+    let mat_f = DMatrix::from_row_slice(2, 2, &[1., 0.1, 0., 0.9]);
+    let mat_q = DMatrix::from_row_slice(2, 2, &[0.01, 0., 0., 0.02]);
+    let vec_x = DVector::from_row_slice(2, &[0.5, -0.2]);
+    let mat_p = DMatrix::from_row_slice(2, 2, &[1., 0.3, 0.3, 2.]);
+
+    let mut fls = FixedLagSmoother::from_builder(KalmanFilterBuilder
+        ::with_numstates_and_numinputs(2, 1)
+        .with_system_matrix(nt::DiscreteSystemMatrix(mat_f.clone()))
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(mat_q.clone()))
+        .with_initial_state(nt::StateVector(vec_x.clone()), nt::CovarianceMatrix(mat_p.clone()))
+        .with_consider_states(vec![1]), 1);
+
+    // x_k+1 = F x_k, x_k = x_k
+    let mut mat_f_a = DMatrix::zeros(4, 4);
+    mat_f_a.slice_mut((0, 0), (2, 2)).copy_from(&mat_f);
+    mat_f_a.slice_mut((2, 0), (2, 2)).copy_from(&DMatrix::<f64>::identity(2, 2));
+    let mut mat_q_a = DMatrix::zeros(4, 4);
+    mat_q_a.slice_mut((0, 0), (2, 2)).copy_from(&mat_q);
+    let (vec_x_a, mat_p_a) = with_copy(&vec_x, &mat_p);
+    let mut akf : KalmanFilter<f64> = KalmanFilterBuilder
+        ::with_numstates_and_numinputs(4, 1)
+        .with_system_matrix(nt::DiscreteSystemMatrix(mat_f_a))
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(mat_q_a))
+        .with_initial_state(vec_x_a, mat_p_a)
+        .with_consider_states(vec![1, 3])
+        .into();
+
+    let u = nt::InputVector(DVector::zeros(1));
+    for &y in &[0.7, 0.4, 1.1, 0.9, 1.5] {
+        fls.predict(&u);
+        akf.predict(&u);
+        fls.measure(nt::Measurement(y), nt::MeasurementMatrixRow(RowDVector::from_row_slice(2, &[1., 0.5])),
+                    nt::MeasurementNoiseVariance(0.1));
+        akf.measure(nt::Measurement(y), nt::MeasurementMatrixRow(RowDVector::from_row_slice(4, &[1., 0.5, 0., 0.])),
+                    nt::MeasurementNoiseVariance(0.1));
+
+        let (vec_x_k, mat_p_k) = {
+            let lagged = fls.get_smoothed_state(1).unwrap();
+            let augmented = akf.get_state();
+            let diff = &lagged.vec_state.0 - augmented.vec_state.0.rows(2, 2);
+            assert!(helpers::max(&diff.abs()) < 1e-12);
+            let diff = &lagged.mat_covariances.0 - augmented.mat_covariances.0.slice((2, 2), (2, 2));
+            assert!(helpers::max(&diff.abs()) < 1e-12);
+            (augmented.vec_state.0.rows(0, 2).clone_owned(),
+             augmented.mat_covariances.0.slice((0, 0), (2, 2)).clone_owned())
+        };
+
+        // Like the smoother, the augmented filter lags the current state during the next prediction
+        let (vec_x_a, mat_p_a) = with_copy(&vec_x_k, &mat_p_k);
+        akf.set_state(vec_x_a, mat_p_a);
+    }
+}
@@ -0,0 +1,69 @@
+#![allow(dead_code)]
+
+extern crate kalmanfilter;
+extern crate nalgebra as na;
+extern crate num;
+extern crate rand;
+
+mod helpers;
+
+use helpers::types::*;
+use helpers::model::*;
+use kalmanfilter::kf::KalmanFilterBuilder;
+use kalmanfilter::rts::RauchTungStriebelSmoother;
+use kalmanfilter::fixedpoint::FixedPointSmoother;
+use kalmanfilter::nt;
+
+use na::{DMatrix, DVector};
+
+
+fn mk_builder(rw : &DiscreteLinearModel) -> KalmanFilterBuilder<f64> {
+    KalmanFilterBuilder
+        ::with_numstates_and_numinputs(rw.get_num_states(), rw.get_num_inputs())
+        .with_system_matrix(rw.get_system_matrix().clone())
+        .with_input_matrix(rw.get_input_matrix().clone())
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(
+            DMatrix::from_row_slice(2, 2, &[0.01, 0.002, 0.002, 0.01])))
+        .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[0., 0.])),
+                            nt::CovarianceMatrix(
+                                DMatrix::from_row_slice(2, 2, &[100., 0., 0., 100.])))
+}
+
+/// x_j|k must equal the RTS estimate of timestep j over the timesteps 0..k, first for the
+/// initial state (j = 0), then for j = 20
+#[test]
+fn fixed_point_equals_rts() {
+    let dt : TimeStep = 0.01;
+    let mut rw : DiscreteLinearModel = example_model_2states_regular_stable().into_discrete(dt, 1e-5).into();
+
+    let mut rts : RauchTungStriebelSmoother<f64> = mk_builder(&rw).into();
+    let mut fps : FixedPointSmoother<f64> = mk_builder(&rw).into();
+    let mut j = 0;
+
+    for i in 0..50 {
+        let t = i as f64 * dt;
+        let u = if t <= 0.2 { 0. } else { 1. };
+        let u = nt::InputVector(DVector::from_row_slice(1, &[u,]));
+        let y = rw.step(&u);
+
+        if i == 20 {
+            fps.fix_current_state();
+            j = 20;
+        }
+
+        rts.predict(&u);
+        fps.predict(&u);
+        let rvec_c = rw.get_measurement_matrix().0.row(0).clone_owned();
+        rts.measure(nt::Measurement(y.0[(0, 0)]), nt::MeasurementMatrixRow(rvec_c.clone()),
+                    nt::MeasurementNoiseVariance(0.1));
+        fps.measure(nt::Measurement(y.0[(0, 0)]), nt::MeasurementMatrixRow(rvec_c),
+                    nt::MeasurementNoiseVariance(0.1));
+
+        let smoothed = rts.smooth();
+        let state = fps.get_fixed_point_state();
+        let diff = &state.vec_state.0 - &smoothed[j].vec_state.0;
+        assert!(helpers::max(&diff.abs()) < 1e-9);
+        let diff = &state.mat_covariances.0 - &smoothed[j].mat_covariances.0;
+        assert!(helpers::max(&diff.abs()) < 1e-9);
+    }
+}
@@ -1,7 +1,7 @@
 
 use na::{DVector, DMatrix};
 use rand::distributions::{Normal, IndependentSample};
-use rand::thread_rng;
+use rand::{thread_rng, StdRng, SeedableRng};
 
 use kalmanfilter::systems::continuous_to_discrete;
 use kalmanfilter::nt;
@@ -137,6 +137,7 @@ pub struct DiscreteLinearModel {
     num_states : usize,
     system_noise_gen : Vec<Normal>,
     measurement_noise_gen : Vec<Normal>,
+    rng : StdRng,
 }
 
 fn mk_noise_generators(variances : &DVector<f64>) -> Vec<Normal> {
@@ -170,21 +171,28 @@ impl From<DiscreteLinearModelBuilder> for DiscreteLinearModel {
             num_states : num_states,
             system_noise_gen : system_noise_gen,
             measurement_noise_gen : measurement_noise_gen,
+            rng : StdRng::new().expect("Cannot seed the noise generator"),
         }
     }
 }
 
 impl DiscreteLinearModel {
 
+    /// Makes the drawn noise reproducible
+    pub fn with_seed(mut self, seed : usize) -> Self {
+        self.rng = StdRng::from_seed(&[seed][..]);
+        self
+    }
+
     pub fn step(&mut self, u : &InputVector) -> Measurements {
         assert_eq!(self.num_inputs, u.nrows());
 
         for (gen, n) in self.system_noise_gen.iter().zip(self.vec_w.iter_mut()) {
-            *n = gen.ind_sample(&mut thread_rng());
+            *n = gen.ind_sample(&mut self.rng);
         }
 
         for (gen, n) in self.measurement_noise_gen.iter().zip(self.vec_r.iter_mut()) {
-            *n = gen.ind_sample(&mut thread_rng());
+            *n = gen.ind_sample(&mut self.rng);
         }
 
         self.vec_x.0 = &self.mat_f.0 * &self.vec_x.0 + &self.mat_h.0 * &u.0 + &self.vec_w.0;
@@ -0,0 +1,159 @@
+#![allow(dead_code)]
+
+extern crate kalmanfilter;
+extern crate nalgebra as na;
+extern crate num;
+extern crate rand;
+
+mod helpers;
+
+use helpers::types::*;
+use helpers::model::*;
+use kalmanfilter::kf::{KalmanFilterBuilder, KalmanFilter, InnovationGate};
+use kalmanfilter::imm::{InteractingMultipleModelBuilder, InteractingMultipleModel};
+use kalmanfilter::nt;
+
+use na::{DMatrix, DVector, RowDVector};
+
+
+fn mk_kf(rw : &DiscreteLinearModel) -> KalmanFilter<f64> {
+    KalmanFilterBuilder
+        ::with_numstates_and_numinputs(rw.get_num_states(), rw.get_num_inputs())
+        .with_system_matrix(rw.get_system_matrix().clone())
+        .with_input_matrix(rw.get_input_matrix().clone())
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(
+            DMatrix::from_row_slice(2, 2, &[0.01, 0.002, 0.002, 0.01])))
+        .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[0., 0.])),
+                            nt::CovarianceMatrix(
+                                DMatrix::from_row_slice(2, 2, &[100., 0., 0., 100.])))
+        .into()
+}
+
+/// Mixing identical modes must not change anything
+#[test]
+fn imm_with_identical_modes_equals_kf() {
+    let dt : TimeStep = 0.01;
+    let mut rw : DiscreteLinearModel = example_model_2states_regular_stable().into_discrete(dt, 1e-5).into();
+
+    let mut kf = mk_kf(&rw);
+    let mut imm : InteractingMultipleModel<f64> = InteractingMultipleModelBuilder
+        ::with_numstates(2)
+        .with_mode(mk_kf(&rw))
+        .with_mode(mk_kf(&rw))
+        .with_transition_matrix(nt::ModeTransitionMatrix(DMatrix::from_row_slice(2, 2, &[0.9, 0.1, 0.2, 0.8])))
+        .into();
+
+    for i in 0..100 {
+        let t = i as f64 * dt;
+        let u = if t <= 0.5 { 0. } else { 1. };
+        let u = nt::InputVector(DVector::from_row_slice(1, &[u,]));
+        let y = rw.step(&u);
+
+        kf.predict(&u);
+        imm.predict(&u);
+        let rvec_c = rw.get_measurement_matrix().0.row(0).clone_owned();
+        kf.measure(nt::Measurement(y.0[(0, 0)]), nt::MeasurementMatrixRow(rvec_c.clone()),
+                   nt::MeasurementNoiseVariance(0.1));
+        imm.measure(nt::Measurement(y.0[(0, 0)]), nt::MeasurementMatrixRow(rvec_c),
+                    nt::MeasurementNoiseVariance(0.1));
+
+        // Identical likelihoods, the probabilities converge to the stationary distribution
+        // of the Markov chain
+        if i >= 50 {
+            let mode_probabilities = imm.get_mode_probabilities();
+            assert!((mode_probabilities[0] - 2. / 3.).abs() < 1e-6);
+        }
+
+        let (vec_x, mat_p) = imm.get_state();
+        let diff = &vec_x.0 - &kf.get_state().vec_state.0;
+        assert!(helpers::max(&diff.abs()) < 1e-9);
+        let diff = &mat_p.0 - &kf.get_state().mat_covariances.0;
+        assert!(helpers::max(&diff.abs()) < 1e-9);
+    }
+}
+
+/// A target first stands still and then moves with constant velocity. The stationary mode
+/// estimates only the position, the constant velocity mode position and velocity.
+#[test]
+fn imm_detects_maneuver() {
+    let dt = 0.1;
+    let stationary : KalmanFilter<f64> = KalmanFilterBuilder
+        ::with_numstates_and_numinputs(1, 1)
+        .with_system_matrix(nt::DiscreteSystemMatrix(DMatrix::from_element(1, 1, 1.)))
+        .with_input_matrix(nt::DiscreteInputMatrix(DMatrix::zeros(1, 1)))
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(DMatrix::from_element(1, 1, 1e-6)))
+        .with_initial_state(nt::StateVector(DVector::zeros(1)),
+                            nt::CovarianceMatrix(DMatrix::from_element(1, 1, 1.)))
+        .into();
+    let constant_velocity : KalmanFilter<f64> = KalmanFilterBuilder
+        ::with_numstates_and_numinputs(2, 1)
+        .with_system_matrix(nt::DiscreteSystemMatrix(DMatrix::from_row_slice(2, 2, &[1., dt, 0., 1.])))
+        .with_input_matrix(nt::DiscreteInputMatrix(DMatrix::zeros(2, 1)))
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(
+            DMatrix::from_row_slice(2, 2, &[1e-4, 0., 0., 1e-2])))
+        .with_initial_state(nt::StateVector(DVector::zeros(2)),
+                            nt::CovarianceMatrix(DMatrix::identity(2, 2)))
+        .into();
+
+    let mut imm : InteractingMultipleModel<f64> = InteractingMultipleModelBuilder
+        ::with_numstates(2)
+        .with_mapped_mode(stationary, DMatrix::from_row_slice(2, 1, &[1., 0.]))
+        .with_mode(constant_velocity)
+        .with_transition_matrix(nt::ModeTransitionMatrix(DMatrix::from_row_slice(2, 2, &[0.95, 0.05, 0.05, 0.95])))
+        .into();
+
+    let u = nt::InputVector(DVector::zeros(1));
+    let rvec_c = nt::MeasurementMatrixRow(na::RowDVector::from_row_slice(2, &[1., 0.]));
+    let mut position = 0.;
+    for i in 0..100 {
+        if i >= 50 {
+            position += dt;
+        }
+        imm.predict(&u);
+        imm.measure(nt::Measurement(position), rvec_c.clone(), nt::MeasurementNoiseVariance(0.01));
+
+        if i == 49 {
+            assert!(imm.get_mode_probabilities()[0] > 0.5);
+            let (vec_x, _) = imm.get_state();
+            assert!(vec_x[0].abs() < 0.1);
+        }
+    }
+
+    assert!(imm.get_mode_probabilities()[1] > 0.9);
+    let (vec_x, mat_p) = imm.get_state();
+    assert!((vec_x[0] - position).abs() < 0.1);
+    assert!((vec_x[1] - 1.).abs() < 0.1);
+    assert!(mat_p[(0, 0)] > 0. && mat_p[(1, 1)] > 0.);
+}
+
+/// A measurement rejected by the gate of one mode shifts the probability to the other mode,
+/// whose state is updated, while the state of the rejecting mode stays unchanged
+#[test]
+fn imm_shifts_probability_away_from_rejecting_mode() {
+    let mk_mode = |variance : f64| -> KalmanFilter<f64> {
+        KalmanFilterBuilder
+            ::with_numstates_and_numinputs(1, 1)
+            .with_initial_state(nt::StateVector(DVector::from_row_slice(1, &[0.])),
+                                nt::CovarianceMatrix(DMatrix::from_row_slice(1, 1, &[variance])))
+            .with_innovation_gate(InnovationGate::Reject(vec![9.]))
+            .into()
+    };
+    let mut imm : InteractingMultipleModel<f64> = InteractingMultipleModelBuilder
+        ::with_numstates(1)
+        .with_mode(mk_mode(1.))
+        .with_mode(mk_mode(100.))
+        .with_transition_matrix(nt::ModeTransitionMatrix(DMatrix::identity(2, 2)))
+        .into();
+    let rvec_c = RowDVector::from_row_slice(1, &[1.]);
+
+    // Mode 0: S = 2, NIS = 50 (rejected), mode 1: S = 101, NIS = 0.99
+    let mode_probabilities = imm.measure(nt::Measurement(10.), nt::MeasurementMatrixRow(rvec_c),
+                                         nt::MeasurementNoiseVariance(1.)).clone();
+    assert!(mode_probabilities[1] > 0.99);
+    assert!((mode_probabilities[0] + mode_probabilities[1] - 1.).abs() < 1e-12);
+
+    // Combined estimate is dominated by the updated mode 1, x = 10 * 100 / 101
+    let vec_x = imm.get_state().0;
+    assert!((vec_x.0[0] - 1000. / 101.).abs() < 0.1);
+    assert_eq!(0., imm.get_filter(0).get_state().vec_state.0[0]);
+}
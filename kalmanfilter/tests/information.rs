@@ -0,0 +1,149 @@
+#![allow(dead_code)]
+
+extern crate kalmanfilter;
+extern crate nalgebra as na;
+extern crate num;
+extern crate rand;
+
+mod helpers;
+
+use helpers::types::*;
+use helpers::model::*;
+use kalmanfilter::kf::{KalmanFilterBuilder, KalmanFilter};
+use kalmanfilter::information::{InformationFilterBuilder, InformationFilter};
+use kalmanfilter::nt;
+
+use na::{DMatrix, DVector, RowDVector};
+
+
+#[test]
+fn information_filter_equals_kf() {
+    let dt : TimeStep = 0.01;
+    let mut rw : DiscreteLinearModel = example_model_2states_regular_stable().into_discrete(dt, 1e-5).into();
+    let steps = 200;
+
+    let mut kf : KalmanFilter<f64> = KalmanFilterBuilder
+        ::with_numstates_and_numinputs(rw.get_num_states(), rw.get_num_inputs())
+        .with_system_matrix(rw.get_system_matrix().clone())
+        .with_input_matrix(rw.get_input_matrix().clone())
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(
+            DMatrix::from_row_slice(2, 2, &[0.01, 0., 0., 0.01])))
+        .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[0.2, -0.1])),
+                            nt::CovarianceMatrix(
+                                DMatrix::from_row_slice(2, 2, &[10., 1., 1., 10.])))
+        .into();
+    let mut inf : InformationFilter<f64> = InformationFilter::from(
+        KalmanFilter::from(KalmanFilterBuilder
+            ::with_numstates_and_numinputs(rw.get_num_states(), rw.get_num_inputs())
+            .with_system_matrix(rw.get_system_matrix().clone())
+            .with_input_matrix(rw.get_input_matrix().clone())
+            .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(
+                DMatrix::from_row_slice(2, 2, &[0.01, 0., 0., 0.01])))
+            .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[0.2, -0.1])),
+                                nt::CovarianceMatrix(
+                                    DMatrix::from_row_slice(2, 2, &[10., 1., 1., 10.])))));
+
+    for i in 0..steps {
+        let t = i as f64 * dt;
+        let u = if t <= 1. { 0. } else { 1. };
+        let u = nt::InputVector(DVector::from_row_slice(1, &[u,]));
+        let y = rw.step(&u);
+
+        kf.predict(&u);
+        inf.predict(&u);
+
+        let rvec_c = rw.get_measurement_matrix().0.row(0).clone_owned();
+        kf.measure(nt::Measurement(y.0[(0, 0)]),
+            nt::MeasurementMatrixRow(rvec_c.clone()),
+            nt::MeasurementNoiseVariance(0.1));
+        inf.measure(nt::Measurement(y.0[(0, 0)]),
+            nt::MeasurementMatrixRow(rvec_c),
+            nt::MeasurementNoiseVariance(0.1));
+
+        let (vec_state, mat_p) = inf.get_state().unwrap();
+        let diff = &vec_state.0 - &kf.get_state().vec_state.0;
+        assert!(helpers::max(&diff.abs()) < 1e-9);
+        let diff = &mat_p.0 - &kf.get_state().mat_covariances.0;
+        assert!(helpers::max(&diff.abs()) < 1e-9);
+    }
+
+    let kf_converted = inf.to_kalman_filter().unwrap();
+    let diff = &kf_converted.get_state().vec_state.0 - &kf.get_state().vec_state.0;
+    assert!(helpers::max(&diff.abs()) < 1e-9);
+}
+
+#[test]
+fn information_filter_without_prior_knowledge() {
+    let mut inf : InformationFilter<f64> = InformationFilterBuilder
+        ::with_numstates_and_numinputs(2, 1)
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(
+            DMatrix::from_row_slice(2, 2, &[0.01, 0., 0., 0.01])))
+        .into();
+    let u = nt::InputVector(DVector::from_row_slice(1, &[0.,]));
+    let rvec_c = RowDVector::from_row_slice(2, &[1., 1.]);
+
+    inf.predict(&u);
+    assert!(inf.get_state().is_none());
+
+    // y = x_0 + x_1 alone does not determine the state
+    inf.measure(nt::Measurement(3.), nt::MeasurementMatrixRow(rvec_c), nt::MeasurementNoiseVariance(0.01));
+    assert!(inf.get_state().is_none());
+
+    inf.measure_vector(nt::MeasurementVector(DVector::from_row_slice(2, &[1., 2.])),
+        nt::MeasurementMatrix(DMatrix::identity(2, 2)),
+        nt::MeasurementNoiseCovarianceMatrix(DMatrix::from_diagonal_element(2, 2, 0.01)));
+    let (vec_state, _) = inf.get_state().unwrap();
+    assert!((vec_state.0[0] - 1.).abs() < 1e-9);
+    assert!((vec_state.0[1] - 2.).abs() < 1e-9);
+}
+
+#[test]
+fn information_filter_rejects_nearly_singular_information() {
+    let mut inf : InformationFilter<f64> = InformationFilterBuilder
+        ::with_numstates_and_numinputs(3, 1)
+        .with_system_matrix(nt::DiscreteSystemMatrix(
+            DMatrix::from_row_slice(3, 3, &[1., 0.1, 0., 0., 1., 0.1, 0., 0., 1.])))
+        .into();
+    let u = nt::InputVector(DVector::from_row_slice(1, &[0.,]));
+
+    // Two scalar measurements of three states, Y has rank 2 apart from rounding errors
+    inf.measure(nt::Measurement(1.),
+        nt::MeasurementMatrixRow(RowDVector::from_row_slice(3, &[0.7, 0.3, 0.4])),
+        nt::MeasurementNoiseVariance(0.01));
+    inf.predict(&u);
+    inf.measure(nt::Measurement(2.),
+        nt::MeasurementMatrixRow(RowDVector::from_row_slice(3, &[1., 2., 3.])),
+        nt::MeasurementNoiseVariance(0.01));
+    assert!(inf.get_state().is_none());
+    assert!(inf.to_kalman_filter().is_none());
+
+    inf.measure(nt::Measurement(3.),
+        nt::MeasurementMatrixRow(RowDVector::from_row_slice(3, &[0.2, 0.1, 0.9])),
+        nt::MeasurementNoiseVariance(0.01));
+    assert!(inf.get_state().is_some());
+}
+
+#[test]
+fn information_filter_returns_badly_scaled_state() {
+    let inf : InformationFilter<f64> = InformationFilterBuilder
+        ::with_numstates_and_numinputs(2, 1)
+        .with_initial_information(nt::InformationVector(DVector::from_row_slice(2, &[1e-4, 1e4])),
+            nt::InformationMatrix(DMatrix::from_row_slice(2, 2, &[1e-4, 0., 0., 1e4])))
+        .into();
+
+    // Variances 1e4 and 1e-4 are well determined, despite the condition number of 1e8
+    let (vec_state, mat_p) = inf.get_state().unwrap();
+    assert!((vec_state.0[0] - 1.).abs() < 1e-9);
+    assert!((vec_state.0[1] - 1.).abs() < 1e-9);
+    assert!((mat_p.0[(0, 0)] - 1e4).abs() < 1e-6);
+    assert!((mat_p.0[(1, 1)] - 1e-4).abs() < 1e-12);
+
+    // A stricter tolerance rejects it
+    let inf : InformationFilter<f64> = InformationFilterBuilder
+        ::with_numstates_and_numinputs(2, 1)
+        .with_initial_information(nt::InformationVector(DVector::from_row_slice(2, &[1e-4, 1e4])),
+            nt::InformationMatrix(DMatrix::from_row_slice(2, 2, &[1e-4, 0., 0., 1e4])))
+        .with_singularity_tolerance(1e-6)
+        .into();
+    assert!(inf.get_state().is_none());
+}
@@ -0,0 +1,98 @@
+#![allow(dead_code)]
+
+extern crate kalmanfilter;
+extern crate nalgebra as na;
+extern crate num;
+extern crate rand;
+
+mod helpers;
+
+use kalmanfilter::kf::{KalmanFilterBuilder, KalmanFilter};
+use kalmanfilter::mmae::{MultipleModelAdaptiveEstimatorBuilder, MultipleModelAdaptiveEstimator};
+use kalmanfilter::nt;
+
+use na::{DMatrix, DVector, RowDVector};
+use rand::{StdRng, SeedableRng};
+use rand::distributions::{Normal, IndependentSample};
+
+
+/// x_k+1 = a x_k + b u_k + w_k
+fn mk_kf(a : f64, b : f64) -> KalmanFilter<f64> {
+    KalmanFilterBuilder
+        ::with_numstates_and_numinputs(1, 1)
+        .with_system_matrix(nt::DiscreteSystemMatrix(DMatrix::from_element(1, 1, a)))
+        .with_input_matrix(nt::DiscreteInputMatrix(DMatrix::from_element(1, 1, b)))
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(DMatrix::from_element(1, 1, 0.01)))
+        .with_initial_state(nt::StateVector(DVector::zeros(1)),
+                            nt::CovarianceMatrix(DMatrix::from_element(1, 1, 1.)))
+        .into()
+}
+
+/// Identifies the pole of a first order system from a set of candidates
+#[test]
+fn mmae_identifies_parameter() {
+    let mut rng = StdRng::from_seed(&[1usize][..]);
+    let process_noise = Normal::new(0., 0.1);
+    let measurement_noise = Normal::new(0., 0.1);
+
+    let candidates = [0.5, 0.8, 0.9, 0.99];
+    let mut mmae : MultipleModelAdaptiveEstimator<f64> = candidates.iter()
+        .fold(MultipleModelAdaptiveEstimatorBuilder::new(),
+              |builder, &a| builder.with_hypothesis(mk_kf(a, 1.)))
+        .into();
+
+    let mut x = 0.;
+    for k in 0..300 {
+        let u = if (k / 20) % 2 == 0 { 1. } else { -1. };
+        x = 0.9 * x + u + process_noise.ind_sample(&mut rng);
+        let y = x + measurement_noise.ind_sample(&mut rng);
+
+        mmae.predict(&nt::InputVector(DVector::from_element(1, u)));
+        let innovations = mmae.measure(nt::Measurement(y),
+                                       nt::MeasurementMatrixRow(RowDVector::from_element(1, 1.)),
+                                       nt::MeasurementNoiseVariance(0.01));
+        assert_eq!(candidates.len(), innovations.len());
+    }
+
+    assert_eq!(2, mmae.get_most_probable());
+    assert!(mmae.get_probabilities()[2] > 0.99);
+    let log_likelihoods = mmae.get_log_likelihoods();
+    assert!(log_likelihoods.iter().all(|&l| l <= log_likelihoods[2]));
+    let (vec_x, mat_p) = mmae.get_state();
+    assert!((vec_x[0] - x).abs() < 3. * mat_p[(0, 0)].sqrt() + 0.1);
+}
+
+/// An actuator fails during operation, the lower bound of the probabilities allows to detect it
+#[test]
+fn mmae_detects_fault() {
+    let mut rng = StdRng::from_seed(&[2usize][..]);
+    let measurement_noise = Normal::new(0., 0.1);
+
+    let mut mmae : MultipleModelAdaptiveEstimator<f64> = MultipleModelAdaptiveEstimatorBuilder::new()
+        .with_hypothesis(mk_kf(0.9, 1.))
+        .with_hypothesis(mk_kf(0.9, 0.))
+        .with_prior_probabilities(nt::HypothesisProbabilities(DVector::from_row_slice(2, &[0.99, 0.01])))
+        .with_minimum_probability(1e-3)
+        .into();
+
+    let mut x = 0.;
+    for k in 0..200 {
+        let b = if k < 100 { 1. } else { 0. };
+        let u = if (k / 10) % 2 == 0 { 1. } else { -1. };
+        x = 0.9 * x + b * u;
+        let y = x + measurement_noise.ind_sample(&mut rng);
+
+        mmae.predict(&nt::InputVector(DVector::from_element(1, u)));
+        mmae.measure(nt::Measurement(y),
+                     nt::MeasurementMatrixRow(RowDVector::from_element(1, 1.)),
+                     nt::MeasurementNoiseVariance(0.01));
+
+        assert!(mmae.get_probabilities().iter().all(|&mu| mu >= 1e-3 * 0.99));
+        if k == 99 {
+            assert_eq!(0, mmae.get_most_probable());
+        }
+    }
+
+    assert_eq!(1, mmae.get_most_probable());
+    assert!(mmae.get_probabilities()[1] > 0.9);
+}
@@ -0,0 +1,171 @@
+#![allow(dead_code)]
+
+extern crate kalmanfilter;
+extern crate nalgebra as na;
+extern crate num;
+extern crate rand;
+
+mod helpers;
+
+use helpers::types::*;
+use helpers::model::*;
+use kalmanfilter::kf::{KalmanFilterBuilder, KalmanFilter};
+use kalmanfilter::pf::{ParticleFilterBuilder, ParticleFilter, Resampling};
+use kalmanfilter::nt;
+
+use na::{DMatrix, DVector};
+use rand::{StdRng, SeedableRng};
+use rand::distributions::{Normal, IndependentSample};
+
+
+fn mk_pf(rw : &DiscreteLinearModel, auxiliary : bool, resampling : Resampling) -> ParticleFilter<f64> {
+    let mat_f = rw.get_system_matrix().clone();
+    let mat_h = rw.get_input_matrix().clone();
+    let mat_c = rw.get_measurement_matrix().0.clone();
+    let (mat_f2, mat_h2) = (mat_f.clone(), mat_h.clone());
+    // Q = diag(0.01, 0.01), R = 0.1
+    let builder = ParticleFilterBuilder
+        ::with_numstates_and_numinputs(rw.get_num_states(), rw.get_num_inputs())
+        .with_process_sampler(move |x, u, rng| {
+            let normal = Normal::new(0.0, 0.1);
+            let vec_w = DVector::from_fn(2, |_, _| normal.ind_sample(rng));
+            nt::StateVector(&mat_f.0 * &x.0 + &mat_h.0 * &u.0 + vec_w)
+        })
+        .with_measurement_likelihood(move |x, y| {
+            let residual = y.0[0] - (&mat_c * &x.0)[0];
+            (-0.5 * residual * residual / 0.1).exp()
+        })
+        .with_resampling(resampling)
+        .with_num_particles(2000)
+        .with_seed(7)
+        .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[0., 0.])),
+                            nt::CovarianceMatrix(DMatrix::from_row_slice(2, 2, &[1., 0., 0., 1.])));
+    if auxiliary {
+        builder.with_point_prediction(move |x, u| nt::StateVector(&mat_f2.0 * &x.0 + &mat_h2.0 * &u.0))
+               .into()
+    } else {
+        builder.into()
+    }
+}
+
+/// Bootstrap and auxiliary particle filter must approximate the KF for a linear Gaussian model
+#[test]
+fn pf_approximates_kf() {
+    let dt : TimeStep = 0.01;
+
+    for &(auxiliary, resampling) in [(false, Resampling::Systematic),
+                                     (false, Resampling::Stratified),
+                                     (false, Resampling::Residual),
+                                     (false, Resampling::Multinomial),
+                                     (true, Resampling::Systematic)].iter() {
+        let mut rw : DiscreteLinearModel = example_model_2states_regular_stable().into_discrete(dt, 1e-5).into();
+        let mut kf : KalmanFilter<f64> = KalmanFilterBuilder
+            ::with_numstates_and_numinputs(rw.get_num_states(), rw.get_num_inputs())
+            .with_system_matrix(rw.get_system_matrix().clone())
+            .with_input_matrix(rw.get_input_matrix().clone())
+            .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(
+                DMatrix::from_row_slice(2, 2, &[0.01, 0., 0., 0.01])))
+            .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[0., 0.])),
+                                nt::CovarianceMatrix(DMatrix::from_row_slice(2, 2, &[1., 0., 0., 1.])))
+            .into();
+        let mut pf = mk_pf(&rw, auxiliary, resampling);
+
+        let mut num_resampled = 0;
+        for i in 0..100 {
+            let t = i as f64 * dt;
+            let u = if t <= 0.5 { 0. } else { 1. };
+            let u = nt::InputVector(DVector::from_row_slice(1, &[u,]));
+            let y = rw.step(&u);
+
+            kf.predict(&u);
+            kf.measure(nt::Measurement(y.0[(0, 0)]),
+                nt::MeasurementMatrixRow(rw.get_measurement_matrix().0.row(0).clone_owned()),
+                nt::MeasurementNoiseVariance(0.1));
+            let update = pf.step(&u, &nt::MeasurementVector(DVector::from_element(1, y.0[(0, 0)])));
+            assert!(update.effective_sample_size >= 1.);
+            assert!(update.effective_sample_size <= 2000. + 1e-6);
+            if update.resampled {
+                num_resampled += 1;
+            }
+        }
+        // The auxiliary filter resamples in its first stage anyway
+        if !auxiliary {
+            assert!(num_resampled > 0);
+        }
+
+        let kf_state = kf.get_state();
+        let (vec_x, mat_p) = pf.get_state();
+        let vec_sigma = kf_state.mat_covariances.0.diagonal().map(|v| v.sqrt());
+        let diff = &vec_x.0 - &kf_state.vec_state.0;
+        assert!(helpers::max(&diff.abs().component_div(&vec_sigma)) < 0.3);
+        let diff = &mat_p.0 - &kf_state.mat_covariances.0;
+        assert!(helpers::max(&diff.abs()) < 0.3 * helpers::max(&kf_state.mat_covariances.0.abs()));
+    }
+}
+
+/// Systematic and residual resampling keep floor(N w_i) to ceil(N w_i) copies of particle i
+#[test]
+fn resampling_schemes() {
+    let mut rng = StdRng::from_seed(&[1usize][..]);
+    let weights = nt::ParticleWeights(DVector::from_row_slice(5, &[0.05, 0.4, 0.0, 0.3, 0.25]));
+    let n = 20;
+    let weights = nt::ParticleWeights(DVector::from_fn(n, |i, _| weights[i % 5] / 4.));
+
+    for &resampling in [Resampling::Systematic, Resampling::Residual, Resampling::Stratified,
+                        Resampling::Multinomial].iter() {
+        for _ in 0..50 {
+            let indices = resampling.indices(&weights, &mut rng);
+            assert_eq!(n, indices.len());
+            let mut counts = vec![0usize; n];
+            for &k in indices.iter() {
+                counts[k] += 1;
+            }
+            // Particles without weight are never selected
+            for i in (0..n).filter(|i| i % 5 == 2) {
+                assert_eq!(0, counts[i]);
+            }
+            if resampling == Resampling::Systematic || resampling == Resampling::Residual {
+                for i in 0..n {
+                    let expected = weights[i] * n as f64;
+                    assert!(counts[i] as f64 >= expected.floor() - 1e-9);
+                }
+            }
+            if resampling == Resampling::Systematic {
+                for i in 0..n {
+                    let expected = weights[i] * n as f64;
+                    assert!(counts[i] as f64 <= expected.ceil() + 1e-9);
+                }
+            }
+        }
+    }
+}
+
+/// A measurement 50 sigma away from all particles underflows every likelihood
+#[test]
+fn pf_survives_vanishing_likelihoods() {
+    for &auxiliary in [false, true].iter() {
+        let builder = ParticleFilterBuilder
+            ::with_numstates_and_numinputs(1, 1)
+            .with_measurement_likelihood(|x, y| {
+                let residual : f64 = y.0[0] - x.0[0];
+                (-0.5 * residual * residual / 0.01).exp()
+            })
+            .with_num_particles(100)
+            .with_initial_state(nt::StateVector(DVector::from_row_slice(1, &[0.])),
+                                nt::CovarianceMatrix(DMatrix::from_row_slice(1, 1, &[0.0001])));
+        let mut pf : ParticleFilter<f64> = if auxiliary {
+            builder.with_point_prediction(|x, _| x.clone()).into()
+        } else {
+            builder.into()
+        };
+        let u = nt::InputVector(DVector::from_row_slice(1, &[0.]));
+
+        let update = pf.step(&u, &nt::MeasurementVector(DVector::from_row_slice(1, &[5.])));
+        assert!(update.weights_vanished);
+        assert!(pf.get_weights().iter().all(|&w| (w - 0.01).abs() < 1e-12));
+
+        let update = pf.step(&u, &nt::MeasurementVector(DVector::from_row_slice(1, &[0.])));
+        assert!(!update.weights_vanished);
+        assert!(pf.get_state().0[0].abs() < 0.01);
+    }
+}
@@ -0,0 +1,160 @@
+#![allow(dead_code)]
+
+extern crate kalmanfilter;
+extern crate nalgebra as na;
+extern crate num;
+extern crate rand;
+
+mod helpers;
+
+use helpers::types::*;
+use helpers::model::*;
+use kalmanfilter::kf::{KalmanFilterBuilder, KalmanFilter, InnovationGate, GatingDecision};
+use kalmanfilter::sckf::StochasticCloningKalmanFilter;
+use kalmanfilter::nt;
+
+use na::{DMatrix, DVector, RowDVector};
+
+
+/// Compares the stochastic cloning filter with a `KalmanFilter` that works on the full
+/// augmented state [x_k; x_l] with F_aug = diag(F, I).
+#[test]
+fn sckf_equals_augmented_kf() {
+    let dt : TimeStep = 0.01;
+    let mut rw : DiscreteLinearModel = example_model_2states_regular_stable().into_discrete(dt, 1e-5).into();
+    let steps = 200;
+    let clone_at = 50;
+    let relative_at = 150;
+
+    let mat_f = rw.get_system_matrix().0.clone();
+    let mat_h = rw.get_input_matrix().0.clone();
+    let mat_q = DMatrix::from_row_slice(2, 2, &[0.01, 0., 0., 0.01]);
+
+    let mut sckf : StochasticCloningKalmanFilter<f64> = KalmanFilterBuilder
+        ::with_numstates_and_numinputs(2, 1)
+        .with_system_matrix(nt::DiscreteSystemMatrix(mat_f.clone()))
+        .with_input_matrix(nt::DiscreteInputMatrix(mat_h.clone()))
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(mat_q.clone()))
+        .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[0., 0.])),
+                            nt::CovarianceMatrix(
+                                DMatrix::from_row_slice(2, 2, &[100., 0., 0., 100.])))
+        .into();
+
+    let mut mat_f_aug = DMatrix::identity(4, 4);
+    mat_f_aug.slice_mut((0, 0), (2, 2)).copy_from(&mat_f);
+    let mut mat_h_aug = DMatrix::zeros(4, 1);
+    mat_h_aug.slice_mut((0, 0), (2, 1)).copy_from(&mat_h);
+    let mut mat_q_aug = DMatrix::zeros(4, 4);
+    mat_q_aug.slice_mut((0, 0), (2, 2)).copy_from(&mat_q);
+
+    let mut augmented : KalmanFilter<f64> = KalmanFilterBuilder
+        ::with_numstates_and_numinputs(4, 1)
+        .with_system_matrix(nt::DiscreteSystemMatrix(mat_f_aug))
+        .with_input_matrix(nt::DiscreteInputMatrix(mat_h_aug))
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(mat_q_aug))
+        .with_initial_state(nt::StateVector(DVector::zeros(4)),
+                            nt::CovarianceMatrix(DMatrix::from_diagonal_element(4, 4, 100.)))
+        .into();
+
+    for i in 0..steps {
+        let t = i as f64 * dt;
+        let u = if t <= 1. { 0. } else { 1. };
+        let u = nt::InputVector(DVector::from_row_slice(1, &[u,]));
+        let y = rw.step(&u);
+
+        if i == clone_at {
+            sckf.clone_state();
+            // augmented = [x; x] with covariance [P P; P P]
+            let (vec_x, mat_p) = {
+                let state = augmented.get_state();
+                (state.vec_state.0.rows(0, 2).clone_owned(),
+                 state.mat_covariances.0.slice((0, 0), (2, 2)).clone_owned())
+            };
+            let mut vec_x_aug = DVector::zeros(4);
+            vec_x_aug.rows_mut(0, 2).copy_from(&vec_x);
+            vec_x_aug.rows_mut(2, 2).copy_from(&vec_x);
+            let mut mat_p_aug = DMatrix::zeros(4, 4);
+            for &(row, col) in [(0, 0), (0, 2), (2, 0), (2, 2)].iter() {
+                mat_p_aug.slice_mut((row, col), (2, 2)).copy_from(&mat_p);
+            }
+            augmented.set_state(nt::StateVector(vec_x_aug), nt::CovarianceMatrix(mat_p_aug));
+        }
+
+        sckf.predict(&u);
+        augmented.predict(&u);
+
+        let rvec_c = rw.get_measurement_matrix().0.row(0).clone_owned();
+        let mut rvec_c_aug = RowDVector::zeros(4);
+        rvec_c_aug.columns_mut(0, 2).copy_from(&rvec_c);
+
+        sckf.measure(nt::Measurement(y.0[(0, 0)]),
+            nt::MeasurementMatrixRow(rvec_c),
+            nt::MeasurementNoiseVariance(0.1));
+        augmented.measure(nt::Measurement(y.0[(0, 0)]),
+            nt::MeasurementMatrixRow(rvec_c_aug),
+            nt::MeasurementNoiseVariance(0.1));
+
+        if i == relative_at {
+            // odometry-like measurement of the difference of the first state
+            let y_rel = rw.get_state().0[0] - augmented.get_state().vec_state.0[2];
+            let rvec_c_current = RowDVector::from_row_slice(2, &[1., 0.]);
+            let rvec_c_clone = RowDVector::from_row_slice(2, &[-1., 0.]);
+            let rvec_c_aug = RowDVector::from_row_slice(4, &[1., 0., -1., 0.]);
+
+            sckf.measure_relative(nt::Measurement(y_rel),
+                nt::MeasurementMatrixRow(rvec_c_current),
+                nt::MeasurementMatrixRow(rvec_c_clone),
+                nt::MeasurementNoiseVariance(0.01));
+            augmented.measure(nt::Measurement(y_rel),
+                nt::MeasurementMatrixRow(rvec_c_aug),
+                nt::MeasurementNoiseVariance(0.01));
+
+            let clone = sckf.get_clone().unwrap();
+            let diff = &clone.vec_state.0 - &augmented.get_state().vec_state.0.rows(2, 2);
+            assert!(helpers::max(&diff.abs()) < 1e-9);
+            let diff = &clone.mat_covariances.0
+                     - &augmented.get_state().mat_covariances.0.slice((2, 2), (2, 2));
+            assert!(helpers::max(&diff.abs()) < 1e-9);
+
+            sckf.drop_clone();
+            assert!(!sckf.has_clone());
+        }
+
+        let diff = &sckf.get_state().vec_state.0 - &augmented.get_state().vec_state.0.rows(0, 2);
+        assert!(helpers::max(&diff.abs()) < 1e-9);
+        let diff = &sckf.get_state().mat_covariances.0
+                 - &augmented.get_state().mat_covariances.0.slice((0, 0), (2, 2));
+        assert!(helpers::max(&diff.abs()) < 1e-9);
+    }
+}
+
+#[test]
+fn sckf_gating_applies_with_clone() {
+    let mut sckf : StochasticCloningKalmanFilter<f64> = KalmanFilterBuilder
+        ::with_numstates_and_numinputs(2, 1)
+        .with_initial_state(nt::StateVector(DVector::zeros(2)),
+                            nt::CovarianceMatrix(DMatrix::from_diagonal_element(2, 2, 1.)))
+        .with_innovation_gate(InnovationGate::Reject(vec![3.84]))
+        .into();
+    sckf.clone_state();
+    let rvec_c = RowDVector::from_row_slice(2, &[1., 0.]);
+
+    let update = sckf.measure(nt::Measurement(1000.),
+        nt::MeasurementMatrixRow(rvec_c.clone()), nt::MeasurementNoiseVariance(1.));
+    assert_eq!(GatingDecision::Rejected, update.gating);
+    assert_eq!(0., update.vec_state.0[0]);
+
+    let update = sckf.measure_relative(nt::Measurement(1000.),
+        nt::MeasurementMatrixRow(rvec_c.clone()),
+        nt::MeasurementMatrixRow(-rvec_c.clone()),
+        nt::MeasurementNoiseVariance(1.));
+    assert_eq!(GatingDecision::Rejected, update.gating);
+    assert_eq!(0., update.vec_state.0[0]);
+    assert_eq!(0., sckf.get_clone().unwrap().vec_state.0[0]);
+
+    let update = sckf.measure(nt::Measurement(1.),
+        nt::MeasurementMatrixRow(rvec_c), nt::MeasurementNoiseVariance(1.));
+    assert_eq!(GatingDecision::Accepted, update.gating);
+    assert!(update.vec_state.0[0] > 0.);
+    assert!(sckf.get_clone().unwrap().vec_state.0[0] > 0.);
+}
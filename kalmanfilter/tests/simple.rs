@@ -9,10 +9,12 @@ mod helpers;
 
 use helpers::types::*;
 use helpers::model::*;
-use kalmanfilter::kf::{KalmanFilterBuilder, KalmanFilter};
+use kalmanfilter::kf::{KalmanFilterBuilder, KalmanFilter, InnovationGate, GatingDecision,
+                          CovarianceUpdate};
+use kalmanfilter::rts::RauchTungStriebelSmoother;
 use kalmanfilter::nt;
 
-use na::{DMatrix, DVector};
+use na::{DMatrix, DVector, RowDVector};
 
 
 
@@ -57,4 +59,371 @@ fn simple_linear_model() {
 
     }
 
-}
\ No newline at end of file
+}
+
+/// Runs the `simple_linear_model` setup through the RTS smoother and checks that the smoothed
+/// estimates are closer to the true states than the filtered ones.
+#[test]
+fn simple_linear_model_rts_smoother() {
+    let dt : TimeStep = 0.01;
+    // Unlike `simple_linear_model`, the simulation is noisy (matching Q and R of the filter, seeded),
+    // otherwise the filtered estimates are already exact
+    let mut model = example_model_2states_regular_stable().into_discrete(dt, 1e-5);
+    model.vec_x_init = nt::StateVector(DVector::from_row_slice(2, &[1., -1.]));
+    model.vec_w = SystemNoiseVariances(DVector::from_row_slice(2, &[0.1, 0.1]));
+    model.vec_r = MeasurementNoiseVariances(DVector::from_row_slice(1, &[0.1f64.sqrt()]));
+    let mut rw = DiscreteLinearModel::from(model).with_seed(1);
+    let sim_time : usize = 2;
+    let steps = (sim_time as f64 / dt) as usize;
+
+    let mut rts : RauchTungStriebelSmoother<f64> = KalmanFilterBuilder
+        ::with_numstates_and_numinputs(rw.get_num_states(), rw.get_num_inputs())
+        .with_system_matrix(rw.get_system_matrix().clone())
+        .with_input_matrix(rw.get_input_matrix().clone())
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(
+            DMatrix::from_row_slice(2, 2, &[0.01, 0., 0., 0.01])))
+        .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[0., 0.])),
+                            nt::CovarianceMatrix(
+                                DMatrix::from_row_slice(2, 2, &[100., 0., 0., 100.])))
+        .into();
+
+    let mut true_states = vec![rw.get_state().0.clone()];
+    let mut filtered = vec![rts.get_state().vec_state.0.clone()];
+    let mut filtered_covariances = vec![rts.get_state().mat_covariances.0.clone()];
+    for i in 0..steps {
+        let t = i as f64 * dt;
+        let u = if t <= 1. { 0. } else { 1. };
+        let u = nt::InputVector(DVector::from_row_slice(1, &[u,]));
+        let y = rw.step(&u);
+        rts.predict(&u);
+        let update = rts.measure(nt::Measurement(y.0[(0, 0)]),
+            nt::MeasurementMatrixRow( rw.get_measurement_matrix().0.row(0).clone_owned() ),
+            nt::MeasurementNoiseVariance( 0.1 ));
+        filtered.push(update.vec_state.0.clone());
+        filtered_covariances.push(update.mat_covariances.0.clone());
+        true_states.push(rw.get_state().0.clone());
+    }
+
+    let smoothed = rts.smooth();
+    assert_eq!(steps + 1, smoothed.len());
+    assert_eq!(steps + 1, rts.get_num_steps());
+
+    // The last smoothed state is the filtered one
+    assert_eq!(&filtered[steps], &smoothed[steps].vec_state.0);
+
+    let mut error_filtered = 0.;
+    let mut error_smoothed = 0.;
+    for k in 0..(steps + 1) {
+        error_filtered += (&filtered[k] - &true_states[k]).norm_squared();
+        error_smoothed += (&smoothed[k].vec_state.0 - &true_states[k]).norm_squared();
+    }
+    assert!(error_smoothed < error_filtered);
+
+    // Smoothing never increases the variances
+    for k in 0..(steps + 1) {
+        for i in 0..2 {
+            assert!(smoothed[k].mat_covariances.0[(i, i)] <= filtered_covariances[k][(i, i)] + 1e-12);
+        }
+    }
+}
+
+/// Runs `simple_linear_model` over a long horizon with a tiny measurement noise and checks that
+/// the covariance matrix stays symmetric and positive semidefinite.
+#[test]
+fn simple_linear_model_long_horizon() {
+    for &covariance_update in [CovarianceUpdate::Joseph, CovarianceUpdate::Symmetrized].iter() {
+        let dt : TimeStep = 0.01;
+        let mut rw : DiscreteLinearModel = example_model_2states_regular_stable().into_discrete(dt, 1e-5).into();
+        let steps = 20000;
+
+        let mut kf : KalmanFilter<f64> = KalmanFilterBuilder
+            ::with_numstates_and_numinputs(rw.get_num_states(), rw.get_num_inputs())
+            .with_system_matrix(rw.get_system_matrix().clone())
+            .with_input_matrix(rw.get_input_matrix().clone())
+            .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(
+                DMatrix::from_row_slice(2, 2, &[1e-6, 0., 0., 1e-6])))
+            .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[0., 0.])),
+                                nt::CovarianceMatrix(
+                                    DMatrix::from_row_slice(2, 2, &[100., 0., 0., 100.])))
+            .with_covariance_update(covariance_update)
+            .into();
+
+        for i in 0..steps {
+            let t = i as f64 * dt;
+            let u = if (t as usize) % 2 == 0 { 0. } else { 1. };
+            let u = nt::InputVector(DVector::from_row_slice(1, &[u,]));
+            let y = rw.step(&u);
+            kf.predict(&u);
+            let pred = kf.measure(nt::Measurement(y.0[(0, 0)]),
+                nt::MeasurementMatrixRow( rw.get_measurement_matrix().0.row(0).clone_owned() ),
+                nt::MeasurementNoiseVariance( 1e-10 ));
+
+            let mat_p = &pred.mat_covariances.0;
+            let asymmetry = mat_p - mat_p.transpose();
+            assert!(helpers::max(&asymmetry.abs()) < 1e-12);
+            let min_eigenvalue = mat_p.symmetric_eigenvalues().iter()
+                                      .fold(f64::INFINITY, |store, item| store.min(*item));
+            assert!(min_eigenvalue > -1e-12);
+        }
+    }
+}
+
+/// With uncorrelated measurement noise, one vector update equals sequential scalar updates
+#[test]
+fn vector_measurement_equals_sequential_scalar_measurements() {
+    let mat_c = DMatrix::from_row_slice(2, 2, &[0., 2., 1., 0.]);
+    let vec_r = [0.1, 0.3];
+
+    let mk_filter = || -> KalmanFilter<f64> {
+        KalmanFilterBuilder
+            ::with_numstates_and_numinputs(2, 1)
+            .with_system_matrix(nt::DiscreteSystemMatrix(
+                DMatrix::from_row_slice(2, 2, &[0.97, 0.01, 0.005, 0.98])))
+            .with_input_matrix(nt::DiscreteInputMatrix(
+                DMatrix::from_row_slice(2, 1, &[0.01, 0.])))
+            .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(
+                DMatrix::from_row_slice(2, 2, &[0.01, 0., 0., 0.01])))
+            .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[0.5, -0.5])),
+                                nt::CovarianceMatrix(
+                                    DMatrix::from_row_slice(2, 2, &[100., 0., 0., 100.])))
+            .into()
+    };
+    let mut kf_scalar = mk_filter();
+    let mut kf_vector = mk_filter();
+
+    for i in 0..50 {
+        let u = nt::InputVector(DVector::from_row_slice(1, &[1.,]));
+        let vec_y = DVector::from_row_slice(2, &[(i as f64 * 0.1).sin(), (i as f64 * 0.1).cos()]);
+
+        kf_scalar.predict(&u);
+        kf_vector.predict(&u);
+
+        for row in 0..2 {
+            kf_scalar.measure(nt::Measurement(vec_y[row]),
+                nt::MeasurementMatrixRow(mat_c.row(row).clone_owned()),
+                nt::MeasurementNoiseVariance(vec_r[row]));
+        }
+        kf_vector.measure_vector(nt::MeasurementVector(vec_y),
+            nt::MeasurementMatrix(mat_c.clone()),
+            nt::MeasurementNoiseCovarianceMatrix(
+                DMatrix::from_diagonal(&DVector::from_row_slice(2, &vec_r))));
+
+        let diff = &kf_scalar.get_state().vec_state.0 - &kf_vector.get_state().vec_state.0;
+        assert!(helpers::max(&diff.abs()) < 1e-9);
+        let diff = &kf_scalar.get_state().mat_covariances.0 - &kf_vector.get_state().mat_covariances.0;
+        assert!(helpers::max(&diff.abs()) < 1e-9);
+    }
+}
+
+#[test]
+fn measurement_update_reports_innovation() {
+    let mut kf : KalmanFilter<f64> = KalmanFilterBuilder
+        ::with_numstates_and_numinputs(2, 1)
+        .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[1., 2.])),
+                            nt::CovarianceMatrix(
+                                DMatrix::from_row_slice(2, 2, &[4., 1., 1., 2.])))
+        .into();
+
+    let update = kf.measure(nt::Measurement(5.),
+        nt::MeasurementMatrixRow(RowDVector::from_row_slice(2, &[1., 1.])),
+        nt::MeasurementNoiseVariance(1.));
+
+    // residual = 5 - (1 + 2), S = 4 + 1 + 1 + 2 + 1, K = [5, 3] / 9
+    assert!((update.vec_innovation.0[0] - 2.).abs() < 1e-12);
+    assert!((update.mat_s.0[(0, 0)] - 9.).abs() < 1e-12);
+    assert!((update.mat_k.0[(0, 0)] - 5. / 9.).abs() < 1e-12);
+    assert!((update.mat_k.0[(1, 0)] - 3. / 9.).abs() < 1e-12);
+    assert!((update.nis - 4. / 9.).abs() < 1e-12);
+    assert!((update.vec_state.0[0] - (1. + 10. / 9.)).abs() < 1e-12);
+}
+
+#[test]
+fn innovation_gate_rejects_outliers() {
+    let mk_filter = |gate| -> KalmanFilter<f64> {
+        KalmanFilterBuilder
+            ::with_numstates_and_numinputs(2, 1)
+            .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[1., 2.])),
+                                nt::CovarianceMatrix(
+                                    DMatrix::from_row_slice(2, 2, &[1., 0., 0., 1.])))
+            .with_innovation_gate(gate)
+            .into()
+    };
+    let rvec_c = RowDVector::from_row_slice(2, &[1., 0.]);
+
+    let mut kf = mk_filter(InnovationGate::Reject(vec![3.84, 5.99]));
+    // S = 2, NIS = 4 / 2 = 2
+    let update = kf.measure(nt::Measurement(3.),
+        nt::MeasurementMatrixRow(rvec_c.clone()), nt::MeasurementNoiseVariance(1.));
+    assert_eq!(GatingDecision::Accepted, update.gating);
+    assert!(update.vec_state.0[0] != 1.);
+
+    let mut kf = mk_filter(InnovationGate::Reject(vec![3.84, 5.99]));
+    // NIS = 100 / 2 = 50
+    let update = kf.measure(nt::Measurement(11.),
+        nt::MeasurementMatrixRow(rvec_c.clone()), nt::MeasurementNoiseVariance(1.));
+    assert_eq!(GatingDecision::Rejected, update.gating);
+    assert_eq!(1., update.vec_state.0[0]);
+    assert_eq!(1., update.mat_covariances.0[(0, 0)]);
+
+    let update = kf.measure_vector(nt::MeasurementVector(DVector::from_row_slice(2, &[11., 2.])),
+        nt::MeasurementMatrix(DMatrix::identity(2, 2)),
+        nt::MeasurementNoiseCovarianceMatrix(DMatrix::identity(2, 2)));
+    assert_eq!(GatingDecision::Rejected, update.gating);
+    assert_eq!(1., update.vec_state.0[0]);
+
+    let mut kf = mk_filter(InnovationGate::Report(vec![3.84]));
+    let update = kf.measure(nt::Measurement(11.),
+        nt::MeasurementMatrixRow(rvec_c), nt::MeasurementNoiseVariance(1.));
+    assert_eq!(GatingDecision::Outlier, update.gating);
+    assert!((update.vec_state.0[0] - 6.).abs() < 1e-12);
+}
+
+#[test]
+fn innovation_gate_with_probability_for_any_dimension() {
+    let mk_filter = |gate| -> KalmanFilter<f64> {
+        KalmanFilterBuilder
+            ::with_numstates_and_numinputs(3, 1)
+            .with_initial_state(nt::StateVector(DVector::zeros(3)),
+                                nt::CovarianceMatrix(DMatrix::identity(3, 3)))
+            .with_innovation_gate(gate)
+            .into()
+    };
+    // S = 2 I, NIS = |y|^2 / 2, the 95% quantile for 3 degrees of freedom is 7.8147
+    let measure = |kf : &mut KalmanFilter<f64>, nis : f64| {
+        kf.measure_vector(nt::MeasurementVector(DVector::from_row_slice(3, &[(2. * nis).sqrt(), 0., 0.])),
+            nt::MeasurementMatrix(DMatrix::identity(3, 3)),
+            nt::MeasurementNoiseCovarianceMatrix(DMatrix::identity(3, 3))).gating
+    };
+    assert_eq!(GatingDecision::Accepted, measure(&mut mk_filter(InnovationGate::RejectProbability(0.95)), 7.81));
+    assert_eq!(GatingDecision::Rejected, measure(&mut mk_filter(InnovationGate::RejectProbability(0.95)), 7.82));
+    assert_eq!(GatingDecision::Outlier, measure(&mut mk_filter(InnovationGate::ReportProbability(0.95)), 7.82));
+
+    // The 99% quantile for 1 degree of freedom is 6.6349
+    let mut kf = mk_filter(InnovationGate::RejectProbability(0.99));
+    let rvec_c = RowDVector::from_row_slice(3, &[1., 0., 0.]);
+    let update = kf.measure(nt::Measurement((2. * 6.63f64).sqrt()),
+        nt::MeasurementMatrixRow(rvec_c.clone()), nt::MeasurementNoiseVariance(1.));
+    assert_eq!(GatingDecision::Accepted, update.gating);
+    let mut kf = mk_filter(InnovationGate::RejectProbability(0.99));
+    let update = kf.measure(nt::Measurement((2. * 6.64f64).sqrt()),
+        nt::MeasurementMatrixRow(rvec_c), nt::MeasurementNoiseVariance(1.));
+    assert_eq!(GatingDecision::Rejected, update.gating);
+}
+
+#[test]
+#[should_panic(expected = "The innovation gate has thresholds for up to 1 measurements at once, but 2 were given")]
+fn innovation_gate_with_too_few_thresholds() {
+    let mut kf : KalmanFilter<f64> = KalmanFilterBuilder
+        ::with_numstates_and_numinputs(2, 1)
+        .with_innovation_gate(InnovationGate::Reject(vec![3.84]))
+        .with_initial_state(nt::StateVector(DVector::zeros(2)),
+                            nt::CovarianceMatrix(DMatrix::identity(2, 2)))
+        .into();
+    kf.measure_vector(nt::MeasurementVector(DVector::zeros(2)),
+        nt::MeasurementMatrix(DMatrix::identity(2, 2)),
+        nt::MeasurementNoiseCovarianceMatrix(DMatrix::identity(2, 2)));
+}
+
+#[test]
+#[should_panic(expected = "Innovation gate without thresholds")]
+fn innovation_gate_without_thresholds_is_rejected_on_build() {
+    let _kf : KalmanFilter<f64> = KalmanFilterBuilder
+        ::with_numstates_and_numinputs(2, 1)
+        .with_innovation_gate(InnovationGate::Report(vec![]))
+        .into();
+}
+
+#[test]
+fn log_likelihood_accumulates_over_measurements() {
+    let mk_filter = |accumulate| -> KalmanFilter<f64> {
+        let builder = KalmanFilterBuilder
+            ::with_numstates_and_numinputs(2, 1)
+            .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[1., 2.])),
+                                nt::CovarianceMatrix(
+                                    DMatrix::from_row_slice(2, 2, &[4., 1., 1., 2.])));
+        if accumulate { builder.with_log_likelihood_accumulation().into() } else { builder.into() }
+    };
+
+    let mut kf = mk_filter(false);
+    kf.measure(nt::Measurement(5.),
+        nt::MeasurementMatrixRow(RowDVector::from_row_slice(2, &[1., 1.])),
+        nt::MeasurementNoiseVariance(1.));
+    assert_eq!(None, kf.get_log_likelihood());
+
+    let mut kf = mk_filter(true);
+    assert_eq!(Some(0.), kf.get_log_likelihood());
+
+    // residual = 2, S = 9
+    let log_likelihood_1 = kf.measure(nt::Measurement(5.),
+        nt::MeasurementMatrixRow(RowDVector::from_row_slice(2, &[1., 1.])),
+        nt::MeasurementNoiseVariance(1.)).log_likelihood();
+    let expected = -0.5 * (4. / 9. + (2. * ::std::f64::consts::PI).ln() + 9f64.ln());
+    assert!((log_likelihood_1 - expected).abs() < 1e-12);
+    assert!((kf.get_log_likelihood().unwrap() - expected).abs() < 1e-12);
+
+    let log_likelihood_2 = kf.measure_vector(nt::MeasurementVector(DVector::from_row_slice(2, &[2., 3.])),
+        nt::MeasurementMatrix(DMatrix::from_row_slice(2, 2, &[1., 0., 1., -1.])),
+        nt::MeasurementNoiseCovarianceMatrix(DMatrix::from_row_slice(2, 2, &[1., 0.5, 0.5, 2.]))).log_likelihood();
+    let expected = log_likelihood_1 + log_likelihood_2;
+    assert!((kf.get_log_likelihood().unwrap() - expected).abs() < 1e-12);
+
+    kf.reset_log_likelihood();
+    assert_eq!(Some(0.), kf.get_log_likelihood());
+}
+
+#[test]
+fn consider_states_are_not_updated() {
+    let mat_p = DMatrix::from_row_slice(2, 2, &[4., 1., 1., 2.]);
+    // x = [ position, bias ], the bias is a consider state
+    let mut kf : KalmanFilter<f64> = KalmanFilterBuilder
+        ::with_numstates_and_numinputs(2, 1)
+        .with_system_matrix(nt::DiscreteSystemMatrix(DMatrix::from_row_slice(2, 2, &[1., 0.5, 0., 1.])))
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(DMatrix::from_row_slice(2, 2, &[0.1, 0., 0., 0.])))
+        .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[1., 2.])),
+                            nt::CovarianceMatrix(mat_p.clone()))
+        .with_consider_states(vec![1])
+        .into();
+
+    let rvec_c = RowDVector::from_row_slice(2, &[1., 1.]);
+    let update = kf.measure(nt::Measurement(5.),
+        nt::MeasurementMatrixRow(rvec_c.clone()), nt::MeasurementNoiseVariance(1.));
+
+    // S = 9 as without consider states, K = [5 / 9, 0]
+    assert!((update.mat_s.0[(0, 0)] - 9.).abs() < 1e-12);
+    assert!((update.mat_k.0[(0, 0)] - 5. / 9.).abs() < 1e-12);
+    assert_eq!(0., update.mat_k.0[(1, 0)]);
+    assert!((update.vec_state.0[0] - (1. + 10. / 9.)).abs() < 1e-12);
+    assert_eq!(2., update.vec_state.0[1]);
+
+    // P = (I - K C) P (I - K C)^T + K R K^T
+    let mat_i_kc = DMatrix::identity(2, 2) - &update.mat_k.0 * DMatrix::from_row_slice(1, 2, &[1., 1.]);
+    let mat_p_expected = &mat_i_kc * &mat_p * mat_i_kc.transpose() + &update.mat_k.0 * update.mat_k.0.transpose();
+    assert!(helpers::max(&(&mat_p_expected - &update.mat_covariances.0).abs()) < 1e-12);
+    assert_eq!(2., update.mat_covariances.0[(1, 1)]);
+
+    // The gain of the estimated state is that of the optimal filter, but the optimal filter
+    // also reduces the bias variance
+    let mut kf_optimal : KalmanFilter<f64> = KalmanFilterBuilder
+        ::with_numstates_and_numinputs(2, 1)
+        .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[1., 2.])),
+                            nt::CovarianceMatrix(mat_p.clone()))
+        .into();
+    let update_optimal = kf_optimal.measure(nt::Measurement(5.),
+        nt::MeasurementMatrixRow(rvec_c.clone()), nt::MeasurementNoiseVariance(1.));
+    assert!((update_optimal.mat_covariances.0[(0, 0)] - mat_p_expected[(0, 0)]).abs() < 1e-12);
+    assert!(update_optimal.mat_covariances.0[(1, 1)] < 2.);
+
+    // Predicted cross covariances follow F P F^T + Q
+    let mat_f = DMatrix::from_row_slice(2, 2, &[1., 0.5, 0., 1.]);
+    let mat_p_predicted = &mat_f * &mat_p_expected * mat_f.transpose()
+                        + DMatrix::from_row_slice(2, 2, &[0.1, 0., 0., 0.]);
+    let state = kf.predict(&nt::InputVector(DVector::zeros(1)));
+    assert!(helpers::max(&(&mat_p_predicted - &state.mat_covariances.0).abs()) < 1e-12);
+    assert_eq!(2., state.vec_state.0[1]);
+
+    let update = kf.measure_vector(nt::MeasurementVector(DVector::from_row_slice(1, &[4.])),
+        nt::MeasurementMatrix(DMatrix::from_row_slice(1, 2, &[1., 1.])),
+        nt::MeasurementNoiseCovarianceMatrix(DMatrix::identity(1, 1)));
+    assert_eq!(2., update.vec_state.0[1]);
+    assert!((update.mat_covariances.0[(1, 1)] - 2.).abs() < 1e-12);
+}
@@ -0,0 +1,81 @@
+#![allow(dead_code)]
+
+extern crate kalmanfilter;
+extern crate nalgebra as na;
+extern crate num;
+extern crate rand;
+
+mod helpers;
+
+use helpers::types::*;
+use helpers::model::*;
+use kalmanfilter::kf::{KalmanFilterBuilder, KalmanFilter};
+use kalmanfilter::udkf::UDKalmanFilter;
+use kalmanfilter::nt;
+
+use na::{DMatrix, DVector};
+
+
+fn mk_builder(rw : &DiscreteLinearModel) -> KalmanFilterBuilder<f64> {
+    KalmanFilterBuilder
+        ::with_numstates_and_numinputs(rw.get_num_states(), rw.get_num_inputs())
+        .with_system_matrix(rw.get_system_matrix().clone())
+        .with_input_matrix(rw.get_input_matrix().clone())
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(
+            DMatrix::from_row_slice(2, 2, &[0.01, 0.002, 0.002, 0.01])))
+        .with_initial_state(nt::StateVector(DVector::from_row_slice(2, &[0., 0.])),
+                            nt::CovarianceMatrix(
+                                DMatrix::from_row_slice(2, 2, &[100., 0., 0., 100.])))
+}
+
+#[test]
+fn udkf_equals_kf() {
+    let dt : TimeStep = 0.01;
+    let mut rw : DiscreteLinearModel = example_model_2states_regular_stable().into_discrete(dt, 1e-5).into();
+    let steps = 500;
+
+    let mut kf : KalmanFilter<f64> = mk_builder(&rw).into();
+    let mut udkf : UDKalmanFilter<f64> = mk_builder(&rw).into();
+
+    for i in 0..steps {
+        let t = i as f64 * dt;
+        let u = if t <= 1. { 0. } else { 1. };
+        let u = nt::InputVector(DVector::from_row_slice(1, &[u,]));
+        let y = rw.step(&u);
+
+        kf.predict(&u);
+        udkf.predict(&u);
+
+        let rvec_c = rw.get_measurement_matrix().0.row(0).clone_owned();
+        kf.measure(nt::Measurement(y.0[(0, 0)]),
+            nt::MeasurementMatrixRow(rvec_c.clone()),
+            nt::MeasurementNoiseVariance(0.1));
+        udkf.measure(nt::Measurement(y.0[(0, 0)]),
+            nt::MeasurementMatrixRow(rvec_c),
+            nt::MeasurementNoiseVariance(0.1));
+
+        let diff = &udkf.get_state().vec_state.0 - &kf.get_state().vec_state.0;
+        assert!(helpers::max(&diff.abs()) < 1e-9);
+        let diff = &udkf.get_covariances().0 - &kf.get_state().mat_covariances.0;
+        assert!(helpers::max(&diff.abs()) < 1e-9);
+    }
+}
+
+#[test]
+fn udkf_covariance_conversion() {
+    let mut udkf : UDKalmanFilter<f64> = KalmanFilterBuilder::with_numstates_and_numinputs(3, 1).into();
+    let mat_p = DMatrix::from_row_slice(3, 3, &[4., 2., 0.6,
+                                                2., 2., 0.5,
+                                                0.6, 0.5, 3.]);
+    udkf.set_state(nt::StateVector(DVector::zeros(3)), nt::CovarianceMatrix(mat_p.clone()));
+
+    let state = udkf.get_state();
+    for i in 0..3 {
+        assert_eq!(1., state.mat_u.0[(i, i)]);
+        for j in 0..i {
+            assert_eq!(0., state.mat_u.0[(i, j)]);
+        }
+    }
+    let diff = &udkf.get_covariances().0 - &mat_p;
+    assert!(helpers::max(&diff.abs()) < 1e-12);
+}
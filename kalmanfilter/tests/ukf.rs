@@ -0,0 +1,107 @@
+#![allow(dead_code)]
+
+extern crate kalmanfilter;
+extern crate nalgebra as na;
+extern crate num;
+extern crate rand;
+
+mod helpers;
+
+use helpers::types::*;
+use helpers::model::*;
+use kalmanfilter::kf::{KalmanFilterBuilder, KalmanFilter};
+use kalmanfilter::ukf::{UnscentedKalmanFilterBuilder, UnscentedKalmanFilter, SigmaPoints};
+use kalmanfilter::nt;
+
+use na::{DMatrix, DVector};
+
+
+/// The unscented transformation is exact for linear f() and c(), so every sigma point set has
+/// to reproduce the linear KF.
+#[test]
+fn ukf_equals_kf_for_linear_model() {
+    let sigma_point_sets = vec![
+        SigmaPoints::Julier { kappa : 1. },
+        SigmaPoints::MerweScaled { alpha : 0.5, beta : 2., kappa : 0. },
+        SigmaPoints::Simplex { w0 : 0.3 },
+    ];
+
+    for sigma_points in sigma_point_sets {
+        let dt : TimeStep = 0.01;
+        let mut rw : DiscreteLinearModel = example_model_2states_regular_stable().into_discrete(dt, 1e-5).into();
+        let steps = 200;
+
+        let mat_q = nt::SystemNoiseVarianceMatrix(DMatrix::from_row_slice(2, 2, &[0.01, 0., 0., 0.01]));
+        let vec_x_init = nt::StateVector(DVector::from_row_slice(2, &[0., 0.]));
+        let mat_p_init = nt::CovarianceMatrix(DMatrix::from_row_slice(2, 2, &[100., 0., 0., 100.]));
+
+        let mut kf : KalmanFilter<f64> = KalmanFilterBuilder
+            ::with_numstates_and_numinputs(rw.get_num_states(), rw.get_num_inputs())
+            .with_system_matrix(rw.get_system_matrix().clone())
+            .with_input_matrix(rw.get_input_matrix().clone())
+            .with_system_noise_variances(mat_q.clone())
+            .with_initial_state(vec_x_init.clone(), mat_p_init.clone())
+            .into();
+
+        let mat_f = rw.get_system_matrix().clone();
+        let mat_h = rw.get_input_matrix().clone();
+        let mat_c = rw.get_measurement_matrix().0.clone();
+
+        let mut ukf : UnscentedKalmanFilter<f64> = UnscentedKalmanFilterBuilder
+            ::with_numstates_and_numinputs(rw.get_num_states(), rw.get_num_inputs())
+            .with_state_function(move |x, u| nt::StateVector(&mat_f.0 * &x.0 + &mat_h.0 * &u.0))
+            .with_output_function(move |x| nt::MeasurementVector(&mat_c * &x.0))
+            .with_sigma_points(sigma_points.clone())
+            .with_system_noise_variances(mat_q)
+            .with_initial_state(vec_x_init, mat_p_init)
+            .into();
+
+        for i in 0..steps {
+            let t = i as f64 * dt;
+            let u = if t <= 1. { 0. } else { 1. };
+            let u = nt::InputVector(DVector::from_row_slice(1, &[u,]));
+            let y = rw.step(&u);
+
+            kf.predict(&u);
+            ukf.predict(&u);
+
+            let kf_state = kf.measure(nt::Measurement(y.0[(0, 0)]),
+                nt::MeasurementMatrixRow( rw.get_measurement_matrix().0.row(0).clone_owned() ),
+                nt::MeasurementNoiseVariance( 0.1 ));
+            let ukf_state = if i % 2 == 0 {
+                ukf.measure(nt::Measurement(y.0[(0, 0)]), 0, nt::MeasurementNoiseVariance( 0.1 ))
+            } else {
+                ukf.measure_vector(nt::MeasurementVector(y.0.clone()),
+                    nt::MeasurementNoiseCovarianceMatrix(DMatrix::from_element(1, 1, 0.1)))
+            };
+
+            let diff = &ukf_state.vec_state.0 - &kf_state.vec_state.0;
+            assert!(helpers::max(&diff.abs()) < 1e-8, "{:?}", sigma_points);
+            let diff = &ukf_state.mat_covariances.0 - &kf_state.mat_covariances.0;
+            assert!(helpers::max(&diff.abs()) < 1e-8, "{:?}", sigma_points);
+        }
+    }
+}
+
+/// For n > 3, Julier's `kappa = 3 - n` gives a negative center weight and can make the
+/// covariance indefinite with a nonlinear output, so the default must avoid it.
+#[test]
+fn ukf_default_sigma_points_for_many_states() {
+    let num_states = 6;
+    let mut ukf : UnscentedKalmanFilter<f64> = UnscentedKalmanFilterBuilder
+        ::with_numstates_and_numinputs(num_states, 1)
+        .with_output_function(|x| nt::MeasurementVector(
+            DVector::from_element(1, x.0.norm_squared())))
+        .with_system_noise_variances(nt::SystemNoiseVarianceMatrix(
+            DMatrix::from_diagonal_element(num_states, num_states, 0.01)))
+        .with_initial_state(nt::StateVector(DVector::from_element(num_states, 1.)),
+                            nt::CovarianceMatrix(DMatrix::identity(num_states, num_states)))
+        .into();
+
+    let u = nt::InputVector(DVector::from_row_slice(1, &[0.,]));
+    for _ in 0..20 {
+        ukf.predict(&u);
+        let state = ukf.measure(nt::Measurement(6.), 0, nt::MeasurementNoiseVariance(0.1));
+        assert!(state.mat_covariances.0.clone().cholesky().is_some());
+    }
+}